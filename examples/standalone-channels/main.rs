@@ -4,10 +4,17 @@ use std::{
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
-use tendermint::{crypto::ECDSAKeypair, params::*, process::*};
+use tendermint::{
+    crypto::{KeyType, Keypair},
+    params::*,
+    privval::{MockSigner, PrivValidator},
+    process::*,
+};
 use tokio::sync::{mpsc, Mutex};
 use tokio_stream::StreamExt;
 
+const CHAIN_ID: &str = "standalone-channels";
+
 async fn setup_pure_sendreceive() {
     // Create channels for each node
     let mut senders = Vec::new();
@@ -22,23 +29,40 @@ async fn setup_pure_sendreceive() {
         receivers.push_back(rx);
     }
 
+    // Generate the validator set up front, so every node can check who votes. Validators may
+    // use different signature schemes, so cycle through them. With the `bls` feature validators
+    // have BLS keys instead, and decisions come with an aggregated commit certificate.
+    #[cfg(not(feature = "bls"))]
+    let key_types = [KeyType::Secp256k1, KeyType::Ed25519];
+    #[cfg(feature = "bls")]
+    let key_types = [KeyType::Bls12381];
+    let keypairs: Vec<Keypair> =
+        (0..NODES).map(|i| Keypair::generate(key_types[i % key_types.len()])).collect();
+    let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+
+    // Node 0 keeps its key out of process, in a signer it reaches over a socket.
+    let signer = MockSigner::start(keypairs[0].clone(), CHAIN_ID).await;
+
     // Define proposer sequence (round-robin)
     let proposer_sequence: Vec<usize> = (0..NODES).collect();
 
     // Initialize nodes
     let mut nodes = Vec::new();
-    for i in 0..NODES {
+    for (i, keypair) in keypairs.iter().enumerate() {
         let mut node_senders = Vec::new();
-        for j in 0..NODES {
+        for (j, sender) in senders.iter().enumerate() {
             if i != j {
-                node_senders.push(senders[j].clone());
+                node_senders.push(sender.clone());
             }
         }
-        let keypair = ECDSAKeypair::new();
         let receiver = receivers.pop_front().unwrap();
+        let privval: PrivValidator =
+            if i == 0 { signer.connect().await.into() } else { keypair.clone().into() };
         let node = Process::new(
             i,
-            keypair,
+            privval,
+            CHAIN_ID.to_string(),
+            validators.clone(),
             Arc::new(Mutex::new(receiver)),
             node_senders,
             proposer_sequence.clone(),
@@ -75,5 +99,6 @@ async fn setup_pure_sendreceive() {
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
     setup_pure_sendreceive().await;
 }
@@ -0,0 +1,95 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tendermint::{
+    crypto::{KeyType, Keypair},
+    gossip::{channel_link, Gossip, GossipConfig},
+    params::*,
+    process::*,
+};
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::StreamExt;
+
+const CHAIN_ID: &str = "standalone-gossip";
+
+async fn setup_gossip_ring() {
+    let get_value = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
+
+    let keypairs: Vec<Keypair> =
+        (0..NODES).map(|_| Keypair::generate(KeyType::Secp256k1)).collect();
+    let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+
+    // Create the inbound channel of each node.
+    let mut inbound_senders = Vec::new();
+    let mut inbound_receivers = Vec::new();
+    for _ in 0..NODES {
+        let (tx, rx) = mpsc::channel(100);
+        inbound_senders.push(tx);
+        inbound_receivers.push(Arc::new(Mutex::new(rx)));
+    }
+
+    // Define proposer sequence (round-robin)
+    let proposer_sequence: Vec<usize> = (0..NODES).collect();
+
+    // Initialize nodes. Each node is only connected to its two neighbours on a ring, so messages
+    // reach the other nodes by being relayed.
+    let mut nodes = Vec::new();
+    let mut trackers = Vec::new();
+    for (i, inbound) in inbound_receivers.into_iter().enumerate() {
+        let gossip = Gossip::new(GossipConfig::default(), CHAIN_ID.to_string());
+        for j in [(i + 1) % NODES, (i + NODES - 1) % NODES] {
+            let link = channel_link(keypairs[i].get_public_key(), inbound_senders[j].clone());
+            gossip.add_peer(keypairs[j].get_public_key(), link);
+        }
+        let (sender, receiver) = gossip.start(inbound);
+
+        let mut node = Process::new(
+            i,
+            keypairs[i].clone(),
+            CHAIN_ID.to_string(),
+            validators.clone(),
+            receiver,
+            vec![sender],
+            proposer_sequence.clone(),
+            get_value,
+        );
+        node.set_verifier(gossip.verifier());
+        trackers.push(gossip.track_votes(node.round_state()));
+        nodes.push(node);
+    }
+
+    // Listen to events from node0.
+    let mut subscriber1 = nodes[0].subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = subscriber1.next().await {
+            println!("Subscriber 1 received: {:?}", event);
+        }
+    });
+
+    // Run all nodes
+    let handles: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                node.run_epoch(None).await;
+            })
+        })
+        .collect();
+
+    // Wait for all nodes to finish
+    for handle in handles {
+        let _ = handle.await;
+    }
+    for tracker in trackers {
+        tracker.abort();
+    }
+
+    println!("Consensus reached.");
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
+    setup_gossip_ring().await;
+}
@@ -3,11 +3,19 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 use tendermint::{
-    crypto::ECDSAKeypair, messages::SignedMessage, params::*, process::*, rpc_client::RpcClient,
+    codec::Encoding,
+    crypto::{KeyType, Keypair},
+    messages::SignedMessage,
+    metrics::Metrics,
+    params::*,
+    process::*,
+    rpc_client::RpcClient,
     rpc_server::Server,
 };
 use tokio_stream::StreamExt;
 
+const CHAIN_ID: &str = "standalone-http";
+
 async fn setup_api_servers() {
     // Create channels for each node
     let mut senders = Vec::new();
@@ -15,41 +23,58 @@ async fn setup_api_servers() {
 
     let get_value = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
 
-    // Setup node API servers.
+    // Setup node API servers. The clients count the messages they fail to post in one metric.
+    let metrics = Metrics::new();
     for i in 0..NODES {
         let server = Server::<SignedMessage>::new("127.0.0.1".parse().unwrap(), 3030 + i as u16);
         receivers.push_back(server.get_receiver());
         let client = RpcClient::<SignedMessage>::new(
             100,
             format!("http://localhost:{}/inbox/", server.port),
+            Encoding::Proto,
+            &metrics.rpc_send_failures(),
         );
         senders.push(client.get_sender());
 
         tokio::spawn(async move {
-            server.run().await;
+            if let Err(err) = server.run().await {
+                eprintln!("{}", err);
+            }
         });
         tokio::spawn(async move {
             client.start().await;
         });
     }
 
+    // Generate the validator set up front, so every node can check who votes.
+    let keypairs: Vec<Keypair> =
+        (0..NODES).map(|_| Keypair::generate(KeyType::Secp256k1)).collect();
+    let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+
     // Define proposer sequence (round-robin)
     let proposer_sequence: Vec<usize> = (0..NODES).collect();
 
     // Initialize nodes
     let mut nodes = Vec::new();
-    for i in 0..NODES {
+    for (i, keypair) in keypairs.iter().enumerate() {
         let mut node_senders = Vec::new();
-        for j in 0..NODES {
+        for (j, sender) in senders.iter().enumerate() {
             if i != j {
-                node_senders.push(senders[j].clone());
+                node_senders.push(sender.clone());
             }
         }
 
-        let keypair = ECDSAKeypair::new();
         let receiver = receivers.pop_front().unwrap();
-        let node =
-            Process::new(i, keypair, receiver, node_senders, proposer_sequence.clone(), get_value);
+        let node = Process::new(
+            i,
+            keypair.clone(),
+            CHAIN_ID.to_string(),
+            validators.clone(),
+            receiver,
+            node_senders,
+            proposer_sequence.clone(),
+            get_value,
+        );
         nodes.push(node);
     }
 
@@ -81,6 +106,7 @@ async fn setup_api_servers() {
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
     println!("Main");
     setup_api_servers().await;
 
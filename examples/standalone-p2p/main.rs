@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tendermint::{
+    addrbook::{AddressBook, AddressBookConfig},
+    codec::Encoding,
+    config::{TendermintConfig, ValidatorInfo},
+    crypto::{KeyType, Keypair},
+    gossip::{Gossip, GossipConfig},
+    messages::PeerMessage,
+    p2p::{NodeInfo, PeerClient, PeerServer},
+    params::*,
+    peers::PeerTable,
+    process::*,
+};
+use tokio_stream::StreamExt;
+
+const CHAIN_ID: &str = "standalone-p2p";
+
+async fn setup_secret_connections() {
+    let mut senders = Vec::new();
+    let mut receivers = VecDeque::new();
+    let mut gossips = Vec::new();
+
+    let get_value = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
+
+    // Generate the validator set up front, so every node knows who to dial.
+    let keypairs: Vec<Keypair> = (0..NODES).map(|_| Keypair::generate(KeyType::Ed25519)).collect();
+    let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+
+    // Nodes only connect to peers that share their genesis.
+    let genesis = TendermintConfig {
+        chain_id: CHAIN_ID.to_string(),
+        genesis_time: 0,
+        initial_height: 0,
+        consensus_params: ConsensusParams::default(),
+        validators: validators
+            .iter()
+            .enumerate()
+            .map(|(i, pubkey)| ValidatorInfo {
+                pubkey: pubkey.to_string(),
+                node_pubkey: None,
+                power: 1,
+                proof_of_possession: None,
+                address: "127.0.0.1".parse().unwrap(),
+                port: 4030 + i as u16,
+            })
+            .collect(),
+    };
+    genesis.validate().unwrap();
+
+    // Setup node peer servers, and dial every other node with the node's own identity.
+    let mut tables = Vec::new();
+    for (i, keypair) in keypairs.iter().enumerate() {
+        let node = NodeInfo {
+            keypair: keypair.clone(),
+            chain_id: CHAIN_ID.to_string(),
+            genesis_hash: genesis.hash(),
+            listen_addr: format!("127.0.0.1:{}", 4030 + i).parse().unwrap(),
+            encoding: Encoding::Proto,
+            allowed: validators.clone(),
+        };
+        let table = Arc::new(PeerTable::<PeerMessage>::new());
+        let book =
+            Arc::new(AddressBook::new(AddressBookConfig::default(), keypair.get_public_key()));
+
+        // Messages are relayed through the gossip layer between the network and the process.
+        let server = PeerServer::new(node.clone(), table.clone(), book.clone(), NODES);
+        let gossip = Gossip::new(GossipConfig::default(), CHAIN_ID.to_string());
+        let (sender, receiver) = gossip.start(server.get_receiver());
+        senders.push(sender);
+        receivers.push_back(receiver);
+        tokio::spawn(async move {
+            server.run().await.unwrap();
+        });
+
+        for (j, validator) in validators.iter().enumerate() {
+            if i != j {
+                let addr = format!("127.0.0.1:{}", 4030 + j).parse().unwrap();
+                let client = PeerClient::new(
+                    100,
+                    node.clone(),
+                    table.clone(),
+                    book.clone(),
+                    *validator,
+                    addr,
+                );
+                gossip.add_peer(*validator, client.get_sender());
+                client.start();
+            }
+        }
+        tables.push(table);
+        gossips.push(gossip);
+    }
+
+    // Define proposer sequence (round-robin)
+    let proposer_sequence: Vec<usize> = (0..NODES).collect();
+
+    // Initialize nodes
+    let mut nodes = Vec::new();
+    let mut trackers = Vec::new();
+    for (i, (keypair, sender)) in keypairs.into_iter().zip(senders).enumerate() {
+        let receiver = receivers.pop_front().unwrap();
+        let mut node = Process::new(
+            i,
+            keypair,
+            CHAIN_ID.to_string(),
+            validators.clone(),
+            receiver,
+            vec![sender],
+            proposer_sequence.clone(),
+            get_value,
+        );
+        node.set_verifier(gossips[i].verifier());
+        trackers.push(gossips[i].track_votes(node.round_state()));
+        nodes.push(node);
+    }
+
+    // Listen to events from node0.
+    let mut subscriber1 = nodes[0].subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = subscriber1.next().await {
+            println!("Subscriber 1 received: {:?}", event);
+        }
+    });
+
+    // Run all nodes
+    let handles: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                node.run_epoch(None).await;
+            })
+        })
+        .collect();
+
+    // Wait for all nodes to finish
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    for tracker in trackers {
+        tracker.abort();
+    }
+
+    println!("Consensus reached.");
+    for peer in tables[0].peers() {
+        println!("Node 0 peer {}: {:?}", peer.pubkey, peer.state);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
+    setup_secret_connections().await;
+}
@@ -0,0 +1,412 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex as StdMutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::crypto::PublicKey;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed address book: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+/// Ways a peer can misbehave. Each adds to the peer's ban score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Relayed a message with an invalid signature.
+    InvalidSignature,
+    /// Sent bytes which don't decode to a message.
+    MalformedMessage,
+    /// Sent an invalid hello during the handshake.
+    InvalidHello,
+    /// Sent peer addresses we didn't ask for, or too many of them.
+    PexAbuse,
+}
+
+impl Misbehaviour {
+    pub fn penalty(&self) -> u32 {
+        match self {
+            Misbehaviour::InvalidSignature => 10,
+            Misbehaviour::MalformedMessage => 20,
+            Misbehaviour::InvalidHello => 50,
+            Misbehaviour::PexAbuse => 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AddressBookConfig {
+    /// The ban score at which a peer is banned.
+    pub ban_threshold: u32,
+    /// How long a banned peer is refused for.
+    pub ban_duration: Duration,
+    /// The most peers the book holds. Once full, a peer we have never connected to is forgotten
+    /// to make room for a new one.
+    pub max_addresses: usize,
+}
+
+impl Default for AddressBookConfig {
+    fn default() -> Self {
+        AddressBookConfig {
+            ban_threshold: 100,
+            ban_duration: Duration::from_secs(24 * 60 * 60),
+            max_addresses: 1000,
+        }
+    }
+}
+
+/// What we know about a peer's address. Times are in seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownAddress {
+    pub pubkey: PublicKey,
+    /// The address the peer accepts connections on. Peers reported for misbehaving before we
+    /// learnt their address have none, and are never dialed.
+    pub addr: Option<SocketAddr>,
+    /// The number of failed dials since we last connected.
+    pub attempts: u32,
+    pub last_attempt: Option<u64>,
+    pub last_success: Option<u64>,
+    pub ban_score: u32,
+    pub banned_until: Option<u64>,
+}
+
+impl KnownAddress {
+    fn new(pubkey: PublicKey, addr: Option<SocketAddr>) -> Self {
+        KnownAddress {
+            pubkey,
+            addr,
+            attempts: 0,
+            last_attempt: None,
+            last_success: None,
+            ban_score: 0,
+            banned_until: None,
+        }
+    }
+
+    fn is_banned(&self, now: u64) -> bool {
+        self.banned_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Writes `data` to a temporary file beside `path`, and renames it over `path` once on disk.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// The addresses of the peers a node knows about, learnt from the genesis config, seed nodes and
+/// peer exchange. The book also tracks the ban score of each peer, and can be persisted to disk
+/// so a restarted node can find the network again.
+pub struct AddressBook {
+    config: AddressBookConfig,
+    path: Option<PathBuf>,
+    /// Our own identity, which is never added to the book.
+    own: PublicKey,
+    addrs: StdMutex<HashMap<PublicKey, KnownAddress>>,
+    dirty: AtomicBool,
+}
+
+impl AddressBook {
+    /// Creates an empty address book which is not persisted.
+    pub fn new(config: AddressBookConfig, own: PublicKey) -> Self {
+        AddressBook {
+            config,
+            path: None,
+            own,
+            addrs: StdMutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Opens the address book stored at `path`, or an empty one if the file doesn't exist yet.
+    /// The stored addresses are added as if learnt anew, so the book is held to `max_addresses`,
+    /// our own identity is left out, and bans which have run out are lifted.
+    pub fn open(config: AddressBookConfig, own: PublicKey, path: PathBuf) -> Result<Self, Error> {
+        let stored: Vec<KnownAddress> = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let book = AddressBook { path: Some(path), ..AddressBook::new(config, own) };
+        let now = now();
+        let count = stored.len();
+        let loaded = book.update(|addrs| {
+            for mut known in stored {
+                if known.pubkey == own ||
+                    addrs.contains_key(&known.pubkey) ||
+                    !book.make_room(addrs)
+                {
+                    continue;
+                }
+                if !known.is_banned(now) {
+                    known.banned_until = None;
+                }
+                addrs.insert(known.pubkey, known);
+            }
+            addrs.len()
+        });
+        // Only a book which lost entries needs writing back.
+        book.dirty.store(loaded != count, Ordering::Relaxed);
+        Ok(book)
+    }
+
+    /// Writes the address book to disk, if it has a path and has changed since it was last saved.
+    /// The book is written to a temporary file beside it and renamed over the old one, so a crash
+    /// never leaves a truncated book behind.
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut addrs: Vec<KnownAddress> = self.addrs.lock().unwrap().values().cloned().collect();
+        addrs.sort_by_key(|a| a.pubkey.to_string());
+        let result = write_atomically(path, &serde_json::to_vec_pretty(&addrs)?);
+        if result.is_err() {
+            // Try again on the next save.
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut HashMap<PublicKey, KnownAddress>) -> R) -> R {
+        self.dirty.store(true, Ordering::Relaxed);
+        f(&mut self.addrs.lock().unwrap())
+    }
+
+    /// Makes room for a new peer in a full book, by forgetting the peer we have failed to reach
+    /// the most times out of those we have never connected to and hold nothing against. Returns
+    /// false if there is no such peer.
+    fn make_room(&self, addrs: &mut HashMap<PublicKey, KnownAddress>) -> bool {
+        if addrs.len() < self.config.max_addresses {
+            return true;
+        }
+        let now = now();
+        let evict = addrs
+            .values()
+            .filter(|known| {
+                known.last_success.is_none() && known.ban_score == 0 && !known.is_banned(now)
+            })
+            .max_by_key(|known| known.attempts)
+            .map(|known| known.pubkey);
+        match evict {
+            Some(pubkey) => addrs.remove(&pubkey).is_some(),
+            None => false,
+        }
+    }
+
+    /// Adds a peer's address. The address of a peer we already know is only replaced if we have
+    /// never connected to the peer on it, so a working address can't be overwritten by a stale
+    /// one learnt from another peer. Returns false if the address is our own, or the book is full.
+    pub fn add(&self, pubkey: PublicKey, addr: SocketAddr) -> bool {
+        if pubkey == self.own {
+            return false;
+        }
+        self.update(|addrs| {
+            if !addrs.contains_key(&pubkey) && !self.make_room(addrs) {
+                return false;
+            }
+            let known = addrs.entry(pubkey).or_insert_with(|| KnownAddress::new(pubkey, None));
+            if known.addr.is_none() || known.last_success.is_none() {
+                known.addr = Some(addr);
+            }
+            true
+        })
+    }
+
+    pub fn remove(&self, pubkey: &PublicKey) {
+        self.update(|addrs| addrs.remove(pubkey));
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<KnownAddress> {
+        self.addrs.lock().unwrap().get(pubkey).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Records a failed attempt to dial a peer.
+    pub fn mark_attempt(&self, pubkey: &PublicKey) {
+        self.update(|addrs| {
+            if let Some(known) = addrs.get_mut(pubkey) {
+                known.attempts += 1;
+                known.last_attempt = Some(now());
+            }
+        });
+    }
+
+    /// Records a successful connection to a peer.
+    pub fn mark_good(&self, pubkey: &PublicKey) {
+        self.update(|addrs| {
+            if let Some(known) = addrs.get_mut(pubkey) {
+                known.attempts = 0;
+                known.last_attempt = Some(now());
+                known.last_success = known.last_attempt;
+            }
+        });
+    }
+
+    /// Adds to a peer's ban score, banning it once the score reaches the threshold. Returns true if
+    /// the peer is banned. Peers we don't have an address for are tracked too, so they can be
+    /// refused if they connect again, unless the book is full.
+    pub fn report(&self, pubkey: &PublicKey, misbehaviour: Misbehaviour) -> bool {
+        let config = &self.config;
+        self.update(|addrs| {
+            if !addrs.contains_key(pubkey) && !self.make_room(addrs) {
+                return false;
+            }
+            let known = addrs.entry(*pubkey).or_insert_with(|| KnownAddress::new(*pubkey, None));
+            known.ban_score += misbehaviour.penalty();
+            if known.ban_score >= config.ban_threshold {
+                known.banned_until = Some(now() + config.ban_duration.as_secs());
+                known.ban_score = 0;
+            }
+            known.is_banned(now())
+        })
+    }
+
+    pub fn is_banned(&self, pubkey: &PublicKey) -> bool {
+        self.addrs.lock().unwrap().get(pubkey).is_some_and(|known| known.is_banned(now()))
+    }
+
+    /// Returns up to `n` random addresses of peers which aren't banned, to share with a peer.
+    pub fn sample(&self, n: usize) -> Vec<KnownAddress> {
+        let now = now();
+        let addrs = self.addrs.lock().unwrap();
+        let mut candidates: Vec<&KnownAddress> =
+            addrs.values().filter(|known| known.addr.is_some() && !known.is_banned(now)).collect();
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.into_iter().take(n).cloned().collect()
+    }
+
+    /// Picks a peer to dial out of those not in `exclude`, preferring peers we have failed to reach
+    /// the fewest times.
+    pub fn pick_to_dial(&self, exclude: &HashSet<PublicKey>) -> Option<KnownAddress> {
+        let now = now();
+        let addrs = self.addrs.lock().unwrap();
+        let mut candidates: Vec<&KnownAddress> = addrs
+            .values()
+            .filter(|known| {
+                known.addr.is_some() && !known.is_banned(now) && !exclude.contains(&known.pubkey)
+            })
+            .collect();
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.into_iter().min_by_key(|known| known.attempts).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ECDSAKeypair;
+
+    #[test]
+    fn test_ban_after_threshold() {
+        let book =
+            AddressBook::new(AddressBookConfig::default(), ECDSAKeypair::new().get_public_key());
+        let peer = ECDSAKeypair::new().get_public_key();
+        let addr = "127.0.0.1:3030".parse().unwrap();
+        book.add(peer, addr);
+
+        assert!(!book.report(&peer, Misbehaviour::InvalidHello));
+        assert_eq!(book.sample(10).len(), 1);
+        assert!(book.report(&peer, Misbehaviour::InvalidHello));
+        assert!(book.is_banned(&peer));
+        assert!(book.sample(10).is_empty());
+        assert!(book.pick_to_dial(&HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn test_full_book_forgets_unreachable_peers() {
+        let config = AddressBookConfig { max_addresses: 2, ..AddressBookConfig::default() };
+        let book = AddressBook::new(config, ECDSAKeypair::new().get_public_key());
+        let good = ECDSAKeypair::new().get_public_key();
+        let unreachable = ECDSAKeypair::new().get_public_key();
+        assert!(book.add(good, "127.0.0.1:3030".parse().unwrap()));
+        assert!(book.add(unreachable, "127.0.0.1:3031".parse().unwrap()));
+        book.mark_good(&good);
+        book.mark_attempt(&unreachable);
+
+        // The peer we never reached makes way for a new one.
+        let new = ECDSAKeypair::new().get_public_key();
+        assert!(book.add(new, "127.0.0.1:3032".parse().unwrap()));
+        assert_eq!(book.len(), 2);
+        assert!(book.get(&unreachable).is_none());
+        assert!(book.get(&good).is_some());
+
+        // Once only peers we have connected to or hold something against are left, new ones are
+        // refused.
+        book.mark_good(&new);
+        assert!(!book.add(unreachable, "127.0.0.1:3031".parse().unwrap()));
+        assert!(!book.report(&unreachable, Misbehaviour::InvalidHello));
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let own = ECDSAKeypair::new().get_public_key();
+        let path = std::env::temp_dir().join(format!("addrbook-{}.json", own));
+        let peer = ECDSAKeypair::new().get_public_key();
+
+        let book = AddressBook::open(AddressBookConfig::default(), own, path.clone()).unwrap();
+        assert!(!book.add(own, "127.0.0.1:3030".parse().unwrap()));
+        assert!(book.add(peer, "127.0.0.1:3031".parse().unwrap()));
+        book.mark_attempt(&peer);
+        book.save().unwrap();
+
+        let reopened = AddressBook::open(AddressBookConfig::default(), own, path.clone()).unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(reopened.get(&peer), book.get(&peer));
+
+        // A stored book is held to the limits of a live one: no own address, no more than
+        // `max_addresses`, and no bans which have run out.
+        let mut stale = KnownAddress::new(ECDSAKeypair::new().get_public_key(), None);
+        stale.banned_until = Some(1);
+        stale.last_success = Some(1);
+        let mut stored = vec![KnownAddress::new(own, None), stale.clone()];
+        stored
+            .extend((0..3).map(|_| KnownAddress::new(ECDSAKeypair::new().get_public_key(), None)));
+        std::fs::write(&path, serde_json::to_string(&stored).unwrap()).unwrap();
+        let config = AddressBookConfig { max_addresses: 2, ..AddressBookConfig::default() };
+        let reopened = AddressBook::open(config, own, path.clone()).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert!(reopened.get(&own).is_none());
+        assert_eq!(reopened.get(&stale.pubkey).unwrap().banned_until, None);
+
+        // The trimmed book is written back in place of the old one.
+        reopened.save().unwrap();
+        let saved: Vec<KnownAddress> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(saved.len(), 2);
+    }
+}
@@ -0,0 +1,136 @@
+use blst::{min_pk, BLST_ERROR};
+use rand::{rngs::OsRng, RngCore};
+use std::fmt;
+
+use crate::crypto::{CryptoError, PublicKey, Signature};
+
+/// The domain separation tag of the proof-of-possession ciphersuite. Fast aggregate verification
+/// is only safe for keys whose owners have proven they hold the secret key, so every BLS validator
+/// key in a genesis must come with a proof of possession.
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// The domain separation tag of proofs of possession, kept apart from that of messages so a proof
+/// can't be passed off as a signature.
+const POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// A BLS12-381 keypair, with public keys in G1 (48 bytes compressed) and signatures in G2 (96
+/// bytes compressed). Signatures by different keys over the same data aggregate into one.
+#[derive(Clone)]
+pub struct Bls12381Keypair {
+    secret_key: min_pk::SecretKey,
+}
+
+impl fmt::Debug for Bls12381Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bls12381Keypair").field("public_key", &self.get_public_key()).finish()
+    }
+}
+
+impl Default for Bls12381Keypair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bls12381Keypair {
+    pub fn new() -> Self {
+        let mut ikm = [0u8; 32];
+        OsRng.fill_bytes(&mut ikm);
+        let secret_key = min_pk::SecretKey::key_gen(&ikm, &[]).expect("32 bytes of key material");
+        Bls12381Keypair { secret_key }
+    }
+
+    /// Loads a keypair from its hex-encoded 32-byte secret key.
+    pub fn new_from_privatekey(private_key: &str) -> Result<Self, CryptoError> {
+        let bytes = hex::decode(private_key)?;
+        let secret_key = min_pk::SecretKey::from_bytes(&bytes).map_err(CryptoError::Bls12381)?;
+        Ok(Bls12381Keypair { secret_key })
+    }
+
+    pub fn get_public_key(&self) -> PublicKey {
+        PublicKey::Bls12381(self.secret_key.sk_to_pk().compress())
+    }
+
+    /// Returns the 32-byte secret key, hex encoded.
+    pub fn get_secret_key(&self) -> String {
+        hex::encode(self.secret_key.to_bytes())
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Signature {
+        Signature::Bls12381(self.secret_key.sign(data, DST, &[]).compress())
+    }
+
+    /// Proves the keypair holds the secret key of its public key, by signing the public key.
+    pub fn prove_possession(&self) -> Signature {
+        let public_key = self.secret_key.sk_to_pk().compress();
+        Signature::Bls12381(self.secret_key.sign(&public_key, POP_DST, &[]).compress())
+    }
+}
+
+/// Checks a proof that the holder of `public_key` has its secret key. Without one, a key made up
+/// from others' keys could forge aggregate signatures on their behalf.
+pub fn verify_possession(public_key: &[u8; 48], proof: &Signature) -> bool {
+    let Signature::Bls12381(proof) = proof else {
+        return false;
+    };
+    let (Ok(public_key), Ok(proof)) =
+        (min_pk::PublicKey::uncompress(public_key), min_pk::Signature::uncompress(proof))
+    else {
+        return false;
+    };
+    proof.verify(true, &public_key.compress(), POP_DST, &[], &public_key, true) ==
+        BLST_ERROR::BLST_SUCCESS
+}
+
+/// Checks that `bytes` are a compressed public key in the G1 subgroup.
+pub(crate) fn public_key_from_bytes(bytes: &[u8]) -> Result<[u8; 48], CryptoError> {
+    let public_key = min_pk::PublicKey::key_validate(bytes).map_err(CryptoError::Bls12381)?;
+    Ok(public_key.compress())
+}
+
+/// Checks that `bytes` are a compressed signature in the G2 subgroup.
+pub(crate) fn signature_from_bytes(bytes: &[u8]) -> Result<[u8; 96], CryptoError> {
+    let signature = min_pk::Signature::sig_validate(bytes, true).map_err(CryptoError::Bls12381)?;
+    Ok(signature.compress())
+}
+
+pub(crate) fn verify(public_key: &[u8; 48], data: &[u8], signature: &[u8; 96]) -> bool {
+    verify_aggregate(&[PublicKey::Bls12381(*public_key)], data, &Signature::Bls12381(*signature))
+}
+
+/// Aggregates signatures over the same data into a single signature. Returns None if there are
+/// no signatures, or any of them isn't a BLS12-381 signature.
+pub fn aggregate_signatures(signatures: &[Signature]) -> Option<Signature> {
+    let signatures = signatures
+        .iter()
+        .map(|signature| match signature {
+            Signature::Bls12381(bytes) => min_pk::Signature::uncompress(bytes).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let signatures: Vec<&min_pk::Signature> = signatures.iter().collect();
+    let aggregate = min_pk::AggregateSignature::aggregate(&signatures, true).ok()?;
+    Some(Signature::Bls12381(aggregate.to_signature().compress()))
+}
+
+/// Verifies a signature over `data`, aggregated from signatures by each of `public_keys`.
+pub fn verify_aggregate(public_keys: &[PublicKey], data: &[u8], signature: &Signature) -> bool {
+    let Signature::Bls12381(signature) = signature else {
+        return false;
+    };
+    let Some(public_keys) = public_keys
+        .iter()
+        .map(|public_key| match public_key {
+            PublicKey::Bls12381(bytes) => min_pk::PublicKey::uncompress(bytes).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    let Ok(signature) = min_pk::Signature::uncompress(signature) else {
+        return false;
+    };
+    let public_keys: Vec<&min_pk::PublicKey> = public_keys.iter().collect();
+    signature.fast_aggregate_verify(true, data, DST, &public_keys) == BLST_ERROR::BLST_SUCCESS
+}
@@ -1,39 +1,214 @@
-use crate::utils::CmdSync;
-use clap::Parser;
-use serde_json::Result;
-use tendermint::{config::AccountConfig, crypto::ECDSAKeypair};
+use crate::utils::{
+    default_home, exit_code, prompt_passphrase, read_passphrase, CliError, CmdSync, Context, Result,
+};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use tendermint::{
+    config::AccountConfig,
+    crypto::{Address, KeyType, Keypair, PublicKey},
+    keystore::{AccountFile, Keyring, Keystore, ScryptParams},
+};
 
 pub struct AccountsOutput {}
 
+/// Manages the named accounts in a keyring directory, each in its own encrypted keystore.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct AccountsArgs {
-    // --list
-    #[clap(long, help = "List all accounts")]
-    pub list: bool,
-    // --new
-    #[clap(long, help = "Create a new account")]
-    pub new: bool,
+    #[clap(
+        long,
+        global = true,
+        env = "TENDERMINT_KEYRING",
+        help = "The keyring directory [default: ~/.tendermint/keyring]"
+    )]
+    pub keyring: Option<PathBuf>,
+    #[clap(long, global = true, help = "Print output as JSON, for scripts")]
+    pub json: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Read the passphrase from a file, instead of TENDERMINT_PASSPHRASE or a prompt"
+    )]
+    pub passphrase_file: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: AccountsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AccountsCommand {
+    /// List the accounts in the keyring
+    List,
+    /// Show an account's public key and address
+    Show { name: String },
+    /// Create a new account
+    New {
+        name: String,
+        #[clap(
+            long,
+            default_value = "secp256k1",
+            help = "Key type: secp256k1, ed25519, or bls12381 with the bls feature"
+        )]
+        key_type: KeyType,
+    },
+    /// Add an account from a plaintext account file, or a keystore
+    Import { name: String, path: PathBuf },
+    /// Decrypt an account, and print it with its private key
+    Export { name: String },
+    /// Re-encrypt an account with a new passphrase
+    ChangePassphrase {
+        name: String,
+        #[clap(long, help = "Read the new passphrase from a file, instead of a prompt")]
+        new_passphrase_file: Option<PathBuf>,
+    },
+    /// Delete an account, and its key with it
+    Delete {
+        name: String,
+        #[clap(long, help = "Confirm the key is to be destroyed")]
+        yes: bool,
+    },
+}
+
+/// An account as printed, without its private key.
+#[derive(Serialize)]
+struct AccountInfo {
+    name: String,
+    key_type: KeyType,
+    pubkey: PublicKey,
+    address: Address,
+    keystore: PathBuf,
 }
 
 impl CmdSync for AccountsArgs {
     type Output = Result<AccountsOutput>;
 
     fn run(self) -> Self::Output {
-        if self.list {
-            // TODO.
-        } else if self.new {
-            new_account()
+        let dir = self.keyring.clone().unwrap_or_else(|| default_home().join("keyring"));
+        let keyring = Keyring::open(dir).context("Failed to open keyring")?;
+
+        match &self.command {
+            AccountsCommand::List => {
+                let mut accounts = Vec::new();
+                for (name, keystore) in keyring.list().context("Failed to read keyring")? {
+                    let info = keystore
+                        .context("Failed to read keystore")
+                        .and_then(|keystore| self.info(&keyring, &name, &keystore));
+                    match info {
+                        Ok(info) => accounts.push(info),
+                        Err(err) => tracing::warn!(
+                            path = %keyring.path(&name).display(),
+                            error = %err,
+                            "Skipping unreadable keystore"
+                        ),
+                    }
+                }
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&accounts).unwrap());
+                } else {
+                    for account in accounts {
+                        println!("{}\t{}\t{}", account.name, account.address, account.pubkey);
+                    }
+                }
+            }
+            AccountsCommand::Show { name } => {
+                let keystore = keyring.get(name).context("Failed to read account")?;
+                self.print(self.info(&keyring, name, &keystore)?);
+            }
+            AccountsCommand::New { name, key_type } => {
+                let keypair = Keypair::generate(*key_type);
+                self.add(&keyring, name, &keypair)?;
+            }
+            AccountsCommand::Import { name, path } => {
+                match AccountFile::load(path).context("Failed to read account")? {
+                    AccountFile::Plaintext(account) => {
+                        let keypair =
+                            Keypair::new_from_privatekey(account.key_type, &account.privkey)
+                                .context("Invalid private key")?;
+                        self.add(&keyring, name, &keypair)?;
+                    }
+                    // Keystores are added as they are, keeping their passphrase.
+                    AccountFile::Encrypted(keystore) => {
+                        keyring.add(name, &keystore).context("Failed to add account")?;
+                        self.print(self.info(&keyring, name, &keystore)?);
+                    }
+                }
+            }
+            AccountsCommand::Export { name } => {
+                let keypair = self.unlock(&keyring, name)?;
+                let datum = AccountConfig {
+                    key_type: keypair.key_type(),
+                    pubkey: keypair.get_public_key().to_string(),
+                    privkey: keypair.get_secret_key(),
+                };
+                println!("{}", serde_json::to_string_pretty(&datum).unwrap());
+            }
+            AccountsCommand::ChangePassphrase { name, new_passphrase_file } => {
+                let keypair = self.unlock(&keyring, name)?;
+                let passphrase = match new_passphrase_file {
+                    Some(file) => read_passphrase("", Some(file), false),
+                    None => prompt_passphrase("New passphrase: ", true),
+                }
+                .context("Failed to read passphrase")?;
+                let keystore = Keystore::encrypt(&keypair, &passphrase, ScryptParams::default());
+                keyring.update(name, &keystore).context("Failed to write keystore")?;
+                if !self.json {
+                    println!("Changed the passphrase of {}", name);
+                }
+            }
+            AccountsCommand::Delete { name, yes } => {
+                if !yes {
+                    return Err(CliError::new(
+                        format!("Deleting {} destroys its key, pass --yes to confirm", name),
+                        exit_code::DATA_ERR,
+                    ));
+                }
+                keyring.delete(name).context("Failed to delete account")?;
+                if !self.json {
+                    println!("Deleted {}", name);
+                }
+            }
         }
         Ok(AccountsOutput {})
     }
 }
 
-fn new_account() {
-    let keypair = ECDSAKeypair::new();
-    let datum = AccountConfig {
-        pubkey: keypair.get_public_key().to_string(),
-        privkey: keypair.get_secret_key().display_secret().to_string(),
-    };
-    println!("{}", serde_json::to_string_pretty(&datum).unwrap());
+impl AccountsArgs {
+    /// Encrypts a keypair into a new account.
+    fn add(&self, keyring: &Keyring, name: &str, keypair: &Keypair) -> Result<()> {
+        let passphrase = read_passphrase("Passphrase: ", self.passphrase_file.as_deref(), true)
+            .context("Failed to read passphrase")?;
+        let keystore = Keystore::encrypt(keypair, &passphrase, ScryptParams::default());
+        keyring.add(name, &keystore).context("Failed to add account")?;
+        self.print(self.info(keyring, name, &keystore)?);
+        Ok(())
+    }
+
+    /// Decrypts an account.
+    fn unlock(&self, keyring: &Keyring, name: &str) -> Result<Keypair> {
+        let keystore = keyring.get(name).context("Failed to read account")?;
+        let passphrase = read_passphrase("Passphrase: ", self.passphrase_file.as_deref(), false)
+            .context("Failed to read passphrase")?;
+        keystore.decrypt(&passphrase).context("Failed to decrypt account")
+    }
+
+    fn info(&self, keyring: &Keyring, name: &str, keystore: &Keystore) -> Result<AccountInfo> {
+        let pubkey = keystore.pubkey.parse::<PublicKey>().context("Invalid keystore")?;
+        Ok(AccountInfo {
+            name: name.to_string(),
+            key_type: keystore.key_type,
+            pubkey,
+            address: pubkey.address(),
+            keystore: keyring.path(name),
+        })
+    }
+
+    fn print(&self, account: AccountInfo) {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&account).unwrap());
+        } else {
+            println!("Account: {}", account.pubkey);
+            println!("Address: {}", account.address);
+            println!("Keystore: {}", account.keystore.display());
+        }
+    }
 }
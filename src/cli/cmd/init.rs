@@ -0,0 +1,143 @@
+use crate::utils::{
+    default_home, exit_code, read_passphrase, CliError, CmdSync, Context, Result, HOME_ENV,
+};
+use clap::Parser;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use tendermint::{
+    config::{parse_config, NodeConfig, TendermintConfig, ValidatorInfo, NODE_CONFIG_FILE},
+    crypto::{KeyType, Keypair},
+    keystore::{Keystore, ScryptParams},
+};
+
+pub struct InitOutput {}
+
+/// Creates a node home directory: a node config, a genesis, and the node's key in an encrypted
+/// keystore.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct InitArgs {
+    #[clap(long, env = HOME_ENV, help = "The home directory to create [default: ~/.tendermint]")]
+    pub home: Option<PathBuf>,
+    #[clap(long, default_value = "tendermint-local", help = "The chain id of a new genesis")]
+    pub chain_id: String,
+    #[clap(
+        long,
+        help = "Join the chain with this genesis, instead of starting a new one with the node as \
+                its only validator, deciding on its own"
+    )]
+    pub genesis: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "secp256k1",
+        help = "Key type of the node: secp256k1, ed25519, or bls12381 with the bls feature"
+    )]
+    pub key_type: KeyType,
+    #[clap(long, default_value = "0.0.0.0:3030", help = "The address to accept peers on")]
+    pub listen_addr: SocketAddr,
+    #[clap(
+        long,
+        help = "Read the passphrase from a file, instead of TENDERMINT_PASSPHRASE or a prompt"
+    )]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+impl CmdSync for InitArgs {
+    type Output = Result<InitOutput>;
+
+    fn run(self) -> Self::Output {
+        let home = self.home.unwrap_or_else(default_home);
+        let keypair = Keypair::generate(self.key_type);
+        let genesis = match &self.genesis {
+            Some(path) => parse_config(path.clone()).context("Failed to load genesis")?,
+            None => {
+                let validator = ValidatorInfo::new(keypair.get_public_key(), self.listen_addr)
+                    .with_proof_of_possession(&keypair);
+                TendermintConfig::new(self.chain_id, vec![validator])
+            }
+        };
+        let mut config = NodeConfig::default();
+        config.p2p.listen_addr = self.listen_addr;
+
+        let passphrase = read_passphrase("Passphrase: ", self.passphrase_file.as_deref(), true)
+            .context("Failed to read passphrase")?;
+        let keystore = Keystore::encrypt(&keypair, &passphrase, ScryptParams::default());
+        write_home(&home, &config, &genesis, &keystore)?;
+        println!("Account: {}", keypair.get_public_key());
+        println!("Home: {}", home.display());
+        Ok(InitOutput {})
+    }
+}
+
+/// Writes a node home directory: the node config, the genesis and keystore at the paths it
+/// names, and an empty data directory. Refuses to overwrite an existing home.
+pub fn write_home(
+    home: &Path,
+    config: &NodeConfig,
+    genesis: &TendermintConfig,
+    keystore: &Keystore,
+) -> Result<()> {
+    if home.join(NODE_CONFIG_FILE).exists() {
+        return Err(CliError::new(
+            format!("Refusing to overwrite the node home {}", home.display()),
+            exit_code::CANT_CREAT,
+        ));
+    }
+    std::fs::create_dir_all(home.join(&config.data_dir)).context("Failed to create home")?;
+    genesis.save(&home.join(&config.genesis)).context("Failed to write genesis")?;
+    keystore.save(&home.join(&config.account)).context("Failed to write keystore")?;
+    config.save(home).context("Failed to write node config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Duration};
+    use tendermint::process::{EpochState, Process};
+    use tokio::sync::{mpsc, Mutex};
+
+    #[tokio::test]
+    async fn test_init_creates_a_chain_that_decides() {
+        let dir = std::env::temp_dir().join(format!("tendermint-init-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let passphrase_file = dir.join("passphrase");
+        std::fs::write(&passphrase_file, "hunter2\n").unwrap();
+        let args = |home: &Path| InitArgs {
+            home: Some(home.to_path_buf()),
+            chain_id: "test-chain".to_string(),
+            genesis: None,
+            key_type: KeyType::Ed25519,
+            listen_addr: "127.0.0.1:3030".parse().unwrap(),
+            passphrase_file: Some(passphrase_file.clone()),
+        };
+        let home = dir.join("node");
+        args(&home).run().unwrap();
+        let err = args(&home).run().err().unwrap();
+        assert_eq!(err.exit_code(), exit_code::CANT_CREAT);
+
+        let config = NodeConfig::load(&home).unwrap();
+        let genesis = parse_config(home.join(&config.genesis)).unwrap();
+        let keypair = Keystore::load(&home.join(&config.account)).unwrap().decrypt("hunter2");
+        let keypair = keypair.unwrap();
+        assert_eq!(genesis.validator_keys().unwrap(), vec![keypair.get_public_key()]);
+
+        // The node is the only validator, so decides on its own.
+        let (_sender, receiver) = mpsc::channel(1);
+        let mut process = Process::new(
+            0,
+            keypair,
+            genesis.chain_id.clone(),
+            genesis.validator_keys().unwrap(),
+            Arc::new(Mutex::new(receiver)),
+            vec![],
+            vec![0],
+            || "v".to_string(),
+        );
+        process.set_voting_powers(genesis.voting_powers());
+        let epoch = process.run_epoch(Some(EpochState::new(genesis.initial_height)));
+        tokio::time::timeout(Duration::from_secs(5), epoch).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,6 @@
 pub mod accounts;
+pub mod init;
 pub mod network;
 pub mod node;
+pub mod sign;
+pub mod testnet;
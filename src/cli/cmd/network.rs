@@ -1,29 +1,32 @@
-use crate::utils::CmdSync;
+use crate::utils::{CmdSync, Result};
 use clap::Parser;
-use serde_json::Result;
 use tendermint::{
     config::{TendermintConfig, ValidatorInfo},
-    crypto::ECDSAKeypair,
+    crypto::{KeyType, Keypair},
 };
 
 pub struct NetworkOutput {}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-pub struct NetworkArgs {}
+pub struct NetworkArgs {
+    // chain id
+    #[clap(long, default_value = "tendermint-local")]
+    chain_id: String,
+
+    // key type of the validator: secp256k1, ed25519, or bls12381 with the bls feature.
+    #[clap(long, default_value = "secp256k1")]
+    key_type: KeyType,
+}
 
 impl CmdSync for NetworkArgs {
     type Output = Result<NetworkOutput>;
 
     fn run(self) -> Self::Output {
-        let keypair = ECDSAKeypair::new();
-        let config = TendermintConfig {
-            validators: vec![ValidatorInfo {
-                pubkey: keypair.get_public_key().to_string(),
-                address: "0.0.0.0".parse().unwrap(),
-                port: 3030,
-            }],
-        };
+        let keypair = Keypair::generate(self.key_type);
+        let validator =
+            ValidatorInfo::new(keypair.get_public_key(), "0.0.0.0:3030".parse().unwrap());
+        let config = TendermintConfig::new(self.chain_id, vec![validator]);
         // Print to JSON format (pretty)
         let config = serde_json::to_string_pretty(&config).unwrap();
         println!("{}", config);
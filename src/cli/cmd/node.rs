@@ -1,96 +1,391 @@
-use crate::utils::CmdAsync;
+use crate::utils::{
+    default_home, exit_code, init_logging, load_account, CliError, CmdAsync, Context, Result,
+    HOME_ENV,
+};
 use clap::Parser;
-use serde_json::Result;
-use std::{net::IpAddr, path::PathBuf};
-use tendermint::config::{parse_config, AccountConfig, ValidatorInfo};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tendermint::{
+    addrbook::{AddressBook, AddressBookConfig},
+    codec::Encoding,
+    config::{parse_config, LogFormat, NodeConfig, TendermintConfig},
+    crypto::Keypair,
+    decision_log::DecisionLog,
+    events::EventSystem,
+    gossip::Gossip,
+    messages::{PeerMessage, SignedMessage},
+    metrics::Metrics,
+    p2p::{NodeInfo, PeerServer},
+    peers::PeerTable,
+    pex::{Pex, PexAddr},
+    privval::{PrivValidator, RemoteSigner},
+    process::{EpochState, Event, Process},
+    rpc_server::{QueryServer, QueryState, Server},
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinError,
+};
+use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
 
 pub struct NodeOutput {}
 
+/// Runs a node. Settings are read from `config.toml` in the node's home directory, and each may
+/// be overridden by an environment variable, and then by a flag.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct NodeArgs {
-    // port
-    #[clap(long, default_value = "3030")]
-    port: u16,
+    // home directory, holding config.toml. Defaults to ~/.tendermint.
+    #[clap(long, env = HOME_ENV)]
+    home: Option<PathBuf>,
 
-    // host
-    #[clap(long, default_value = "0.0.0.0")]
-    host: IpAddr,
+    // genesis config.
+    #[clap(long, alias = "config", env = "TENDERMINT_GENESIS")]
+    genesis: Option<PathBuf>,
 
-    // privatekey
-    #[clap(long)]
-    account: PathBuf,
+    // account keystore, or a plaintext account file.
+    #[clap(long, env = "TENDERMINT_ACCOUNT")]
+    account: Option<PathBuf>,
 
-    // genesis config.
+    // file holding the keystore passphrase, instead of TENDERMINT_PASSPHRASE or a prompt.
     #[clap(long)]
-    config: PathBuf,
+    passphrase_file: Option<PathBuf>,
+
+    // remote signer holding the validator key, as ip:port. The account is then only the node's
+    // identity towards peers and the signer.
+    #[clap(long, env = "TENDERMINT_SIGNER")]
+    signer: Option<SocketAddr>,
+
+    // directory the address book and other state is kept in.
+    #[clap(long, env = "TENDERMINT_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    // address to accept peer connections on, as ip:port.
+    #[clap(long, env = "TENDERMINT_P2P_LISTEN_ADDR")]
+    listen_addr: Option<SocketAddr>,
+
+    // seed nodes, as pubkey@ip:port.
+    #[clap(long, env = "TENDERMINT_P2P_SEEDS", use_value_delimiter = true)]
+    seeds: Option<Vec<PexAddr>>,
+
+    // maximum number of peers which may dial us.
+    #[clap(long, env = "TENDERMINT_P2P_MAX_INBOUND")]
+    max_inbound: Option<usize>,
+
+    // maximum number of peers we dial.
+    #[clap(long, env = "TENDERMINT_P2P_MAX_OUTBOUND")]
+    max_outbound: Option<usize>,
+
+    // encoding of messages sent to peers: proto, or json for debugging.
+    #[clap(long, env = "TENDERMINT_P2P_WIRE_ENCODING")]
+    wire_encoding: Option<Encoding>,
+
+    // address to serve the HTTP inbox on, as ip:port.
+    #[clap(long, env = "TENDERMINT_RPC_LISTEN_ADDR")]
+    rpc_listen_addr: Option<SocketAddr>,
+
+    // address to serve status, decision, validator and peer queries on, as ip:port.
+    #[clap(long, env = "TENDERMINT_RPC_QUERY_LISTEN_ADDR")]
+    rpc_query_listen_addr: Option<SocketAddr>,
+
+    // lowest level logged, optionally per module, e.g. info,tendermint::gossip=debug.
+    #[clap(long, env = "TENDERMINT_LOG_LEVEL")]
+    log_level: Option<String>,
+
+    // format of log lines: text, or json for log pipelines.
+    #[clap(long, env = "TENDERMINT_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+impl NodeArgs {
+    /// Overrides the settings of a node config with those given in the environment or as flags.
+    /// Paths given this way are relative to the working directory.
+    fn apply(self, config: &mut NodeConfig) {
+        if let Some(genesis) = self.genesis {
+            config.genesis = genesis;
+        }
+        if let Some(account) = self.account {
+            config.account = account;
+        }
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = data_dir;
+        }
+        config.signer = self.signer.or(config.signer);
+        let p2p = &mut config.p2p;
+        p2p.listen_addr = self.listen_addr.unwrap_or(p2p.listen_addr);
+        if let Some(seeds) = self.seeds {
+            p2p.seeds = seeds;
+        }
+        p2p.max_inbound = self.max_inbound.unwrap_or(p2p.max_inbound);
+        p2p.max_outbound = self.max_outbound.unwrap_or(p2p.max_outbound);
+        p2p.wire_encoding = self.wire_encoding.unwrap_or(p2p.wire_encoding);
+        config.rpc.listen_addr = self.rpc_listen_addr.or(config.rpc.listen_addr);
+        config.rpc.query_listen_addr = self.rpc_query_listen_addr.or(config.rpc.query_listen_addr);
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        config.log.format = self.log_format.unwrap_or(config.log.format);
+    }
 }
 
 impl CmdAsync for NodeArgs {
     type Output = Result<NodeOutput>;
 
     async fn run(self) -> Self::Output {
-        let config = parse_config(self.config);
-        // Load the account config.
-        let accountData = std::fs::read_to_string(self.account).unwrap();
-        let account: AccountConfig = serde_json::from_str(&accountData).unwrap();
-        println!("Account: {:?}", account);
-        run_node(config.validators, self.host, self.port).await;
+        let home = self.home.clone().unwrap_or_else(default_home);
+        let mut node_config = NodeConfig::load(&home).context("Failed to load node config")?;
+        // Paths in the config file are relative to the home directory.
+        node_config.genesis = home.join(&node_config.genesis);
+        node_config.account = home.join(&node_config.account);
+        node_config.data_dir = home.join(&node_config.data_dir);
+        let passphrase_file = self.passphrase_file.clone();
+        self.apply(&mut node_config);
+        init_logging(&node_config.log)?;
+
+        let genesis =
+            parse_config(node_config.genesis.clone()).context("Failed to load genesis")?;
+        debug!(?genesis, "Loaded genesis");
+        // Load the account, decrypting it if it's in a keystore.
+        let keypair = load_account(&node_config.account, passphrase_file.as_deref())?;
+        info!(account = %keypair.get_public_key(), "Loaded account");
+        let signer: PrivValidator = match node_config.signer {
+            Some(addr) => {
+                let signer = RemoteSigner::connect(addr, keypair.clone())
+                    .await
+                    .context("Failed to connect to signer")?;
+                info!(validator = %signer.public_key(), signer = %addr, "Connected to signer");
+                signer.into()
+            }
+            None => keypair.clone().into(),
+        };
+        std::fs::create_dir_all(&node_config.data_dir).context("Failed to create data dir")?;
+        run_node(genesis, node_config, keypair, signer).await?;
         Ok(NodeOutput {})
     }
 }
 
-use std::time::{SystemTime, UNIX_EPOCH};
+async fn run_node(
+    config: TendermintConfig,
+    node_config: NodeConfig,
+    keypair: Keypair,
+    signer: PrivValidator,
+) -> Result<()> {
+    // The validator set, in genesis order. A node's index in the set is its process id.
+    let validators = config.validator_keys().context("Invalid config")?;
+    let powers = config.voting_powers();
+    let id = validators.iter().position(|v| *v == signer.public_key());
+    let genesis_hash = config.hash();
+    info!(chain_id = %config.chain_id, genesis_hash = %hex::encode(genesis_hash), "Starting node");
 
-use tendermint::{
-    crypto::ECDSAKeypair, messages::SignedMessage, process::Process, rpc_server::Server,
-};
-use tokio_stream::StreamExt;
+    // Only validators, seeds and the full nodes listed in the config may connect.
+    let mut allowed = config
+        .validators
+        .iter()
+        .map(|info| info.node_pubkey())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid config")?;
+    allowed.extend(node_config.p2p.seeds.iter().map(|seed| seed.pubkey));
+    allowed.extend(node_config.p2p.allowed_peers.iter().copied());
+    let node = NodeInfo {
+        keypair: keypair.clone(),
+        chain_id: config.chain_id.clone(),
+        genesis_hash,
+        listen_addr: node_config.p2p.listen_addr,
+        encoding: node_config.p2p.wire_encoding,
+        allowed,
+    };
+    let table = Arc::new(PeerTable::<PeerMessage>::new());
+
+    // Load the address book, and add the genesis validators to it.
+    let addr_book = node_config.data_dir.join("addrbook.json");
+    let book = AddressBook::open(AddressBookConfig::default(), keypair.get_public_key(), addr_book)
+        .context("Failed to open address book")?;
+    let book = Arc::new(book);
+    for info in &config.validators {
+        let node_pubkey = info.node_pubkey().context("Invalid config")?;
+        book.add(node_pubkey, SocketAddr::new(info.address, info.port));
+    }
 
-async fn run_node(_validators: Vec<ValidatorInfo>, host: IpAddr, port: u16) {
-    // Network configuration:
-    // - peers: (pubkey,address)[]
-    // Parse the configuration file.
+    // Setup peer server. Messages from peers pass through peer exchange and then gossip.
+    let p2p = &node_config.p2p;
+    let server = PeerServer::new(node.clone(), table.clone(), book.clone(), p2p.max_inbound);
+    let gossip = Gossip::new(p2p.gossip_config(), config.chain_id.clone());
+    gossip.set_address_book(book.clone());
+    let pex = Pex::new(p2p.pex_config(), node, table.clone(), book, gossip.clone());
+    let (sender, mut receiver) = gossip.start(pex.start(server.get_receiver()));
+    let server = tokio::spawn(async move { server.run().await });
+    if let Some(addr) = node_config.rpc.listen_addr {
+        receiver = serve_inbox(addr, gossip.clone(), receiver);
+    }
+    let metrics = Arc::new(Metrics::new());
+    metrics.register(gossip.verifier().invalid_signatures()).context("Failed to set up metrics")?;
+    let queries = QueryState::new(keypair.get_public_key(), &config, table.clone())
+        .with_metrics(metrics.clone());
 
-    // Setup RPC server.
-    // Setup RPC client for each peer.
-    // Setup process.
-    // Run process.
+    // Nodes outside of the validator set relay messages, without taking part in consensus.
+    let Some(id) = id else {
+        info!(
+            account = %signer.public_key(),
+            "Account is not in the validator set, running as a full node"
+        );
+        tokio::spawn(async move {
+            let mut receiver = receiver.lock().await;
+            while receiver.recv().await.is_some() {}
+        });
+        serve_queries(node_config.rpc.query_listen_addr, queries);
+        return server_stopped(server.await);
+    };
 
-    let keypair = ECDSAKeypair::new();
+    // The function to get the current value for the chain.
+    let get_value = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
 
-    let peer_senders = Vec::new();
+    // Define proposer sequence (round-robin over the validator set)
+    let proposer_sequence: Vec<usize> = (0..validators.len()).collect();
+    let mut process = Process::new(
+        id,
+        signer,
+        config.chain_id,
+        validators,
+        receiver,
+        vec![sender],
+        proposer_sequence,
+        get_value,
+    );
+    process.set_consensus_params(config.consensus_params);
+    process.set_voting_powers(powers);
+    // Messages verified by gossip aren't verified again by the process.
+    process.set_verifier(gossip.verifier());
+    let vote_set_bits = gossip.track_votes(process.round_state());
+    process.set_events(EventSystem::with_capacity(node_config.rpc.event_capacity));
+    let decision_log = DecisionLog::open(node_config.data_dir.join("decisions.jsonl"))
+        .context("Failed to open decision log")?;
+    let decision_log = Arc::new(decision_log);
+    process.set_decision_log(decision_log.clone());
+    let queries = serve_queries(
+        node_config.rpc.query_listen_addr,
+        queries
+            .with_process(id, process.round_state(), process.events())
+            .with_decision_log(decision_log.clone()),
+    );
 
-    let api_server = Server::<SignedMessage>::new(host, port);
-    let receiver = api_server.get_receiver();
+    // Listen to events, record decisions for queries, and advertise the latest decided height to
+    // new peers. Decisions missed by falling behind are read back from the log.
+    let mut subscriber1 = process.events().subscribe_checked();
+    let events_table = table.clone();
     tokio::spawn(async move {
-        api_server.run().await;
+        let mut latest = 0;
+        while let Some(event) = subscriber1.next().await {
+            let decisions = match event {
+                Ok(event @ Event::Decision { .. }) => vec![event],
+                Ok(_) => continue,
+                Err(lagged) => {
+                    warn!(
+                        missed = lagged.0,
+                        "Fell behind consensus events, reading the decision log"
+                    );
+                    decision_log.read_from(latest + 1).await.unwrap_or_else(|err| {
+                        error!(error = %err, "Failed to read decision log");
+                        Vec::new()
+                    })
+                }
+            };
+            for event in decisions {
+                latest = event.height();
+                queries.record(&event);
+                events_table.set_latest_height(latest);
+                debug!(?event, "Recorded decision");
+            }
+        }
     });
-    // tokio::spawn(async move {
-    //     client.start().await;
-    // });
 
-    // The function to get the current value for the chain.
-    let get_value = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
+    // Keep the consensus metrics. Events missed by falling behind are only missing from them.
+    let mut subscriber2 = process.events().subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = subscriber2.next().await {
+            metrics.record(&event);
+        }
+    });
 
-    // Define proposer sequence (round-robin)
-    let proposer_sequence: Vec<usize> = (0..4).collect();
-    let mut process =
-        Process::new(0, keypair, receiver, peer_senders, proposer_sequence.clone(), get_value);
+    // Decide one height after another, for as long as the node runs.
+    let consensus = tokio::spawn(async move {
+        let mut height = config.initial_height;
+        loop {
+            height = process.run_epoch(Some(EpochState::new(height))).await.height();
+        }
+    });
+    let result = tokio::select! {
+        // Consensus only stops if it panics.
+        result = consensus => result.map_err(|err| CliError::new(err, exit_code::SOFTWARE)),
+        result = server => server_stopped(result),
+    };
+    vote_set_bits.abort();
+    result
+}
 
-    // Listen to events from node0.
-    let mut subscriber1 = process.subscribe();
+/// Serves the HTTP inbox on `addr`. Messages posted to it are relayed to peers, and delivered to
+/// the local process along with those from peers. Returns the receiver the process reads both from.
+fn serve_inbox(
+    addr: SocketAddr,
+    gossip: Arc<Gossip>,
+    from_peers: Arc<Mutex<mpsc::Receiver<SignedMessage>>>,
+) -> Arc<Mutex<mpsc::Receiver<SignedMessage>>> {
+    let (deliver_tx, deliver_rx) = mpsc::channel(100);
+    let server = Server::<SignedMessage>::new(addr.ip(), addr.port());
+    let from_inbox = server.get_receiver();
     tokio::spawn(async move {
-        while let Some(event) = subscriber1.next().await {
-            println!("Subscriber 1 received: {:?}", event);
+        if let Err(err) = server.run().await {
+            error!(error = %err, "RPC server failed");
         }
     });
 
+    let sender = deliver_tx.clone();
+    tokio::spawn(async move {
+        let mut from_peers = from_peers.lock().await;
+        while let Some(message) = from_peers.recv().await {
+            if sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
     tokio::spawn(async move {
-        process.run_epoch(None).await;
-    })
-    .await
-    .unwrap();
+        let mut from_inbox = from_inbox.lock().await;
+        while let Some(message) = from_inbox.recv().await {
+            // Don't relay forgeries to peers.
+            if !gossip.verifier().verify(&message) {
+                continue;
+            }
+            gossip.publish(&message);
+            if deliver_tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+    Arc::new(Mutex::new(deliver_rx))
+}
 
-    println!("Consensus reached.");
+/// Serves queries about the node on `addr`, if it is set.
+fn serve_queries(addr: Option<SocketAddr>, state: QueryState) -> Arc<QueryState> {
+    let state = Arc::new(state);
+    if let Some(addr) = addr {
+        let server = QueryServer::new(addr, state.clone());
+        tokio::spawn(async move {
+            if let Err(err) = server.run().await {
+                error!(error = %err, "Query server failed");
+            }
+        });
+    }
+    state
+}
+
+/// The peer server only stops if it fails, which stops the node too.
+fn server_stopped(result: std::result::Result<std::io::Result<()>, JoinError>) -> Result<()> {
+    match result {
+        Ok(result) => result.context("Peer server failed"),
+        Err(err) => Err(CliError::new(err, exit_code::SOFTWARE)),
+    }
 }
@@ -0,0 +1,188 @@
+use crate::utils::{
+    default_home, exit_code, load_account, CliError, CmdSync, Context, Result, HOME_ENV,
+};
+use clap::Parser;
+use serde::de::DeserializeOwned;
+use sha3::{Digest, Keccak256};
+use std::path::{Path, PathBuf};
+use tendermint::{
+    config::{parse_config, NodeConfig},
+    messages::{Message, SignedMessage},
+};
+
+pub struct SignOutput {}
+
+/// Signs a consensus message as a node would, printing the signed message. The sign bytes and
+/// their hash are printed to stderr, to compare with another node's.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct SignArgs {
+    #[clap(long, help = "The account keystore, or a plaintext account file, to sign with")]
+    pub account: PathBuf,
+    #[clap(
+        long,
+        help = "Read the passphrase from a file, instead of TENDERMINT_PASSPHRASE or a prompt"
+    )]
+    pub passphrase_file: Option<PathBuf>,
+    #[clap(long, help = "The message to sign, as JSON, e.g. {\"Prevote\":{...}}")]
+    pub message: PathBuf,
+    #[clap(long, help = "The chain the message is signed for [default: the chain of the home]")]
+    pub chain_id: Option<String>,
+    #[clap(long, env = HOME_ENV, help = "The node home to take the chain from [default: ~/.tendermint]")]
+    pub home: Option<PathBuf>,
+}
+
+impl SignArgs {
+    fn sign(&self) -> Result<SignedMessage> {
+        let chain_id = chain_id(&self.chain_id, &self.home)?;
+        let message: Message = read_json(&self.message)?;
+        let keypair = load_account(&self.account, self.passphrase_file.as_deref())?;
+        let signed = SignedMessage::new(message, &chain_id, &keypair);
+        print_sign_bytes(&signed, &chain_id);
+        Ok(signed)
+    }
+}
+
+impl CmdSync for SignArgs {
+    type Output = Result<SignOutput>;
+
+    fn run(self) -> Self::Output {
+        let signed = self.sign()?;
+        println!("{}", serde_json::to_string_pretty(&signed).unwrap());
+        Ok(SignOutput {})
+    }
+}
+
+pub struct VerifyOutput {}
+
+/// Checks the signature of a signed consensus message, printing its sign bytes and hashes.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct VerifyArgs {
+    #[clap(help = "The signed message, as JSON")]
+    pub signed: PathBuf,
+    #[clap(
+        long,
+        help = "The chain the message should be signed for [default: the chain of the home]"
+    )]
+    pub chain_id: Option<String>,
+    #[clap(long, env = HOME_ENV, help = "The node home to take the chain from [default: ~/.tendermint]")]
+    pub home: Option<PathBuf>,
+}
+
+impl VerifyArgs {
+    fn verify(&self) -> Result<()> {
+        let chain_id = chain_id(&self.chain_id, &self.home)?;
+        let signed: SignedMessage = read_json(&self.signed)?;
+        print_sign_bytes(&signed, &chain_id);
+        eprintln!("Sender: {} ({})", signed.sender, signed.sender.address());
+        if !signed.verify(&chain_id) {
+            return Err(CliError::new("Signature is invalid", exit_code::DATA_ERR));
+        }
+        Ok(())
+    }
+}
+
+impl CmdSync for VerifyArgs {
+    type Output = Result<VerifyOutput>;
+
+    fn run(self) -> Self::Output {
+        self.verify()?;
+        println!("Signature is valid");
+        Ok(VerifyOutput {})
+    }
+}
+
+/// The chain id given, or else the chain of the genesis in the node home.
+fn chain_id(chain_id: &Option<String>, home: &Option<PathBuf>) -> Result<String> {
+    if let Some(chain_id) = chain_id {
+        return Ok(chain_id.clone());
+    }
+    let home = home.clone().unwrap_or_else(default_home);
+    let config = NodeConfig::load(&home).context("Failed to load node config")?;
+    let genesis = parse_config(home.join(&config.genesis))
+        .context("Failed to load genesis, give --chain-id instead")?;
+    Ok(genesis.chain_id)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let json = std::fs::read_to_string(path).context("Failed to read message")?;
+    serde_json::from_str(&json).map_err(|err| {
+        CliError::new(format!("Malformed message {}: {}", path.display(), err), exit_code::DATA_ERR)
+    })
+}
+
+/// Prints the bytes a message is signed over, the Keccak256 hash secp256k1 keys sign, and the
+/// hash identifying the signed message in gossip.
+fn print_sign_bytes(signed: &SignedMessage, chain_id: &str) {
+    let sign_bytes = signed.body.sign_bytes(chain_id);
+    eprintln!("Sign bytes: {}", String::from_utf8_lossy(&sign_bytes));
+    eprintln!("Sign bytes hash: {}", hex::encode(Keccak256::digest(&sign_bytes)));
+    eprintln!("Message hash: {}", hex::encode(signed.hash()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::init::write_home;
+    use tendermint::{
+        config::{AccountConfig, TendermintConfig, ValidatorInfo},
+        crypto::{KeyType, Keypair},
+        keystore::{Keystore, ScryptParams},
+    };
+
+    #[test]
+    fn test_sign_and_verify_with_the_home_chain() {
+        let dir = std::env::temp_dir().join(format!("tendermint-sign-{}", std::process::id()));
+        let home = dir.join("node");
+        let keypair = Keypair::generate(KeyType::Ed25519);
+        let validator =
+            ValidatorInfo::new(keypair.get_public_key(), "127.0.0.1:3030".parse().unwrap());
+        let genesis = TendermintConfig::new("home-chain".to_string(), vec![validator]);
+        let keystore = Keystore::encrypt(&keypair, "hunter2", ScryptParams::light());
+        write_home(&home, &NodeConfig::default(), &genesis, &keystore).unwrap();
+
+        let account = dir.join("account.json");
+        let datum = AccountConfig {
+            key_type: keypair.key_type(),
+            pubkey: keypair.get_public_key().to_string(),
+            privkey: keypair.get_secret_key(),
+        };
+        std::fs::write(&account, serde_json::to_string(&datum).unwrap()).unwrap();
+        let message = dir.join("message.json");
+        let prevote = Message::Prevote { height: 1, round: 0, value: Some("v".to_string()) };
+        std::fs::write(&message, serde_json::to_string(&prevote).unwrap()).unwrap();
+
+        // Without --chain-id, the message is signed for the home's chain.
+        let signed = SignArgs {
+            account,
+            passphrase_file: None,
+            message,
+            chain_id: None,
+            home: Some(home.clone()),
+        }
+        .sign()
+        .unwrap();
+        assert!(signed.verify("home-chain"));
+
+        let path = dir.join("signed.json");
+        let verify = |chain_id: Option<&str>| {
+            VerifyArgs {
+                signed: path.clone(),
+                chain_id: chain_id.map(str::to_string),
+                home: Some(home.clone()),
+            }
+            .verify()
+        };
+        std::fs::write(&path, serde_json::to_string(&signed).unwrap()).unwrap();
+        verify(None).unwrap();
+        assert_eq!(verify(Some("other-chain")).unwrap_err().exit_code(), exit_code::DATA_ERR);
+
+        // A signature over another value doesn't verify.
+        let mut tampered = signed;
+        tampered.body = Message::Prevote { height: 1, round: 0, value: Some("w".to_string()) };
+        std::fs::write(&path, serde_json::to_string(&tampered).unwrap()).unwrap();
+        assert_eq!(verify(None).unwrap_err().exit_code(), exit_code::DATA_ERR);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    cmd::init::write_home,
+    utils::{exit_code, read_passphrase, CliError, CmdSync, Context, Result, PASSPHRASE_ENV},
+};
+use clap::Parser;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+use tendermint::{
+    config::{NodeConfig, TendermintConfig, ValidatorInfo},
+    crypto::{KeyType, Keypair},
+    keystore::{Keystore, ScryptParams},
+    pex::PexAddr,
+};
+
+pub struct TestnetOutput {}
+
+/// Generates the home directories of a local network of validators, sharing a genesis and dialing
+/// each other on startup.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct TestnetArgs {
+    #[clap(long, default_value = "4", help = "The number of validators")]
+    pub validators: usize,
+    #[clap(long, default_value = "testnet", help = "The directory the homes are created in")]
+    pub output_dir: PathBuf,
+    #[clap(long, default_value = "tendermint-testnet", help = "The chain id")]
+    pub chain_id: String,
+    #[clap(
+        long,
+        default_value = "secp256k1",
+        help = "Key type of the validators: secp256k1, ed25519, or bls12381 with the bls feature"
+    )]
+    pub key_type: KeyType,
+    #[clap(long, default_value = "127.0.0.1", help = "The address the validators listen on")]
+    pub host: IpAddr,
+    #[clap(
+        long,
+        default_value = "3030",
+        help = "The port of the first validator. The others listen on the ports after it"
+    )]
+    pub base_port: u16,
+    #[clap(
+        long,
+        help = "Read the passphrase of every keystore from a file, instead of \
+                TENDERMINT_PASSPHRASE or a prompt"
+    )]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+impl CmdSync for TestnetArgs {
+    type Output = Result<TestnetOutput>;
+
+    fn run(self) -> Self::Output {
+        let keypairs: Vec<Keypair> =
+            (0..self.validators).map(|_| Keypair::generate(self.key_type)).collect();
+        let peers = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| {
+                let port = u16::try_from(self.base_port as usize + i).map_err(|_| {
+                    CliError::new("Validator ports run past 65535", exit_code::DATA_ERR)
+                })?;
+                Ok(PexAddr {
+                    pubkey: keypair.get_public_key(),
+                    addr: SocketAddr::new(self.host, port),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let validators = peers.iter().zip(&keypairs).map(|(peer, keypair)| {
+            ValidatorInfo::new(peer.pubkey, peer.addr).with_proof_of_possession(keypair)
+        });
+        let genesis = TendermintConfig::new(self.chain_id, validators.collect());
+        genesis.validate().context("Invalid genesis")?;
+        if self.validators < 4 {
+            tracing::warn!(
+                validators = self.validators,
+                "A network of fewer than 4 validators stops if any of them fails"
+            );
+        }
+
+        let passphrase = read_passphrase("Passphrase: ", self.passphrase_file.as_deref(), true)
+            .context("Failed to read passphrase")?;
+        for (i, keypair) in keypairs.iter().enumerate() {
+            let mut config = NodeConfig::default();
+            config.p2p.listen_addr = peers[i].addr;
+            config.p2p.seeds =
+                peers.iter().filter(|peer| peer.pubkey != peers[i].pubkey).copied().collect();
+            // The keys only guard a local network, so are quick to decrypt.
+            let keystore = Keystore::encrypt(keypair, &passphrase, ScryptParams::light());
+            let home = self.output_dir.join(format!("node{}", i));
+            write_home(&home, &config, &genesis, &keystore)?;
+            println!("Validator {}: {} at {}", i, keypair.get_public_key(), peers[i].addr);
+        }
+
+        println!("Start each validator, with the passphrase in {}, with:", PASSPHRASE_ENV);
+        for i in 0..self.validators {
+            println!(
+                "  tendermint node --home {}",
+                self.output_dir.join(format!("node{}", i)).display()
+            );
+        }
+        Ok(TestnetOutput {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tendermint::{config::parse_config, params::quorum_power};
+
+    #[test]
+    fn test_testnet_quorums_overlap() {
+        let dir = std::env::temp_dir().join(format!("tendermint-testnet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let passphrase_file = dir.join("passphrase");
+        std::fs::write(&passphrase_file, "hunter2").unwrap();
+        let args = |validators, output_dir| TestnetArgs {
+            validators,
+            output_dir,
+            chain_id: "test-chain".to_string(),
+            key_type: KeyType::Ed25519,
+            host: "127.0.0.1".parse().unwrap(),
+            base_port: 3030,
+            passphrase_file: Some(passphrase_file.clone()),
+        };
+
+        let err = args(0, dir.join("empty")).run().err().unwrap();
+        assert_eq!(err.exit_code(), exit_code::CONFIG);
+
+        for validators in [1, 2, 7] {
+            let output_dir = dir.join(validators.to_string());
+            args(validators, output_dir.clone()).run().unwrap();
+            for i in 0..validators {
+                let home = output_dir.join(format!("node{}", i));
+                let config = NodeConfig::load(&home).unwrap();
+                let genesis = parse_config(home.join(&config.genesis)).unwrap();
+                assert_eq!(genesis.validators.len(), validators);
+                // Any two quorums share more than a third of the power, so two sets of validators
+                // can't decide different values unless a third of the power is faulty.
+                let total: u64 = genesis.voting_powers().iter().sum();
+                assert!(2 * quorum_power(total) - total > total / 3);
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+//! A reference remote signer. It holds a secp256k1 validator key outside of the node, and signs
+//! the node's proposals and votes with double-sign protection.
+#[allow(dead_code)]
+mod utils;
+
+use crate::utils::{exit_code, init_logging, load_account, CliError, Context, Result};
+use clap::Parser;
+use std::{net::SocketAddr, path::PathBuf};
+use tendermint::{
+    config::{LogConfig, LogFormat},
+    crypto::{Keypair, PublicKey},
+    privval::{SignerServer, SignerService},
+};
+
+#[derive(Debug, Parser)]
+#[clap(name = "tendermint-signer", about = "Signs proposals and votes for a validator")]
+pub struct SignerArgs {
+    // validator keystore, or a plaintext account file.
+    #[clap(long)]
+    account: PathBuf,
+
+    // file holding the keystore passphrase, instead of TENDERMINT_PASSPHRASE or a prompt.
+    #[clap(long)]
+    passphrase_file: Option<PathBuf>,
+
+    // the chain to sign for.
+    #[clap(long)]
+    chain_id: String,
+
+    // address nodes connect to.
+    #[clap(long, default_value = "127.0.0.1:26659")]
+    listen: SocketAddr,
+
+    // where the last signed height, round and step are kept.
+    #[clap(long, default_value = "sign_state.json")]
+    state_file: PathBuf,
+
+    // identity keys of the nodes allowed to connect. Anyone who can connect can have votes
+    // signed, so at least one is required.
+    #[clap(long, use_value_delimiter = true, required = true)]
+    allow: Vec<PublicKey>,
+
+    // lowest level logged, optionally per module.
+    #[clap(long, env = "TENDERMINT_LOG_LEVEL", default_value = "info")]
+    log_level: String,
+
+    // format of log lines: text, or json for log pipelines.
+    #[clap(long, env = "TENDERMINT_LOG_FORMAT", default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run(SignerArgs::parse()).await {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+async fn run(args: SignerArgs) -> Result<()> {
+    init_logging(&LogConfig { level: args.log_level.clone(), format: args.log_format })?;
+    let keypair = load_account(&args.account, args.passphrase_file.as_deref())?;
+    let Keypair::Secp256k1(keypair) = keypair else {
+        return Err(CliError::new(
+            format!("Unsupported key type {}, the signer holds secp256k1 keys", keypair.key_type()),
+            exit_code::DATA_ERR,
+        ));
+    };
+
+    let service = SignerService::new(keypair, args.chain_id, Some(args.state_file))
+        .context("Failed to read sign state")?;
+    let validator = service.public_key();
+    let server =
+        SignerServer::bind(args.listen, service, args.allow).await.context("Failed to listen")?;
+    let addr = server.local_addr().unwrap_or(args.listen);
+    tracing::info!(%validator, %addr, "Signing");
+    server.run().await.context("Signer failed")
+}
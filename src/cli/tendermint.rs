@@ -1,9 +1,17 @@
 pub mod cmd;
 mod utils;
 
-use crate::utils::{CmdAsync, CmdSync};
+use crate::utils::{init_logging, CmdAsync, CmdSync};
 use clap::{Parser, Subcommand};
-use cmd::{accounts::AccountsArgs, network::NetworkArgs, node::NodeArgs};
+use cmd::{
+    accounts::AccountsArgs,
+    init::InitArgs,
+    network::NetworkArgs,
+    node::NodeArgs,
+    sign::{SignArgs, VerifyArgs},
+    testnet::TestnetArgs,
+};
+use tendermint::config::LogConfig;
 
 #[derive(Debug, Parser)]
 #[clap(name = "tendermint")]
@@ -18,23 +26,32 @@ pub enum Subcommands {
     Node(NodeArgs),
     Accounts(AccountsArgs),
     Network(NetworkArgs),
+    Init(InitArgs),
+    Testnet(TestnetArgs),
+    Sign(SignArgs),
+    Verify(VerifyArgs),
 }
 
 #[tokio::main]
 async fn main() {
     let opts = Opts::parse();
-    match opts.sub {
-        Subcommands::Node(cmd) => {
-            cmd.run().await.unwrap();
-        }
-        Subcommands::Accounts(cmd) => {
-            cmd.run().unwrap();
-        }
-        Subcommands::Network(cmd) => {
-            cmd.run().unwrap();
-        }
+    // Nodes log as their config says. Other commands print their output, and only log what the
+    // library warns of.
+    if !matches!(opts.sub, Subcommands::Node(_)) {
+        let config = LogConfig { level: "warn".to_string(), ..LogConfig::default() };
+        init_logging(&config).expect("the log config is valid");
+    }
+    let result = match opts.sub {
+        Subcommands::Node(cmd) => cmd.run().await.map(|_| ()),
+        Subcommands::Accounts(cmd) => cmd.run().map(|_| ()),
+        Subcommands::Network(cmd) => cmd.run().map(|_| ()),
+        Subcommands::Init(cmd) => cmd.run().map(|_| ()),
+        Subcommands::Testnet(cmd) => cmd.run().map(|_| ()),
+        Subcommands::Sign(cmd) => cmd.run().map(|_| ()),
+        Subcommands::Verify(cmd) => cmd.run().map(|_| ()),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
     }
-
-    // TODO: consider returning Result<T,E> for error codes.
-    // Ok(())
 }
@@ -1,3 +1,13 @@
+use std::path::{Path, PathBuf};
+use tendermint::{
+    addrbook,
+    config::{self, LogConfig, LogFormat},
+    crypto::Keypair,
+    decision_log,
+    keystore::{self, AccountFile},
+    Error,
+};
+
 /// Common trait for all cli commands
 pub trait CmdSync: clap::Parser + Sized {
     type Output;
@@ -10,3 +20,171 @@ pub trait CmdAsync: clap::Parser + Sized {
 
     async fn run(self) -> Self::Output;
 }
+
+/// The environment variable a node's home directory may be given in.
+pub const HOME_ENV: &str = "TENDERMINT_HOME";
+
+/// The home directory of a node when none is given: `~/.tendermint`.
+pub fn default_home() -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    home.join(".tendermint")
+}
+
+/// The environment variable a keystore passphrase may be given in, for unattended nodes.
+pub const PASSPHRASE_ENV: &str = "TENDERMINT_PASSPHRASE";
+
+/// Reads a keystore passphrase from `file` if one is given, then from `PASSPHRASE_ENV`, and
+/// otherwise prompts for it. With `confirm`, a prompted passphrase must be entered twice.
+pub fn read_passphrase(
+    prompt: &str,
+    file: Option<&Path>,
+    confirm: bool,
+) -> std::io::Result<String> {
+    if let Some(file) = file {
+        let passphrase = std::fs::read_to_string(file)?;
+        return Ok(passphrase.trim_end_matches(['\n', '\r']).to_string());
+    }
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    prompt_passphrase(prompt, confirm)
+}
+
+/// Prompts for a passphrase on the terminal. With `confirm`, it must be entered twice.
+pub fn prompt_passphrase(prompt: &str, confirm: bool) -> std::io::Result<String> {
+    let passphrase = rpassword::prompt_password(prompt)?;
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "passphrases differ"));
+    }
+    Ok(passphrase)
+}
+
+/// Logs to stderr, keeping logs apart from a command's output, at the level and in the format
+/// configured.
+pub fn init_logging(config: &LogConfig) -> Result<()> {
+    let filter = tracing_subscriber::EnvFilter::try_new(&config.level).map_err(|err| {
+        CliError::new(format!("Invalid log level {}: {}", config.level, err), exit_code::CONFIG)
+    })?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    match config.format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+/// Loads an account's keypair, decrypting it if it's in a keystore. The passphrase is read as
+/// by `read_passphrase`.
+pub fn load_account(path: &Path, passphrase_file: Option<&Path>) -> Result<Keypair> {
+    match AccountFile::load(path).context("Failed to read account")? {
+        AccountFile::Encrypted(keystore) => {
+            let passphrase = read_passphrase("Passphrase: ", passphrase_file, false)
+                .context("Failed to read passphrase")?;
+            keystore.decrypt(&passphrase).context("Failed to decrypt account")
+        }
+        AccountFile::Plaintext(account) => {
+            tracing::warn!(
+                path = %path.display(),
+                "The account's private key is stored unencrypted, encrypt it with \
+                 `tendermint accounts import <name> <path>`"
+            );
+            Keypair::new_from_privatekey(account.key_type, &account.privkey)
+                .context("Invalid private key")
+        }
+    }
+}
+
+/// Exit codes, following BSD's sysexits.h so scripts and supervisors can tell failures apart.
+pub mod exit_code {
+    /// An input file, key or message was malformed.
+    pub const DATA_ERR: i32 = 65;
+    /// An input file doesn't exist.
+    pub const NO_INPUT: i32 = 66;
+    /// A peer, signer or server couldn't be reached.
+    pub const UNAVAILABLE: i32 = 69;
+    /// An internal error.
+    pub const SOFTWARE: i32 = 70;
+    /// An output file can't be created.
+    pub const CANT_CREAT: i32 = 73;
+    /// Reading or writing a file failed.
+    pub const IO_ERR: i32 = 74;
+    /// Permission was denied, or a passphrase was wrong.
+    pub const NO_PERM: i32 = 77;
+    /// The config file is malformed.
+    pub const CONFIG: i32 = 78;
+}
+
+/// An error that stops a command, with what the command was doing when it failed, and the code
+/// the process exits with.
+#[derive(Debug)]
+pub struct CliError {
+    message: String,
+    code: i32,
+}
+
+pub type Result<T, E = CliError> = std::result::Result<T, E>;
+
+impl CliError {
+    pub fn new(message: impl std::fmt::Display, code: i32) -> Self {
+        CliError { message: message.to_string(), code }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.code
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Attaches what a command was doing to a library error, and picks the exit code for it.
+pub trait Context<T> {
+    fn context(self, context: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: &str) -> Result<T> {
+        self.map_err(|err| {
+            let err = err.into();
+            CliError::new(format!("{}: {}", context, err), error_exit_code(&err))
+        })
+    }
+}
+
+fn error_exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Io(err) |
+        Error::Config(config::Error::Io(_, err)) |
+        Error::Config(config::Error::Write(_, err)) |
+        Error::Keystore(keystore::Error::Io(err)) => io_exit_code(err),
+        Error::Config(_) => exit_code::CONFIG,
+        Error::Keystore(keystore::Error::WrongPassphrase) => exit_code::NO_PERM,
+        Error::Keystore(keystore::Error::NotFound(_)) => exit_code::NO_INPUT,
+        Error::Keystore(keystore::Error::Exists(_)) => exit_code::CANT_CREAT,
+        Error::Keystore(_) | Error::Crypto(_) | Error::Codec(_) | Error::Vote(_) => {
+            exit_code::DATA_ERR
+        }
+        Error::AddressBook(addrbook::Error::Io(err)) => io_exit_code(err),
+        Error::AddressBook(_) => exit_code::DATA_ERR,
+        Error::DecisionLog(decision_log::Error::Io(err)) => io_exit_code(err),
+        Error::DecisionLog(_) => exit_code::DATA_ERR,
+        Error::Metrics(_) => exit_code::SOFTWARE,
+        Error::Connection(_) |
+        Error::P2p(_) |
+        Error::Signer(_) |
+        Error::RpcServer(_) |
+        Error::RpcClient(_) => exit_code::UNAVAILABLE,
+    }
+}
+
+fn io_exit_code(err: &std::io::Error) -> i32 {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => exit_code::NO_INPUT,
+        std::io::ErrorKind::PermissionDenied => exit_code::NO_PERM,
+        std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => exit_code::DATA_ERR,
+        _ => exit_code::IO_ERR,
+    }
+}
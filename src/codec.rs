@@ -0,0 +1,518 @@
+use prost::Message as _;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{net::SocketAddr, str::FromStr};
+
+use crate::{
+    crypto::{KeyType, PublicKey, Signature},
+    messages::{Message, PeerMessage, SignedMessage, Step, VoteSetBits},
+    pex::PexAddr,
+    vote_set::BitArray,
+};
+
+/// The first byte of a frame in the binary encoding. JSON frames always begin with a printable
+/// character, so a receiver can tell the encodings apart and accept either.
+pub const PROTO_V1: u8 = 0x01;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("empty frame")]
+    Empty,
+    #[error("unknown wire encoding version {0:#04x}")]
+    UnknownVersion(u8),
+    #[error("malformed json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("malformed protobuf: {0}")]
+    Proto(#[from] prost::DecodeError),
+    #[error("invalid {0}")]
+    Invalid(&'static str),
+}
+
+/// How messages are encoded on a transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Human-readable JSON, useful for debugging.
+    Json,
+    /// Compact protobuf, laid out after Tendermint's `types.proto` where the models agree.
+    #[default]
+    Proto,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Encoding::Json),
+            "proto" => Ok(Encoding::Proto),
+            _ => Err(format!("unknown encoding {}, expected json or proto", s)),
+        }
+    }
+}
+
+/// A message which can be sent over a transport in either encoding.
+pub trait WireMessage: Serialize + DeserializeOwned + Sized {
+    type Proto: prost::Message + Default;
+
+    fn to_proto(&self) -> Self::Proto;
+    fn from_proto(proto: Self::Proto) -> Result<Self, Error>;
+}
+
+/// Encodes a message in the given encoding.
+pub fn encode<T: WireMessage>(message: &T, encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Json => serde_json::to_vec(message).expect("message serializes"),
+        Encoding::Proto => {
+            let proto = message.to_proto();
+            let mut bytes = Vec::with_capacity(1 + proto.encoded_len());
+            bytes.push(PROTO_V1);
+            proto.encode(&mut bytes).expect("vec has capacity");
+            bytes
+        }
+    }
+}
+
+/// Decodes a message in either encoding.
+pub fn decode<T: WireMessage>(bytes: &[u8]) -> Result<T, Error> {
+    match bytes.first() {
+        None => Err(Error::Empty),
+        Some(&PROTO_V1) => T::from_proto(T::Proto::decode(&bytes[1..])?),
+        Some(byte) if byte.is_ascii_graphic() || byte.is_ascii_whitespace() => {
+            Ok(serde_json::from_slice(bytes)?)
+        }
+        Some(&byte) => Err(Error::UnknownVersion(byte)),
+    }
+}
+
+/// The protobuf messages. Field numbers follow Tendermint's `tendermint.types` and
+/// `tendermint.consensus` packages where the messages correspond.
+pub mod proto {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum SignedMsgType {
+        Unknown = 0,
+        Prevote = 1,
+        Precommit = 2,
+        Proposal = 32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum RoundStepType {
+        Unknown = 0,
+        Propose = 3,
+        Prevote = 4,
+        Precommit = 6,
+    }
+
+    /// A public key, tagged with its signature scheme. BLS12-381 keys are part of the schema
+    /// whether or not the `bls` feature is enabled, but are rejected without it.
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PublicKey {
+        #[prost(oneof = "PublicKeySum", tags = "1, 2, 3")]
+        pub sum: Option<PublicKeySum>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum PublicKeySum {
+        #[prost(bytes, tag = "1")]
+        Ed25519(Vec<u8>),
+        #[prost(bytes, tag = "2")]
+        Secp256k1(Vec<u8>),
+        #[prost(bytes, tag = "3")]
+        Bls12381(Vec<u8>),
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Proposal {
+        #[prost(enumeration = "SignedMsgType", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint64, tag = "2")]
+        pub height: u64,
+        #[prost(uint64, tag = "3")]
+        pub round: u64,
+        #[prost(string, tag = "5")]
+        pub value: String,
+        #[prost(bytes = "vec", tag = "7")]
+        pub signature: Vec<u8>,
+        #[prost(message, optional, tag = "8")]
+        pub proposer_pub_key: Option<PublicKey>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Vote {
+        #[prost(enumeration = "SignedMsgType", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint64, tag = "2")]
+        pub height: u64,
+        #[prost(uint64, tag = "3")]
+        pub round: u64,
+        #[prost(string, optional, tag = "4")]
+        pub value: Option<String>,
+        #[prost(message, optional, tag = "6")]
+        pub validator_pub_key: Option<PublicKey>,
+        #[prost(bytes = "vec", tag = "8")]
+        pub signature: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct BitArray {
+        #[prost(uint64, tag = "1")]
+        pub bits: u64,
+        #[prost(uint64, repeated, tag = "2")]
+        pub elems: Vec<u64>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct VoteSetBits {
+        #[prost(uint64, tag = "1")]
+        pub height: u64,
+        #[prost(uint64, tag = "2")]
+        pub round: u64,
+        #[prost(enumeration = "RoundStepType", tag = "3")]
+        pub step: i32,
+        #[prost(message, optional, tag = "5")]
+        pub prevotes: Option<BitArray>,
+        #[prost(message, optional, tag = "6")]
+        pub precommits: Option<BitArray>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct NetAddress {
+        #[prost(message, optional, tag = "1")]
+        pub pub_key: Option<PublicKey>,
+        #[prost(string, tag = "2")]
+        pub ip: String,
+        #[prost(uint32, tag = "3")]
+        pub port: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PexRequest {}
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PexAddrs {
+        #[prost(message, repeated, tag = "1")]
+        pub addrs: Vec<NetAddress>,
+    }
+
+    /// A signed consensus message. Its tags match `PeerMessage`, so an encoded `SignedMessage`
+    /// is also a valid `PeerMessage`.
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SignedMessage {
+        #[prost(oneof = "SignedMessageSum", tags = "3, 6")]
+        pub sum: Option<SignedMessageSum>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum SignedMessageSum {
+        #[prost(message, tag = "3")]
+        Proposal(Proposal),
+        #[prost(message, tag = "6")]
+        Vote(Vote),
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PeerMessage {
+        #[prost(oneof = "PeerMessageSum", tags = "3, 6, 9, 10, 11")]
+        pub sum: Option<PeerMessageSum>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum PeerMessageSum {
+        #[prost(message, tag = "3")]
+        Proposal(Proposal),
+        #[prost(message, tag = "6")]
+        Vote(Vote),
+        #[prost(message, tag = "9")]
+        VoteSetBits(VoteSetBits),
+        #[prost(message, tag = "10")]
+        PexRequest(PexRequest),
+        #[prost(message, tag = "11")]
+        PexAddrs(PexAddrs),
+    }
+}
+
+fn pubkey_to_proto(pubkey: &PublicKey) -> Option<proto::PublicKey> {
+    let bytes = pubkey.to_bytes();
+    let sum = match pubkey.key_type() {
+        KeyType::Secp256k1 => proto::PublicKeySum::Secp256k1(bytes),
+        KeyType::Ed25519 => proto::PublicKeySum::Ed25519(bytes),
+        #[cfg(feature = "bls")]
+        KeyType::Bls12381 => proto::PublicKeySum::Bls12381(bytes),
+    };
+    Some(proto::PublicKey { sum: Some(sum) })
+}
+
+fn pubkey_from_proto(pubkey: Option<proto::PublicKey>) -> Result<PublicKey, Error> {
+    let (key_type, bytes) = match pubkey.and_then(|pubkey| pubkey.sum) {
+        Some(proto::PublicKeySum::Secp256k1(bytes)) => (KeyType::Secp256k1, bytes),
+        Some(proto::PublicKeySum::Ed25519(bytes)) => (KeyType::Ed25519, bytes),
+        #[cfg(feature = "bls")]
+        Some(proto::PublicKeySum::Bls12381(bytes)) => (KeyType::Bls12381, bytes),
+        _ => return Err(Error::Invalid("public key")),
+    };
+    PublicKey::from_bytes(key_type, &bytes).map_err(|_| Error::Invalid("public key"))
+}
+
+/// Signatures are encoded in their compact form, in the scheme of the signer's key.
+fn signature_from_proto(pubkey: &PublicKey, bytes: &[u8]) -> Result<Signature, Error> {
+    Signature::from_compact(pubkey.key_type(), bytes).map_err(|_| Error::Invalid("signature"))
+}
+
+impl WireMessage for SignedMessage {
+    type Proto = proto::SignedMessage;
+
+    fn to_proto(&self) -> Self::Proto {
+        let signature = self.signature.to_compact();
+        let pub_key = pubkey_to_proto(&self.sender);
+        let sum = match &self.body {
+            Message::Propose { height, round, value } => {
+                proto::SignedMessageSum::Proposal(proto::Proposal {
+                    r#type: proto::SignedMsgType::Proposal as i32,
+                    height: *height,
+                    round: *round,
+                    value: value.clone(),
+                    signature,
+                    proposer_pub_key: pub_key,
+                })
+            }
+            Message::Prevote { height, round, value } |
+            Message::Precommit { height, round, value } => {
+                let r#type = match self.body {
+                    Message::Prevote { .. } => proto::SignedMsgType::Prevote,
+                    _ => proto::SignedMsgType::Precommit,
+                };
+                proto::SignedMessageSum::Vote(proto::Vote {
+                    r#type: r#type as i32,
+                    height: *height,
+                    round: *round,
+                    value: value.clone(),
+                    validator_pub_key: pub_key,
+                    signature,
+                })
+            }
+        };
+        proto::SignedMessage { sum: Some(sum) }
+    }
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, Error> {
+        match proto.sum.ok_or(Error::Invalid("empty message"))? {
+            proto::SignedMessageSum::Proposal(proposal) => {
+                if proposal.r#type != proto::SignedMsgType::Proposal as i32 {
+                    return Err(Error::Invalid("proposal type"));
+                }
+                let sender = pubkey_from_proto(proposal.proposer_pub_key)?;
+                Ok(SignedMessage {
+                    body: Message::Propose {
+                        height: proposal.height,
+                        round: proposal.round,
+                        value: proposal.value,
+                    },
+                    signature: signature_from_proto(&sender, &proposal.signature)?,
+                    sender,
+                })
+            }
+            proto::SignedMessageSum::Vote(vote) => {
+                let (height, round, value) = (vote.height, vote.round, vote.value);
+                let body = match proto::SignedMsgType::try_from(vote.r#type) {
+                    Ok(proto::SignedMsgType::Prevote) => Message::Prevote { height, round, value },
+                    Ok(proto::SignedMsgType::Precommit) => {
+                        Message::Precommit { height, round, value }
+                    }
+                    _ => return Err(Error::Invalid("vote type")),
+                };
+                let sender = pubkey_from_proto(vote.validator_pub_key)?;
+                Ok(SignedMessage {
+                    body,
+                    signature: signature_from_proto(&sender, &vote.signature)?,
+                    sender,
+                })
+            }
+        }
+    }
+}
+
+fn bit_array_to_proto(bits: &BitArray) -> proto::BitArray {
+    proto::BitArray { bits: bits.len() as u64, elems: bits.words().to_vec() }
+}
+
+fn bit_array_from_proto(bits: Option<proto::BitArray>) -> Result<BitArray, Error> {
+    let bits = bits.unwrap_or_default();
+    BitArray::from_words(bits.bits as usize, bits.elems).ok_or(Error::Invalid("bit array"))
+}
+
+impl WireMessage for PeerMessage {
+    type Proto = proto::PeerMessage;
+
+    fn to_proto(&self) -> Self::Proto {
+        let sum = match self {
+            PeerMessage::Consensus(message) => match message.to_proto().sum {
+                Some(proto::SignedMessageSum::Proposal(p)) => proto::PeerMessageSum::Proposal(p),
+                Some(proto::SignedMessageSum::Vote(v)) => proto::PeerMessageSum::Vote(v),
+                None => unreachable!("signed messages always have a body"),
+            },
+            PeerMessage::VoteSetBits(bits) => {
+                let step = match bits.step {
+                    Step::Propose => proto::RoundStepType::Propose,
+                    Step::Prevote => proto::RoundStepType::Prevote,
+                    Step::Precommit => proto::RoundStepType::Precommit,
+                };
+                proto::PeerMessageSum::VoteSetBits(proto::VoteSetBits {
+                    height: bits.height,
+                    round: bits.round,
+                    step: step as i32,
+                    prevotes: Some(bit_array_to_proto(&bits.prevotes)),
+                    precommits: Some(bit_array_to_proto(&bits.precommits)),
+                })
+            }
+            PeerMessage::PexRequest => proto::PeerMessageSum::PexRequest(proto::PexRequest {}),
+            PeerMessage::PexAddrs(addrs) => proto::PeerMessageSum::PexAddrs(proto::PexAddrs {
+                addrs: addrs
+                    .iter()
+                    .map(|addr| proto::NetAddress {
+                        pub_key: pubkey_to_proto(&addr.pubkey),
+                        ip: addr.addr.ip().to_string(),
+                        port: addr.addr.port() as u32,
+                    })
+                    .collect(),
+            }),
+        };
+        proto::PeerMessage { sum: Some(sum) }
+    }
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, Error> {
+        Ok(match proto.sum.ok_or(Error::Invalid("empty message"))? {
+            proto::PeerMessageSum::Proposal(p) => {
+                PeerMessage::Consensus(SignedMessage::from_proto(proto::SignedMessage {
+                    sum: Some(proto::SignedMessageSum::Proposal(p)),
+                })?)
+            }
+            proto::PeerMessageSum::Vote(v) => {
+                PeerMessage::Consensus(SignedMessage::from_proto(proto::SignedMessage {
+                    sum: Some(proto::SignedMessageSum::Vote(v)),
+                })?)
+            }
+            proto::PeerMessageSum::VoteSetBits(bits) => {
+                let step = match proto::RoundStepType::try_from(bits.step) {
+                    Ok(proto::RoundStepType::Propose) => Step::Propose,
+                    Ok(proto::RoundStepType::Prevote) => Step::Prevote,
+                    Ok(proto::RoundStepType::Precommit) => Step::Precommit,
+                    _ => return Err(Error::Invalid("round step")),
+                };
+                PeerMessage::VoteSetBits(VoteSetBits {
+                    height: bits.height,
+                    round: bits.round,
+                    step,
+                    prevotes: bit_array_from_proto(bits.prevotes)?,
+                    precommits: bit_array_from_proto(bits.precommits)?,
+                })
+            }
+            proto::PeerMessageSum::PexRequest(_) => PeerMessage::PexRequest,
+            proto::PeerMessageSum::PexAddrs(addrs) => PeerMessage::PexAddrs(
+                addrs
+                    .addrs
+                    .into_iter()
+                    .map(|addr| {
+                        let ip = addr.ip.parse().map_err(|_| Error::Invalid("ip address"))?;
+                        let port = u16::try_from(addr.port).map_err(|_| Error::Invalid("port"))?;
+                        Ok(PexAddr {
+                            pubkey: pubkey_from_proto(addr.pub_key)?,
+                            addr: SocketAddr::new(ip, port),
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{ECDSAKeypair, Keypair};
+
+    fn peer_messages(keypair: Keypair) -> Vec<PeerMessage> {
+        let sign = |body| PeerMessage::Consensus(SignedMessage::new(body, "test-chain", &keypair));
+        let mut prevotes = BitArray::new(70);
+        prevotes.set(65, true);
+        vec![
+            sign(Message::Propose { height: 1, round: 2, value: "v".to_string() }),
+            sign(Message::Prevote { height: 1, round: 2, value: Some("v".to_string()) }),
+            sign(Message::Precommit { height: 1, round: 2, value: None }),
+            PeerMessage::VoteSetBits(VoteSetBits {
+                height: 1,
+                round: 2,
+                step: Step::Precommit,
+                prevotes,
+                precommits: BitArray::new(70),
+            }),
+            PeerMessage::PexRequest,
+            PeerMessage::PexAddrs(vec![PexAddr {
+                pubkey: keypair.get_public_key(),
+                addr: "[::1]:26656".parse().unwrap(),
+            }]),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_and_cross_format() {
+        let keypairs = [Keypair::generate(KeyType::Secp256k1), Keypair::generate(KeyType::Ed25519)];
+        for message in keypairs.into_iter().flat_map(peer_messages) {
+            let json = encode(&message, Encoding::Json);
+            let proto = encode(&message, Encoding::Proto);
+            assert!(proto.len() < json.len());
+
+            // Both encodings decode to the same message, which re-encodes to the same bytes.
+            let from_json: PeerMessage = decode(&json).unwrap();
+            let from_proto: PeerMessage = decode(&proto).unwrap();
+            assert_eq!(encode(&from_json, Encoding::Proto), proto);
+            assert_eq!(encode(&from_proto, Encoding::Json), json);
+        }
+    }
+
+    #[test]
+    fn test_signed_message_is_a_peer_message() {
+        let message = SignedMessage::new(
+            Message::Prevote { height: 1, round: 2, value: None },
+            "test-chain",
+            &ECDSAKeypair::new(),
+        );
+        match decode::<PeerMessage>(&encode(&message, Encoding::Proto)).unwrap() {
+            PeerMessage::Consensus(decoded) => {
+                assert_eq!(decoded.hash(), message.hash());
+                assert!(decoded.verify("test-chain"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed() {
+        assert!(matches!(decode::<PeerMessage>(&[]), Err(Error::Empty)));
+        assert!(matches!(decode::<PeerMessage>(&[0x02, 0x00]), Err(Error::UnknownVersion(2))));
+        assert!(matches!(decode::<PeerMessage>(&[PROTO_V1]), Err(Error::Invalid(_))));
+        assert!(matches!(decode::<PeerMessage>(b"{\"Nope\":1}"), Err(Error::Json(_))));
+
+        // Truncated frames are rejected without panicking.
+        for message in peer_messages(Keypair::generate(KeyType::Secp256k1)) {
+            for encoding in [Encoding::Json, Encoding::Proto] {
+                let bytes = encode(&message, encoding);
+                for len in 0..bytes.len() {
+                    let _ = decode::<PeerMessage>(&bytes[..len]);
+                }
+            }
+        }
+
+        // So are signatures which don't parse.
+        let keypair = ECDSAKeypair::new();
+        let message = SignedMessage::new(
+            Message::Prevote { height: 1, round: 2, value: None },
+            "test-chain",
+            &keypair,
+        );
+        let json = String::from_utf8(encode(&message, Encoding::Json)).unwrap();
+        let json = json.replace(&message.signature.to_string(), "secp256k1:30060201");
+        assert!(matches!(decode::<SignedMessage>(json.as_bytes()), Err(Error::Json(_))));
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bls,
+    crypto::{PublicKey, Signature},
+    messages::Message,
+    params::quorum_power,
+    vote_set::{BitArray, VoteSet},
+};
+
+/// Proof that a value was decided: the precommits for the value, aggregated into a single BLS
+/// signature, and a bitmap of the validators whose precommits it contains. This takes the place
+/// of storing and sending a quorum of individual signatures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    /// The height and round of the precommits.
+    pub height: u64,
+    pub round: u64,
+    pub value: String,
+    /// The validators who signed, by index in the validator set.
+    pub signers: BitArray,
+    pub signature: Signature,
+}
+
+impl CommitCertificate {
+    /// Aggregates the precommits for `value` in a round. Precommits by validators without a BLS
+    /// key can't be aggregated, and are left out. Returns None if the rest don't make a quorum of
+    /// the voting power.
+    pub fn aggregate(
+        height: u64,
+        round: u64,
+        value: &str,
+        precommits: &VoteSet,
+    ) -> Option<CommitCertificate> {
+        let expected = Message::Precommit { height, round, value: Some(value.to_string()) };
+        let mut signers = BitArray::new(precommits.bit_array().len());
+        let mut signatures = Vec::new();
+        let mut power = 0;
+        for (index, vote) in precommits.iter() {
+            if vote.body == expected && matches!(vote.signature, Signature::Bls12381(_)) {
+                signers.set(index, true);
+                signatures.push(vote.signature);
+                power += precommits.validator_power(index);
+            }
+        }
+        if power < quorum_power(precommits.total_power()) {
+            return None;
+        }
+        Some(CommitCertificate {
+            height,
+            round,
+            value: value.to_string(),
+            signers,
+            signature: bls::aggregate_signatures(&signatures)?,
+        })
+    }
+
+    /// Verifies the certificate against the validator set and the voting power of each validator,
+    /// by checking the signers have more than two thirds of the power, and the aggregate signature
+    /// against the aggregate of their public keys.
+    pub fn verify(&self, chain_id: &str, validators: &[PublicKey], powers: &[u64]) -> bool {
+        if self.signers.len() != validators.len() || powers.len() != validators.len() {
+            return false;
+        }
+        let power: u64 =
+            powers.iter().enumerate().filter(|(i, _)| self.signers.get(*i)).map(|(_, p)| p).sum();
+        if power < quorum_power(powers.iter().sum()) {
+            return false;
+        }
+        let signers: Vec<PublicKey> = validators
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.signers.get(*index))
+            .map(|(_, validator)| *validator)
+            .collect();
+        let precommit = Message::Precommit {
+            height: self.height,
+            round: self.round,
+            value: Some(self.value.clone()),
+        };
+        bls::verify_aggregate(&signers, &precommit.sign_bytes(chain_id), &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{KeyType, Keypair},
+        messages::SignedMessage,
+        vote_set::HeightVoteSet,
+    };
+
+    #[test]
+    fn test_aggregate_and_verify() {
+        // One validator has a secp256k1 key, so its precommit can't be aggregated.
+        let keypairs: Vec<Keypair> = [KeyType::Bls12381; 6]
+            .into_iter()
+            .chain([KeyType::Secp256k1])
+            .map(Keypair::generate)
+            .collect();
+        let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+        let mut votes = HeightVoteSet::new(1, validators.clone());
+        for (i, keypair) in keypairs.iter().enumerate() {
+            // The fourth validator precommits nil.
+            let value = (i != 3).then(|| "v".to_string());
+            let precommit = Message::Precommit { height: 1, round: 2, value };
+            votes.add_vote(SignedMessage::new(precommit, "test-chain", keypair)).unwrap();
+        }
+
+        let certificate =
+            CommitCertificate::aggregate(1, 2, "v", votes.precommits(2).unwrap()).unwrap();
+        assert_eq!(certificate.signers.count_ones(), 5);
+        assert!(!certificate.signers.get(3) && !certificate.signers.get(6));
+        let powers = vec![1; validators.len()];
+        assert!(certificate.verify("test-chain", &validators, &powers));
+        assert!(!certificate.verify("other-chain", &validators, &powers));
+        // Nor does it if the signers have too little of the power.
+        let mut powers_against = powers.clone();
+        powers_against[6] = 3;
+        assert!(!certificate.verify("test-chain", &validators, &powers_against));
+
+        // The certificate doesn't verify for a value or signers it wasn't made for.
+        let forged = CommitCertificate { value: "w".to_string(), ..certificate.clone() };
+        assert!(!forged.verify("test-chain", &validators, &powers));
+        let mut signers = certificate.signers.clone();
+        signers.set(0, false);
+        signers.set(3, true);
+        let swapped = CommitCertificate { signers, ..certificate };
+        assert!(!swapped.verify("test-chain", &validators, &powers));
+
+        // Without a quorum of BLS precommits for the value, there is no certificate.
+        assert!(CommitCertificate::aggregate(1, 2, "w", votes.precommits(2).unwrap()).is_none());
+    }
+}
@@ -1,32 +1,547 @@
 use serde::{Deserialize, Serialize};
-use std::{net::IpAddr, path::PathBuf};
+use sha3::{Digest, Keccak256};
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    codec::Encoding,
+    crypto::{CryptoError, KeyType, Keypair, PublicKey, Signature},
+    events,
+    gossip::GossipConfig,
+    messages::to_canonical_json,
+    params::ConsensusParams,
+    pex::{PexAddr, PexConfig},
+};
+
+/// The name of the node config file in a node's home directory.
+pub const NODE_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("failed to write {0}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("malformed config {0}: {1}")]
+    Malformed(PathBuf, serde_json::Error),
+    #[error("malformed config {0}: {1}")]
+    MalformedToml(PathBuf, toml::de::Error),
+    #[error("chain id is empty")]
+    EmptyChainId,
+    #[error("validator set is empty")]
+    NoValidators,
+    #[error("invalid validator key {0}: {1}")]
+    InvalidPubkey(String, CryptoError),
+    #[error("validator key {0} appears more than once")]
+    DuplicateKey(PublicKey),
+    #[error("validator {0} has no voting power")]
+    ZeroPower(PublicKey),
+    #[error("total voting power overflows")]
+    PowerOverflow,
+    #[error("validator key {0} has no proof of possession")]
+    MissingProof(PublicKey),
+    #[error("invalid proof of possession for validator key {0}")]
+    InvalidProof(PublicKey),
+    #[error("more than one validator listens on {0}")]
+    AddressClash(SocketAddr),
+    #[error("invalid consensus params: {0}")]
+    InvalidParams(&'static str),
+    #[error("invalid node config: {0}")]
+    InvalidNodeConfig(&'static str),
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidatorInfo {
-    /// The public key of the validator.
+    /// The public key of the validator, as `<key type>:<hex>`. Untagged keys are secp256k1.
     pub pubkey: String,
+    /// The identity key the validator's node connects to peers with, when the validator key is
+    /// held by a remote signer. Defaults to `pubkey`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_pubkey: Option<String>,
+    /// The voting power of the validator. A quorum needs more than two thirds of the total.
+    #[serde(default = "default_power")]
+    pub power: u64,
+    /// For a BLS key, its signature over itself proving the validator holds the secret key, so
+    /// that its precommits can be safely aggregated. Required for BLS keys, and ignored otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_of_possession: Option<Signature>,
     /// The IP address of the validator.
     pub address: IpAddr,
     /// The IP port of the validator.
     pub port: u16,
 }
 
+fn default_power() -> u64 {
+    1
+}
+
+impl ValidatorInfo {
+    /// A validator with a voting power of 1, whose node identifies itself with the validator key.
+    pub fn new(pubkey: PublicKey, addr: SocketAddr) -> Self {
+        ValidatorInfo {
+            pubkey: pubkey.to_string(),
+            node_pubkey: None,
+            power: default_power(),
+            proof_of_possession: None,
+            address: addr.ip(),
+            port: addr.port(),
+        }
+    }
+
+    /// Adds the proof of possession of the validator's key, if it is a BLS key.
+    pub fn with_proof_of_possession(mut self, keypair: &Keypair) -> Self {
+        self.proof_of_possession = keypair.prove_possession();
+        self
+    }
+
+    /// The validator's key.
+    pub fn pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(&self.pubkey)
+    }
+
+    /// The key the validator's node identifies itself to peers with.
+    pub fn node_pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(self.node_pubkey.as_ref().unwrap_or(&self.pubkey))
+    }
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<PublicKey, Error> {
+    PublicKey::from_str(pubkey).map_err(|err| Error::InvalidPubkey(pubkey.to_string(), err))
+}
+
+/// The genesis of a chain, which every node on the chain must agree on.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TendermintConfig {
+    /// The identifier of the chain. Peers on a different chain are rejected.
+    pub chain_id: String,
+    /// When the chain started, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub genesis_time: u64,
+    /// The height consensus starts at.
+    #[serde(default)]
+    pub initial_height: u64,
+    /// The timeouts and limits of consensus.
+    #[serde(default)]
+    pub consensus_params: ConsensusParams,
     /// The set of validators at genesis.
     pub validators: Vec<ValidatorInfo>,
 }
 
+impl TendermintConfig {
+    /// The genesis of a new chain starting now, with the default consensus params.
+    pub fn new(chain_id: String, validators: Vec<ValidatorInfo>) -> Self {
+        let genesis_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        TendermintConfig {
+            chain_id,
+            genesis_time,
+            initial_height: 0,
+            consensus_params: ConsensusParams::default(),
+            validators,
+        }
+    }
+
+    /// Writes the genesis as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).expect("genesis serializes");
+        std::fs::write(path, json).map_err(|err| Error::Write(path.to_path_buf(), err))
+    }
+
+    /// Checks that the genesis describes a chain that can run: a nonempty set of validators with
+    /// valid, distinct keys and addresses, each with some voting power and BLS keys with a proof
+    /// of possession, and usable consensus params.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.chain_id.is_empty() {
+            return Err(Error::EmptyChainId);
+        }
+        if self.validators.is_empty() {
+            return Err(Error::NoValidators);
+        }
+
+        let mut keys = HashSet::new();
+        let mut node_keys = HashSet::new();
+        let mut addrs = HashSet::new();
+        let mut total_power = 0u64;
+        for validator in &self.validators {
+            let pubkey = validator.pubkey()?;
+            if !keys.insert(pubkey) {
+                return Err(Error::DuplicateKey(pubkey));
+            }
+            #[cfg(feature = "bls")]
+            if let PublicKey::Bls12381(bytes) = &pubkey {
+                let proof =
+                    validator.proof_of_possession.as_ref().ok_or(Error::MissingProof(pubkey))?;
+                if !crate::bls::verify_possession(bytes, proof) {
+                    return Err(Error::InvalidProof(pubkey));
+                }
+            }
+            let node_pubkey = validator.node_pubkey()?;
+            if !node_keys.insert(node_pubkey) {
+                return Err(Error::DuplicateKey(node_pubkey));
+            }
+            let addr = SocketAddr::new(validator.address, validator.port);
+            if !addrs.insert(addr) {
+                return Err(Error::AddressClash(addr));
+            }
+            if validator.power == 0 {
+                return Err(Error::ZeroPower(pubkey));
+            }
+            total_power = total_power.checked_add(validator.power).ok_or(Error::PowerOverflow)?;
+        }
+
+        let params = &self.consensus_params;
+        if params.timeout_propose_ms == 0 ||
+            params.timeout_prevote_ms == 0 ||
+            params.timeout_precommit_ms == 0
+        {
+            return Err(Error::InvalidParams("timeouts must be nonzero"));
+        }
+        if params.max_value_size == 0 {
+            return Err(Error::InvalidParams("max_value_size must be nonzero"));
+        }
+        Ok(())
+    }
+
+    /// The validator keys, in genesis order. A validator's index in the set is its process id.
+    pub fn validator_keys(&self) -> Result<Vec<PublicKey>, Error> {
+        self.validators.iter().map(ValidatorInfo::pubkey).collect()
+    }
+
+    /// The voting power of each validator, in genesis order.
+    pub fn voting_powers(&self) -> Vec<u64> {
+        self.validators.iter().map(|validator| validator.power).collect()
+    }
+
+    /// The Keccak256 hash of the genesis in canonical JSON. Nodes only connect to peers with the
+    /// same genesis hash.
+    pub fn hash(&self) -> [u8; 32] {
+        Keccak256::digest(to_canonical_json(self)).into()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountConfig {
+    /// The signature scheme of the account's key. Accounts created before Ed25519 keys were
+    /// supported have none, and are secp256k1.
+    #[serde(default)]
+    pub key_type: KeyType,
     pub pubkey: String,
     pub privkey: String,
 }
 
-pub fn parse_config(config: PathBuf) -> TendermintConfig {
+/// A node's own settings, read from `config.toml` in its home directory. Unlike the genesis, these
+/// may differ from node to node. Relative paths are relative to the home directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// The genesis of the chain the node runs.
+    pub genesis: PathBuf,
+    /// The account keystore, or a plaintext account file, holding the node's key.
+    pub account: PathBuf,
+    /// The directory the node keeps its state in, such as the address book.
+    pub data_dir: PathBuf,
+    /// The remote signer holding the validator key, as ip:port. The account is then only the
+    /// node's identity towards peers and the signer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<SocketAddr>,
+    pub p2p: P2pConfig,
+    pub rpc: RpcConfig,
+    pub log: LogConfig,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            genesis: PathBuf::from("genesis.json"),
+            account: PathBuf::from("keystore.json"),
+            data_dir: PathBuf::from("data"),
+            signer: None,
+            p2p: P2pConfig::default(),
+            rpc: RpcConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+/// How a node connects to its peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct P2pConfig {
+    /// The address the node accepts peer connections on.
+    pub listen_addr: SocketAddr,
+    /// Peers dialed when the address book has no other peers to try.
+    pub seeds: Vec<PexAddr>,
+    /// The node keys of full nodes allowed to connect, besides the validators and seeds. Nodes
+    /// outside the validator set must be listed here by the nodes they connect to.
+    pub allowed_peers: Vec<PublicKey>,
+    /// The maximum number of peers which may dial us.
+    pub max_inbound: usize,
+    /// The maximum number of peers we dial.
+    pub max_outbound: usize,
+    /// How messages sent to peers are encoded: proto, or json for debugging.
+    pub wire_encoding: Encoding,
+    /// How often new peers are dialed and asked for addresses, in milliseconds.
+    pub ensure_peers_interval_ms: u64,
+    /// How often a summary of the votes we hold is sent to peers, in milliseconds.
+    pub vote_set_bits_interval_ms: u64,
+}
+
+impl Default for P2pConfig {
+    fn default() -> Self {
+        let pex = PexConfig::default();
+        P2pConfig {
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], 3030)),
+            seeds: pex.seeds,
+            allowed_peers: Vec::new(),
+            max_inbound: pex.max_inbound,
+            max_outbound: pex.max_outbound,
+            wire_encoding: Encoding::default(),
+            ensure_peers_interval_ms: pex.ensure_peers_interval.as_millis() as u64,
+            vote_set_bits_interval_ms: GossipConfig::default().vote_set_bits_interval.as_millis()
+                as u64,
+        }
+    }
+}
+
+impl P2pConfig {
+    pub fn pex_config(&self) -> PexConfig {
+        PexConfig {
+            seeds: self.seeds.clone(),
+            max_inbound: self.max_inbound,
+            max_outbound: self.max_outbound,
+            ensure_peers_interval: Duration::from_millis(self.ensure_peers_interval_ms),
+            ..PexConfig::default()
+        }
+    }
+
+    pub fn gossip_config(&self) -> GossipConfig {
+        GossipConfig {
+            vote_set_bits_interval: Duration::from_millis(self.vote_set_bits_interval_ms),
+            ..GossipConfig::default()
+        }
+    }
+}
+
+/// The node's HTTP servers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// The address the inbox, which messages may be posted to, listens on. The inbox only runs
+    /// if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_addr: Option<SocketAddr>,
+    /// The address status, decision, validator and peer queries are served on. The query server
+    /// only runs if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_listen_addr: Option<SocketAddr>,
+    /// How many consensus events are held for subscribers that fall behind. Subscribers further
+    /// behind are told how many events they missed.
+    pub event_capacity: usize,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            listen_addr: None,
+            query_listen_addr: None,
+            event_capacity: events::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+/// How the node logs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// The lowest level logged, optionally per module, e.g. `info,tendermint::gossip=debug`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig { level: "info".to_string(), format: LogFormat::default() }
+    }
+}
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object a line, with the fields of the event and its spans, for log pipelines.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Reads `config.toml` from a node's home directory. A home directory without one has the
+    /// default config.
+    pub fn load(home: &Path) -> Result<Self, Error> {
+        let path = home.join(NODE_CONFIG_FILE);
+        let config = match std::fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(NodeConfig::default()),
+            Err(err) => return Err(Error::Io(path, err)),
+        };
+        let config: NodeConfig =
+            toml::from_str(&config).map_err(|err| Error::MalformedToml(path, err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the settings that no node can run with.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.rpc.event_capacity == 0 {
+            return Err(Error::InvalidNodeConfig("rpc.event_capacity must be nonzero"));
+        }
+        Ok(())
+    }
+
+    /// Writes the config to `config.toml` in a node's home directory.
+    pub fn save(&self, home: &Path) -> Result<(), Error> {
+        let path = home.join(NODE_CONFIG_FILE);
+        let config = toml::to_string_pretty(self).expect("node config serializes");
+        std::fs::write(&path, config).map_err(|err| Error::Write(path, err))
+    }
+}
+
+/// Reads a genesis config, and checks that it is valid.
+pub fn parse_config(path: PathBuf) -> Result<TendermintConfig, Error> {
     // Parse the configuration file.
-    let config = std::fs::read_to_string(config).unwrap();
-    let config: TendermintConfig = serde_json::from_str(&config).unwrap();
-    println!("Config: {:?}", config);
-    config
+    let config = match std::fs::read_to_string(&path) {
+        Ok(config) => config,
+        Err(err) => return Err(Error::Io(path, err)),
+    };
+    let config: TendermintConfig =
+        serde_json::from_str(&config).map_err(|err| Error::Malformed(path, err))?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_genesis() {
+        let validators = (0..3)
+            .map(|i| ValidatorInfo {
+                pubkey: Keypair::generate(KeyType::Secp256k1).get_public_key().to_string(),
+                node_pubkey: None,
+                power: 1,
+                proof_of_possession: None,
+                address: "127.0.0.1".parse().unwrap(),
+                port: 3030 + i,
+            })
+            .collect();
+        let genesis = TendermintConfig {
+            chain_id: "test-chain".to_string(),
+            genesis_time: 0,
+            initial_height: 0,
+            consensus_params: ConsensusParams::default(),
+            validators,
+        };
+        genesis.validate().unwrap();
+        assert_eq!(genesis.hash(), genesis.clone().hash());
+
+        let mut invalid = genesis.clone();
+        invalid.validators[2].pubkey = "ed25519:1234".to_string();
+        assert!(matches!(invalid.validate(), Err(Error::InvalidPubkey(..))));
+
+        // The same key untagged and tagged is still a duplicate.
+        let mut duplicate = genesis.clone();
+        let untagged = genesis.validators[0].pubkey.trim_start_matches("secp256k1:");
+        duplicate.validators[1].pubkey = untagged.to_string();
+        assert!(matches!(duplicate.validate(), Err(Error::DuplicateKey(_))));
+
+        let mut clash = genesis.clone();
+        clash.validators[1].port = 3030;
+        assert!(matches!(clash.validate(), Err(Error::AddressClash(_))));
+
+        let mut powerless = genesis.clone();
+        powerless.validators[0].power = 0;
+        assert!(matches!(powerless.validate(), Err(Error::ZeroPower(_))));
+
+        let mut changed = genesis.clone();
+        changed.consensus_params.timeout_delta_ms = 500;
+        assert_ne!(changed.hash(), genesis.hash());
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn test_bls_validators_prove_possession() {
+        let keypairs: Vec<_> = (0..2).map(|_| Keypair::generate(KeyType::Bls12381)).collect();
+        let validators = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| {
+                let addr = SocketAddr::new("127.0.0.1".parse().unwrap(), 3030 + i as u16);
+                ValidatorInfo::new(keypair.get_public_key(), addr).with_proof_of_possession(keypair)
+            })
+            .collect();
+        let genesis = TendermintConfig::new("test-chain".to_string(), validators);
+        genesis.validate().unwrap();
+
+        let mut missing = genesis.clone();
+        missing.validators[0].proof_of_possession = None;
+        assert!(matches!(missing.validate(), Err(Error::MissingProof(_))));
+        // A proof for another key doesn't do.
+        let mut swapped = genesis.clone();
+        swapped.validators[0].proof_of_possession = genesis.validators[1].proof_of_possession;
+        assert!(matches!(swapped.validate(), Err(Error::InvalidProof(_))));
+    }
+
+    #[test]
+    fn test_node_config() {
+        let home = std::env::temp_dir().join(format!("tendermint-home-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        // Without a config file, the node has the defaults.
+        assert_eq!(NodeConfig::load(&home).unwrap(), NodeConfig::default());
+
+        // Settings missing from the file keep their defaults.
+        let toml = r#"
+            data_dir = "/var/lib/tendermint"
+
+            [p2p]
+            listen_addr = "127.0.0.1:4000"
+            max_inbound = 3
+
+            [log]
+            format = "json"
+        "#;
+        std::fs::write(home.join(NODE_CONFIG_FILE), toml).unwrap();
+        let config = NodeConfig::load(&home).unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/tendermint"));
+        assert_eq!(config.p2p.listen_addr, "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.p2p.max_inbound, 3);
+        assert_eq!(config.p2p.max_outbound, P2pConfig::default().max_outbound);
+        assert_eq!(config.genesis, NodeConfig::default().genesis);
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.log.level, "info");
+
+        std::fs::write(home.join(NODE_CONFIG_FILE), "[p2p]\nlisten_adr = \"0.0.0.0:1\"").unwrap();
+        assert!(matches!(NodeConfig::load(&home), Err(Error::MalformedToml(..))));
+        std::fs::write(home.join(NODE_CONFIG_FILE), "[rpc]\nevent_capacity = 0").unwrap();
+        assert!(matches!(NodeConfig::load(&home), Err(Error::InvalidNodeConfig(_))));
+        std::fs::remove_dir_all(home).unwrap();
+    }
 }
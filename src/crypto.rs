@@ -1,27 +1,92 @@
+use ed25519_dalek::{Signer as _, Verifier as _};
 use rand::rngs::OsRng;
-use secp256k1::{ecdsa::SerializedSignature, Message, Secp256k1, SecretKey};
+use secp256k1::{Message, Secp256k1, SecretKey};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha3::{Digest, Keccak256};
 use std::{
     fmt,
     fmt::{Display, Formatter},
     str::FromStr,
+    sync::LazyLock,
 };
 
-#[derive(Clone, Debug, Copy)]
-pub struct Signature(secp256k1::ecdsa::SerializedSignature);
+#[cfg(feature = "bls")]
+use crate::bls;
+#[cfg(feature = "bls")]
+pub use crate::bls::Bls12381Keypair;
 
-#[derive(Clone, Debug, Copy)]
-pub struct PublicKey(secp256k1::PublicKey);
+/// The secp256k1 context, shared by all keys. Creating a context allocates and precomputes
+/// tables, which is costly to do for every signature.
+static SECP256K1: LazyLock<Secp256k1<secp256k1::All>> = LazyLock::new(Secp256k1::new);
 
-pub type Keypair = ECDSAKeypair;
+/// The signature schemes a key can use.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    /// ECDSA over secp256k1, signing the Keccak256 hash of the data.
+    #[default]
+    Secp256k1,
+    /// Ed25519, as used by Tendermint validators.
+    Ed25519,
+    /// BLS over the BLS12-381 curve, whose signatures aggregate. Requires the `bls` feature.
+    #[cfg(feature = "bls")]
+    Bls12381,
+}
+
+/// Produces signatures with a private key.
+pub trait Signer {
+    fn public_key(&self) -> PublicKey;
+    fn sign(&self, data: &[u8]) -> Signature;
+}
+
+/// Checks signatures against a public key.
+pub trait Verifier {
+    fn verify(&self, data: &[u8], signature: &Signature) -> bool;
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum Signature {
+    /// Serialized as DER.
+    Secp256k1(secp256k1::ecdsa::Signature),
+    Ed25519(ed25519_dalek::Signature),
+    /// The compressed G2 point.
+    #[cfg(feature = "bls")]
+    Bls12381([u8; 96]),
+}
+
+/// A public key, tagged with its signature scheme. Serialized as `<key type>:<hex>`, for example
+/// `ed25519:1d5f...`. Untagged hex is read as a secp256k1 key, for existing config files.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub enum PublicKey {
+    Secp256k1(secp256k1::PublicKey),
+    /// The compressed point, checked to be valid when the key is parsed. Kept compressed, as the
+    /// decompressed key is six times larger and public keys are copied into every message.
+    Ed25519([u8; 32]),
+    /// The compressed G1 point, checked to be in the subgroup when the key is parsed.
+    #[cfg(feature = "bls")]
+    Bls12381([u8; 48]),
+}
 
-#[derive(Debug)]
+/// A keypair of any supported scheme.
+#[derive(Debug, Clone)]
+pub enum Keypair {
+    Secp256k1(ECDSAKeypair),
+    Ed25519(Ed25519Keypair),
+    #[cfg(feature = "bls")]
+    Bls12381(Bls12381Keypair),
+}
+
+#[derive(Debug, Clone)]
 pub struct ECDSAKeypair {
     secret_key: SecretKey,
     public_key: PublicKey,
 }
 
+#[derive(Debug, Clone)]
+pub struct Ed25519Keypair {
+    signing_key: ed25519_dalek::SigningKey,
+}
+
 impl Default for ECDSAKeypair {
     fn default() -> Self {
         Self::new()
@@ -30,17 +95,17 @@ impl Default for ECDSAKeypair {
 
 impl ECDSAKeypair {
     pub fn new() -> Self {
-        let secp = Secp256k1::new();
-        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
-        ECDSAKeypair { secret_key, public_key: PublicKey(public_key) }
+        let (secret_key, public_key) = SECP256K1.generate_keypair(&mut OsRng);
+        ECDSAKeypair { secret_key, public_key: PublicKey::Secp256k1(public_key) }
     }
 
-    pub fn new_from_privatekey(private_key: &str) -> Self {
-        let secp = Secp256k1::new();
-        let secret_key = SecretKey::from_str(private_key).unwrap();
+    /// Loads a keypair from its hex-encoded 32-byte secret key.
+    pub fn new_from_privatekey(private_key: &str) -> Result<Self, CryptoError> {
+        let bytes = hex::decode(private_key)?;
+        let secret_key = SecretKey::from_slice(&bytes)?;
 
-        let public_key = secp256k1::PublicKey::from_secret_key(&secp, &secret_key);
-        ECDSAKeypair { secret_key, public_key: PublicKey(public_key) }
+        let public_key = secp256k1::PublicKey::from_secret_key(&SECP256K1, &secret_key);
+        Ok(ECDSAKeypair { secret_key, public_key: PublicKey::Secp256k1(public_key) })
     }
 
     pub fn get_public_key(&self) -> PublicKey {
@@ -52,27 +117,386 @@ impl ECDSAKeypair {
     }
 
     pub fn sign(&self, data: &[u8]) -> Signature {
-        let secp = Secp256k1::new();
-        let mut hasher = Keccak256::new();
-        hasher.update(data);
-        let hash = hasher.finalize();
-        let message = Message::from_slice(&hash).expect("32 bytes");
-        let signature = secp.sign_ecdsa(&message, &self.secret_key);
-        Signature(signature.serialize_der())
+        let message = keccak_message(data);
+        let signature = SECP256K1.sign_ecdsa(&message, &self.secret_key);
+        Signature::Secp256k1(signature)
     }
 }
 
-pub fn verify_signature(
-    data: &[u8],
-    signature: &SerializedSignature,
-    public_key: PublicKey,
-) -> bool {
-    let secp: Secp256k1<secp256k1::All> = Secp256k1::new();
+impl Default for Ed25519Keypair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ed25519Keypair {
+    pub fn new() -> Self {
+        Ed25519Keypair { signing_key: ed25519_dalek::SigningKey::generate(&mut OsRng) }
+    }
+
+    /// Loads a keypair from its hex-encoded 32-byte secret key.
+    pub fn new_from_privatekey(private_key: &str) -> Result<Self, CryptoError> {
+        let bytes: [u8; 32] =
+            hex::decode(private_key)?.try_into().map_err(|_| CryptoError::Length)?;
+        Ok(Ed25519Keypair { signing_key: ed25519_dalek::SigningKey::from_bytes(&bytes) })
+    }
+
+    pub fn get_public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// Returns the 32-byte secret key, hex encoded.
+    pub fn get_secret_key(&self) -> String {
+        hex::encode(self.signing_key.to_bytes())
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Signature {
+        Signature::Ed25519(self.signing_key.sign(data))
+    }
+}
+
+impl Keypair {
+    pub fn generate(key_type: KeyType) -> Self {
+        match key_type {
+            KeyType::Secp256k1 => Keypair::Secp256k1(ECDSAKeypair::new()),
+            KeyType::Ed25519 => Keypair::Ed25519(Ed25519Keypair::new()),
+            #[cfg(feature = "bls")]
+            KeyType::Bls12381 => Keypair::Bls12381(Bls12381Keypair::new()),
+        }
+    }
+
+    /// Loads a keypair from its hex-encoded secret key.
+    pub fn new_from_privatekey(key_type: KeyType, private_key: &str) -> Result<Self, CryptoError> {
+        Ok(match key_type {
+            KeyType::Secp256k1 => ECDSAKeypair::new_from_privatekey(private_key)?.into(),
+            KeyType::Ed25519 => Ed25519Keypair::new_from_privatekey(private_key)?.into(),
+            #[cfg(feature = "bls")]
+            KeyType::Bls12381 => Bls12381Keypair::new_from_privatekey(private_key)?.into(),
+        })
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            Keypair::Secp256k1(_) => KeyType::Secp256k1,
+            Keypair::Ed25519(_) => KeyType::Ed25519,
+            #[cfg(feature = "bls")]
+            Keypair::Bls12381(_) => KeyType::Bls12381,
+        }
+    }
+
+    pub fn get_public_key(&self) -> PublicKey {
+        match self {
+            Keypair::Secp256k1(keypair) => keypair.get_public_key(),
+            Keypair::Ed25519(keypair) => keypair.get_public_key(),
+            #[cfg(feature = "bls")]
+            Keypair::Bls12381(keypair) => keypair.get_public_key(),
+        }
+    }
+
+    /// Returns the secret key, hex encoded.
+    pub fn get_secret_key(&self) -> String {
+        match self {
+            Keypair::Secp256k1(keypair) => keypair.get_secret_key().display_secret().to_string(),
+            Keypair::Ed25519(keypair) => keypair.get_secret_key(),
+            #[cfg(feature = "bls")]
+            Keypair::Bls12381(keypair) => keypair.get_secret_key(),
+        }
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Signature {
+        match self {
+            Keypair::Secp256k1(keypair) => keypair.sign(data),
+            Keypair::Ed25519(keypair) => keypair.sign(data),
+            #[cfg(feature = "bls")]
+            Keypair::Bls12381(keypair) => keypair.sign(data),
+        }
+    }
+
+    /// Proves possession of a BLS secret key, as a BLS validator key in a genesis must. None for
+    /// the other schemes, which need no proof.
+    pub fn prove_possession(&self) -> Option<Signature> {
+        match self {
+            #[cfg(feature = "bls")]
+            Keypair::Bls12381(keypair) => Some(keypair.prove_possession()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ECDSAKeypair> for Keypair {
+    fn from(keypair: ECDSAKeypair) -> Self {
+        Keypair::Secp256k1(keypair)
+    }
+}
+
+impl From<Ed25519Keypair> for Keypair {
+    fn from(keypair: Ed25519Keypair) -> Self {
+        Keypair::Ed25519(keypair)
+    }
+}
+
+#[cfg(feature = "bls")]
+impl From<Bls12381Keypair> for Keypair {
+    fn from(keypair: Bls12381Keypair) -> Self {
+        Keypair::Bls12381(keypair)
+    }
+}
+
+impl Signer for Keypair {
+    fn public_key(&self) -> PublicKey {
+        self.get_public_key()
+    }
+
+    fn sign(&self, data: &[u8]) -> Signature {
+        Keypair::sign(self, data)
+    }
+}
+
+impl Signer for ECDSAKeypair {
+    fn public_key(&self) -> PublicKey {
+        self.get_public_key()
+    }
+
+    fn sign(&self, data: &[u8]) -> Signature {
+        ECDSAKeypair::sign(self, data)
+    }
+}
+
+impl Signer for Ed25519Keypair {
+    fn public_key(&self) -> PublicKey {
+        self.get_public_key()
+    }
+
+    fn sign(&self, data: &[u8]) -> Signature {
+        Ed25519Keypair::sign(self, data)
+    }
+}
+
+#[cfg(feature = "bls")]
+impl Signer for Bls12381Keypair {
+    fn public_key(&self) -> PublicKey {
+        self.get_public_key()
+    }
+
+    fn sign(&self, data: &[u8]) -> Signature {
+        Bls12381Keypair::sign(self, data)
+    }
+}
+
+fn keccak_message(data: &[u8]) -> Message {
     let mut hasher = Keccak256::new();
     hasher.update(data);
     let hash = hasher.finalize();
-    let message = Message::from_slice(&hash).expect("32 bytes");
-    secp.verify_ecdsa(&message, &signature.to_signature().unwrap(), &public_key.0).is_ok()
+    Message::from_slice(&hash).expect("32 bytes")
+}
+
+impl Verifier for PublicKey {
+    fn verify(&self, data: &[u8], signature: &Signature) -> bool {
+        match (self, signature) {
+            (PublicKey::Secp256k1(public_key), Signature::Secp256k1(signature)) => {
+                SECP256K1.verify_ecdsa(&keccak_message(data), signature, public_key).is_ok()
+            }
+            (PublicKey::Ed25519(public_key), Signature::Ed25519(signature)) => {
+                ed25519_dalek::VerifyingKey::from_bytes(public_key)
+                    .is_ok_and(|public_key| public_key.verify(data, signature).is_ok())
+            }
+            #[cfg(feature = "bls")]
+            (PublicKey::Bls12381(public_key), Signature::Bls12381(signature)) => {
+                bls::verify(public_key, data, signature)
+            }
+            // A signature from another scheme is never valid.
+            _ => false,
+        }
+    }
+}
+
+pub fn verify_signature(data: &[u8], signature: &Signature, public_key: PublicKey) -> bool {
+    public_key.verify(data, signature)
+}
+
+impl PublicKey {
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            PublicKey::Secp256k1(_) => KeyType::Secp256k1,
+            PublicKey::Ed25519(_) => KeyType::Ed25519,
+            #[cfg(feature = "bls")]
+            PublicKey::Bls12381(_) => KeyType::Bls12381,
+        }
+    }
+
+    /// Returns the raw encoding of the key: 33 compressed bytes for secp256k1, 32 bytes for
+    /// Ed25519, and 48 compressed bytes for BLS12-381.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            PublicKey::Secp256k1(public_key) => public_key.serialize().to_vec(),
+            PublicKey::Ed25519(public_key) => public_key.to_vec(),
+            #[cfg(feature = "bls")]
+            PublicKey::Bls12381(public_key) => public_key.to_vec(),
+        }
+    }
+
+    pub fn from_bytes(key_type: KeyType, bytes: &[u8]) -> Result<Self, CryptoError> {
+        match key_type {
+            KeyType::Secp256k1 => {
+                Ok(PublicKey::Secp256k1(secp256k1::PublicKey::from_slice(bytes)?))
+            }
+            KeyType::Ed25519 => {
+                let bytes: &[u8; 32] = bytes.try_into().map_err(|_| CryptoError::Length)?;
+                ed25519_dalek::VerifyingKey::from_bytes(bytes)?;
+                Ok(PublicKey::Ed25519(*bytes))
+            }
+            #[cfg(feature = "bls")]
+            KeyType::Bls12381 => Ok(PublicKey::Bls12381(bls::public_key_from_bytes(bytes)?)),
+        }
+    }
+}
+
+/// An account address: the last 20 bytes of the Keccak256 hash of a public key, as in Ethereum.
+/// Written as `0x`-prefixed hex.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 20]);
+
+impl PublicKey {
+    /// Derives the key's address. Secp256k1 keys are hashed uncompressed, without the leading
+    /// 0x04 byte, so their addresses are the same as on Ethereum. Other keys are hashed in their
+    /// raw encoding.
+    pub fn address(&self) -> Address {
+        let hash = match self {
+            PublicKey::Secp256k1(public_key) => {
+                Keccak256::digest(&public_key.serialize_uncompressed()[1..])
+            }
+            _ => Keccak256::digest(self.to_bytes()),
+        };
+        Address(hash[12..].try_into().expect("hash is 32 bytes"))
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for Address {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        Ok(Address(hex::decode(hex)?.try_into().map_err(|_| CryptoError::Length)?))
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Address::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Signature {
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            Signature::Secp256k1(_) => KeyType::Secp256k1,
+            Signature::Ed25519(_) => KeyType::Ed25519,
+            #[cfg(feature = "bls")]
+            Signature::Bls12381(_) => KeyType::Bls12381,
+        }
+    }
+
+    /// Returns the fixed-size encoding of the signature, used on the wire: 64 bytes for
+    /// secp256k1 and Ed25519, and 96 bytes for BLS12-381.
+    pub fn to_compact(&self) -> Vec<u8> {
+        match self {
+            Signature::Secp256k1(signature) => signature.serialize_compact().to_vec(),
+            Signature::Ed25519(signature) => signature.to_bytes().to_vec(),
+            #[cfg(feature = "bls")]
+            Signature::Bls12381(signature) => signature.to_vec(),
+        }
+    }
+
+    pub fn from_compact(key_type: KeyType, bytes: &[u8]) -> Result<Self, CryptoError> {
+        match key_type {
+            KeyType::Secp256k1 => {
+                Ok(Signature::Secp256k1(secp256k1::ecdsa::Signature::from_compact(bytes)?))
+            }
+            KeyType::Ed25519 => {
+                Ok(Signature::Ed25519(ed25519_dalek::Signature::from_slice(bytes)?))
+            }
+            #[cfg(feature = "bls")]
+            KeyType::Bls12381 => Ok(Signature::Bls12381(bls::signature_from_bytes(bytes)?)),
+        }
+    }
+
+    /// Returns the bytes of the signature as serialized: DER for secp256k1, and the compact
+    /// encoding for the other schemes.
+    fn to_vec(self) -> Vec<u8> {
+        match self {
+            Signature::Secp256k1(signature) => signature.serialize_der().to_vec(),
+            _ => self.to_compact(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error(transparent)]
+    Secp256k1(#[from] secp256k1::Error),
+    #[error(transparent)]
+    Ed25519(#[from] ed25519_dalek::SignatureError),
+    #[cfg(feature = "bls")]
+    #[error("invalid BLS12-381 key or signature: {0:?}")]
+    Bls12381(blst::BLST_ERROR),
+    #[error("invalid hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("unknown key type {0}")]
+    UnknownKeyType(String),
+    #[error("invalid length")]
+    Length,
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            KeyType::Secp256k1 => write!(f, "secp256k1"),
+            KeyType::Ed25519 => write!(f, "ed25519"),
+            #[cfg(feature = "bls")]
+            KeyType::Bls12381 => write!(f, "bls12381"),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            "ed25519" => Ok(KeyType::Ed25519),
+            #[cfg(feature = "bls")]
+            "bls12381" => Ok(KeyType::Bls12381),
+            _ => Err(CryptoError::UnknownKeyType(s.to_string())),
+        }
+    }
+}
+
+/// Splits `<key type>:<hex>` into its parts. Untagged hex is secp256k1.
+fn split_tag(s: &str) -> Result<(KeyType, &str), CryptoError> {
+    match s.split_once(':') {
+        Some((key_type, hex)) => Ok((KeyType::from_str(key_type)?, hex)),
+        None => Ok((KeyType::Secp256k1, s)),
+    }
 }
 
 // PublicKey.
@@ -83,18 +507,17 @@ impl<'de> Deserialize<'de> for PublicKey {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let public_key = PublicKey::from_str(&s).map_err(serde::de::Error::custom)?;
-        Ok(PublicKey(public_key.0))
+        PublicKey::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
 // FromStr.
 impl FromStr for PublicKey {
-    type Err = secp256k1::Error;
+    type Err = CryptoError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let public_key = secp256k1::PublicKey::from_str(s)?;
-        Ok(PublicKey(public_key))
+        let (key_type, hex) = split_tag(s)?;
+        PublicKey::from_bytes(key_type, &hex::decode(hex)?)
     }
 }
 
@@ -104,8 +527,7 @@ impl Serialize for PublicKey {
     where
         S: Serializer,
     {
-        let public_key_str = self.0.to_string();
-        serializer.serialize_str(&public_key_str)
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -117,20 +539,23 @@ impl<'de> Deserialize<'de> for Signature {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let b = hex::decode(s).map_err(serde::de::Error::custom)?;
-        let signature =
-            secp256k1::ecdsa::Signature::from_der(&b).map_err(serde::de::Error::custom)?;
-        Ok(Signature(signature.serialize_der()))
+        Signature::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
 // FromStr.
 impl FromStr for Signature {
-    type Err = secp256k1::Error;
+    type Err = CryptoError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let signature = secp256k1::ecdsa::Signature::from_der(s.as_bytes())?;
-        Ok(Signature(signature.serialize_der()))
+        let (key_type, hex) = split_tag(s)?;
+        let b = hex::decode(hex)?;
+        match key_type {
+            KeyType::Secp256k1 => {
+                Ok(Signature::Secp256k1(secp256k1::ecdsa::Signature::from_der(&b)?))
+            }
+            key_type => Signature::from_compact(key_type, &b),
+        }
     }
 }
 
@@ -140,36 +565,22 @@ impl Serialize for Signature {
     where
         S: Serializer,
     {
-        // get the signature bytes no length
-        let b = self.0.as_ref();
-        let mut signature_str = String::new();
-        for i in b {
-            signature_str.push_str(&format!("{:02x}", i));
-        }
-        serializer.serialize_str(&signature_str)
-    }
-}
-
-// to_inner
-impl Signature {
-    pub fn to_inner(&self) -> secp256k1::ecdsa::SerializedSignature {
-        self.0
+        serializer.serialize_str(&self.to_string())
     }
 }
 
 impl Display for PublicKey {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}:{}", self.key_type(), hex::encode(self.to_bytes()))
     }
 }
 
 impl Display for Signature {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}:{}", self.key_type(), hex::encode(self.to_vec()))
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,9 +589,105 @@ mod tests {
     #[test]
     fn test_parse_keypair() {
         let keypair = ECDSAKeypair::new();
-        let keypair2 = ECDSAKeypair::new_from_privatekey(&keypair.get_secret_key().display_secret().to_string(),);
+        let keypair2 = ECDSAKeypair::new_from_privatekey(
+            &keypair.get_secret_key().display_secret().to_string(),
+        )
+        .unwrap();
         // Verify generated keypair.
-        assert!(keypair2.get_secret_key().display_secret().to_string() == keypair.get_secret_key().display_secret().to_string());
+        assert!(
+            keypair2.get_secret_key().display_secret().to_string() ==
+                keypair.get_secret_key().display_secret().to_string()
+        );
         assert!(keypair2.get_public_key().to_string() == keypair.get_public_key().to_string());
     }
+
+    #[test]
+    fn test_sign_verify_each_key_type() {
+        #[allow(unused_mut)]
+        let mut key_types = vec![KeyType::Secp256k1, KeyType::Ed25519];
+        #[cfg(feature = "bls")]
+        key_types.push(KeyType::Bls12381);
+        for key_type in key_types {
+            let keypair = Keypair::generate(key_type);
+            let reloaded =
+                Keypair::new_from_privatekey(key_type, &keypair.get_secret_key()).unwrap();
+            assert_eq!(reloaded.get_public_key(), keypair.get_public_key());
+
+            let signature = keypair.sign(b"gm tendermint");
+            let public_key = keypair.get_public_key();
+            assert!(public_key.verify(b"gm tendermint", &signature));
+            assert!(!public_key.verify(b"gn tendermint", &signature));
+
+            // Keys and signatures round trip through their tagged string form.
+            let tagged = public_key.to_string();
+            assert!(tagged.starts_with(&format!("{}:", key_type)));
+            assert_eq!(PublicKey::from_str(&tagged).unwrap(), public_key);
+            assert_eq!(Signature::from_str(&signature.to_string()).unwrap(), signature);
+            let compact = signature.to_compact();
+            assert_eq!(Signature::from_compact(key_type, &compact).unwrap(), signature);
+        }
+    }
+
+    #[test]
+    fn test_key_types_do_not_cross_verify() {
+        let ed25519 = Keypair::generate(KeyType::Ed25519);
+        let secp256k1 = Keypair::generate(KeyType::Secp256k1);
+        let signature = ed25519.sign(b"data");
+        assert!(!secp256k1.get_public_key().verify(b"data", &signature));
+    }
+
+    #[test]
+    fn test_malformed_inputs_are_errors() {
+        assert!(matches!(ECDSAKeypair::new_from_privatekey("not hex"), Err(CryptoError::Hex(_))));
+        // Zero is not a valid secp256k1 secret key.
+        assert!(ECDSAKeypair::new_from_privatekey(&"00".repeat(32)).is_err());
+        assert!(matches!(Ed25519Keypair::new_from_privatekey("0011"), Err(CryptoError::Length)));
+        assert!(matches!(
+            Keypair::new_from_privatekey(KeyType::Secp256k1, &"01".repeat(33)),
+            Err(CryptoError::Secp256k1(_))
+        ));
+
+        for s in ["", "ed25519:", "ed25519:zz", "ed25519:0011", "rsa:0011", "02", &"00".repeat(33)]
+        {
+            assert!(PublicKey::from_str(s).is_err(), "{:?} parsed as a public key", s);
+        }
+
+        let signature = Keypair::generate(KeyType::Ed25519).sign(b"data").to_string();
+        let truncated = &signature[..signature.len() - 2];
+        for s in ["", "secp256k1:", "secp256k1:zz", "secp256k1:30060201", truncated] {
+            assert!(Signature::from_str(s).is_err(), "{:?} parsed as a signature", s);
+        }
+        assert!(Signature::from_compact(KeyType::Secp256k1, &[0xff; 64]).is_err());
+        assert!(Signature::from_compact(KeyType::Ed25519, &[0; 63]).is_err());
+
+        for s in ["", "0x", "0x00", "zz", &format!("0x{}", "00".repeat(21))] {
+            assert!(Address::from_str(s).is_err(), "{:?} parsed as an address", s);
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        // The well-known Ethereum address of the secret key 1.
+        let keypair = ECDSAKeypair::new_from_privatekey(&format!("{:064x}", 1)).unwrap();
+        let address = keypair.get_public_key().address();
+        assert_eq!(address.to_string(), "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf");
+        assert_eq!(Address::from_str(&address.to_string()).unwrap(), address);
+        assert_eq!(
+            serde_json::from_value::<Address>(serde_json::to_value(address).unwrap()).unwrap(),
+            address
+        );
+
+        // Different keys and key types have different addresses.
+        let ed25519 = Keypair::generate(KeyType::Ed25519).get_public_key();
+        assert_ne!(ed25519.address(), address);
+        assert_ne!(ECDSAKeypair::new().get_public_key().address(), address);
+    }
+
+    #[test]
+    fn test_untagged_public_key_is_secp256k1() {
+        let hex = "026a9ba125897c7b14ad85f606f1ba0891a252cb81e268bfd2daeb0eaeb1dec93f";
+        let public_key = PublicKey::from_str(hex).unwrap();
+        assert_eq!(public_key.key_type(), KeyType::Secp256k1);
+        assert_eq!(public_key.to_string(), format!("secp256k1:{}", hex));
+    }
 }
@@ -0,0 +1,199 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    events::{EventSystem, Lagged},
+    process::Event,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed decision log: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+/// The decisions a process has made, appended to a file as they happen, one JSON event per line.
+/// Subscribers can replay them to catch up on the decisions they missed.
+///
+/// The log keeps the offset of each height's line in memory, so a replay only reads the lines it
+/// needs. Reads and writes block, so the async methods run them on tokio's blocking pool.
+pub struct DecisionLog {
+    path: PathBuf,
+    /// The file, and the offset its next line is written at.
+    file: StdMutex<(File, u64)>,
+    /// The offset of the latest line for each height. A height decided more than once, by a node
+    /// which restarted, points at its latest decision.
+    index: StdMutex<BTreeMap<u64, u64>>,
+}
+
+impl DecisionLog {
+    /// Opens the decision log at `path`, creating it if it doesn't exist yet. A last line cut
+    /// short by a crash is removed, so the next decision starts on a line of its own.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        let mut index = BTreeMap::new();
+        let mut end = 0;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)? as u64;
+            if read == 0 {
+                break;
+            }
+            if !line.ends_with('\n') {
+                // Only the last line can be cut short.
+                break;
+            }
+            if !line.trim().is_empty() {
+                let event: Event = serde_json::from_str(&line)?;
+                index.insert(event.height(), end);
+            }
+            end += read;
+        }
+        drop(reader);
+        if file.metadata()?.len() != end {
+            file.set_len(end)?;
+        }
+        Ok(DecisionLog { path, file: StdMutex::new((file, end)), index: StdMutex::new(index) })
+    }
+
+    /// Appends a decision, and waits for it to reach the disk. Other events aren't logged.
+    pub async fn append(self: &Arc<Self>, event: &Event) -> Result<(), Error> {
+        if !matches!(event, Event::Decision { .. }) {
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let height = event.height();
+        let log = self.clone();
+        blocking(move || log.write_line(height, &line)).await
+    }
+
+    fn write_line(&self, height: u64, line: &[u8]) -> Result<(), Error> {
+        let mut file = self.file.lock().unwrap();
+        let (file, end) = &mut *file;
+        file.write_all(line)?;
+        file.sync_data()?;
+        self.index.lock().unwrap().insert(height, *end);
+        *end += line.len() as u64;
+        Ok(())
+    }
+
+    /// Reads the decisions at `height` and above, in order of height. A height decided more than
+    /// once, by a node which restarted, yields the latest decision.
+    pub async fn read_from(self: &Arc<Self>, height: u64) -> Result<Vec<Event>, Error> {
+        let offsets: Vec<u64> =
+            self.index.lock().unwrap().range(height..).map(|(_, o)| *o).collect();
+        let log = self.clone();
+        blocking(move || log.read_lines(&offsets)).await
+    }
+
+    /// Reads the lines starting at `offsets`, seeking only when they aren't consecutive.
+    fn read_lines(&self, offsets: &[u64]) -> Result<Vec<Event>, Error> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut position = 0;
+        let mut line = String::new();
+        let mut decisions = Vec::with_capacity(offsets.len());
+        for &offset in offsets {
+            if offset != position {
+                reader.seek(SeekFrom::Start(offset))?;
+            }
+            line.clear();
+            position = offset + reader.read_line(&mut line)? as u64;
+            decisions.push(serde_json::from_str(&line)?);
+        }
+        Ok(decisions)
+    }
+
+    /// Subscribes to events from `height` on: the logged decisions first, then live events. The
+    /// live stream is subscribed to before the log is read, so no decision falls between the two,
+    /// and decisions already replayed are skipped.
+    pub async fn subscribe_from(
+        self: &Arc<Self>,
+        events: &EventSystem<Event>,
+        height: u64,
+    ) -> Result<impl Stream<Item = Result<Event, Lagged>>, Error> {
+        let live = events.subscribe_checked();
+        let replayed = self.read_from(height).await?;
+        let replayed_to = replayed.last().map(Event::height);
+        let live = live.filter(move |item| match item {
+            Ok(event @ Event::Decision { .. }) => {
+                event.height() >= height && replayed_to.is_none_or(|to| event.height() > to)
+            }
+            Ok(event) => event.height() >= height,
+            Err(_) => true,
+        });
+        Ok(tokio_stream::iter(replayed.into_iter().map(Ok)).chain(live))
+    }
+}
+
+/// Runs blocking file IO on tokio's blocking pool.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    tokio::task::spawn_blocking(f).await.map_err(std::io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decision(height: u64) -> Event {
+        Event::Decision {
+            height,
+            round: 1,
+            value: height.to_string(),
+            from: 0,
+            #[cfg(feature = "bls")]
+            certificate: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_then_live() {
+        let path = std::env::temp_dir().join(format!("decisions-{}.jsonl", std::process::id()));
+        let log = Arc::new(DecisionLog::open(&path).unwrap());
+        for height in 1..=3 {
+            log.append(&decision(height)).await.unwrap();
+        }
+        log.append(&Event::NewHeight { height: 4 }).await.unwrap();
+
+        let events = EventSystem::new();
+        let mut subscriber = Box::pin(log.subscribe_from(&events, 2).await.unwrap());
+        // A decision both logged and published after subscribing is only seen once.
+        events.publish(decision(3));
+        events.publish(decision(4));
+
+        let mut heights = Vec::new();
+        for _ in 0..3 {
+            heights.push(subscriber.next().await.unwrap().unwrap().height());
+        }
+        assert_eq!(heights, vec![2, 3, 4]);
+
+        // Reopened after a crash cut its last line short, the log drops the partial line and
+        // indexes the rest, keeping the latest decision of a height decided twice.
+        drop(log);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"type\":\"decision\",\"hei").unwrap();
+        let log = Arc::new(DecisionLog::open(&path).unwrap());
+        let mut again = decision(2);
+        if let Event::Decision { value, .. } = &mut again {
+            *value = "again".to_string();
+        }
+        log.append(&again).await.unwrap();
+        let replayed = log.read_from(2).await.unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(replayed.iter().map(Event::height).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(matches!(&replayed[0], Event::Decision { value, .. } if value == "again"));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    addrbook, codec, config, crypto::CryptoError, decision_log, keystore, metrics, p2p, privval,
+    rpc_client, rpc_server, secret_connection, vote_set::VoteError,
+};
+
+/// Any error returned by the library. Each module has its own error type, which converts into
+/// this one, so callers working across modules can use `?` throughout.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Config(#[from] config::Error),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Keystore(#[from] keystore::Error),
+    #[error(transparent)]
+    Codec(#[from] codec::Error),
+    #[error(transparent)]
+    Vote(#[from] VoteError),
+    #[error(transparent)]
+    Connection(#[from] secret_connection::Error),
+    #[error(transparent)]
+    P2p(#[from] p2p::Error),
+    #[error(transparent)]
+    AddressBook(#[from] addrbook::Error),
+    #[error(transparent)]
+    DecisionLog(#[from] decision_log::Error),
+    #[error(transparent)]
+    Metrics(#[from] metrics::Error),
+    #[error(transparent)]
+    Signer(#[from] privval::Error),
+    #[error(transparent)]
+    RpcServer(#[from] rpc_server::Error),
+    #[error(transparent)]
+    RpcClient(#[from] rpc_client::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,5 +1,17 @@
 use tokio::sync::broadcast;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+
+/// How many events an `EventSystem` holds for subscribers by default. A process publishes a few
+/// dozen events a height.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// The number of events a subscriber missed because it fell behind the publisher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("subscriber fell behind, missing {0} events")]
+pub struct Lagged(pub u64);
 
 pub struct EventSystem<T>
 where
@@ -17,25 +29,67 @@ where
     }
 }
 
+/// Clones share the channel, so events published on one reach the subscribers of all.
+impl<T> Clone for EventSystem<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn clone(&self) -> Self {
+        EventSystem { sender: self.sender.clone() }
+    }
+}
+
 impl<T> EventSystem<T>
 where
     T: Clone + Send + 'static,
 {
     // Create a new EventSystem
     pub fn new() -> Self {
-        let (sender, _) = broadcast::channel(100); // Create a broadcast channel with 100 capacity
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates an event system holding at least `capacity` events for subscribers that fall
+    /// behind. Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
         EventSystem { sender }
     }
 
-    // Function to subscribe to the event stream
+    /// Subscribes to the event stream. Events a subscriber falls too far behind to receive are
+    /// skipped without notice; use `subscribe_checked` to learn of them.
     pub fn subscribe(&self) -> impl tokio_stream::Stream<Item = T> {
         let receiver = self.sender.subscribe();
         // Wrap receiver into a BroadcastStream which implements Stream trait
         BroadcastStream::new(receiver).filter_map(|res| res.ok())
     }
 
+    /// Subscribes to the event stream, learning how many events were missed whenever the
+    /// subscriber falls more than the channel's capacity behind. The stream carries on with the
+    /// oldest event still held.
+    pub fn subscribe_checked(&self) -> impl tokio_stream::Stream<Item = Result<T, Lagged>> {
+        let receiver = self.sender.subscribe();
+        BroadcastStream::new(receiver)
+            .map(|res| res.map_err(|BroadcastStreamRecvError::Lagged(missed)| Lagged(missed)))
+    }
+
     // Function to publish an event
     pub fn publish(&self, event: T) {
         let _ = self.sender.send(event); // Ignore the error for simplicity
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscriber_learns_it_lagged() {
+        let events = EventSystem::with_capacity(4);
+        let mut subscriber = Box::pin(events.subscribe_checked());
+        for i in 0..9 {
+            events.publish(i);
+        }
+        assert_eq!(subscriber.next().await, Some(Err(Lagged(5))));
+        assert_eq!(subscriber.next().await, Some(Ok(5)));
+    }
+}
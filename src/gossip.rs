@@ -0,0 +1,415 @@
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+    time::{interval, Duration},
+};
+
+use crate::{
+    addrbook::{AddressBook, Misbehaviour},
+    crypto::PublicKey,
+    messages::{PeerMessage, SignedMessage, Step, VoteSetBits},
+    process::RoundState,
+    verification::{SignatureVerifier, VerifierConfig},
+};
+
+pub type MessageHash = [u8; 32];
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// The maximum number of peers a new message is relayed to.
+    pub fanout: usize,
+    /// How many message hashes are remembered, both globally and per peer.
+    pub seen_capacity: usize,
+    /// How often a summary of the votes we hold is sent to peers.
+    pub vote_set_bits_interval: Duration,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig {
+            fanout: 8,
+            seen_capacity: 10_000,
+            vote_set_bits_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// A bounded set of message hashes. Once full, the oldest hashes are forgotten first.
+#[derive(Debug)]
+struct SeenSet {
+    hashes: HashSet<MessageHash>,
+    order: VecDeque<MessageHash>,
+    capacity: usize,
+}
+
+impl SeenSet {
+    fn new(capacity: usize) -> Self {
+        SeenSet { hashes: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    fn contains(&self, hash: &MessageHash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Inserts a hash, returning false if it was already present.
+    fn insert(&mut self, hash: MessageHash) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+struct GossipPeer {
+    sender: mpsc::Sender<PeerMessage>,
+    /// Messages this peer is known to have, because it sent them to us or we sent them to it.
+    known: SeenSet,
+}
+
+/// Relays consensus messages between peers, so that messages reach every process even when the
+/// network is not fully connected.
+///
+/// Every message with a valid signature that hasn't been seen before is delivered to the local
+/// process and forwarded to up to `fanout` peers which aren't known to have it already. Messages
+/// published by the local process are forwarded the same way.
+///
+/// Once attached to a process with [`Gossip::track_votes`], peers also periodically exchange a
+/// summary of the votes they hold for their current round, and send each other the votes the
+/// other side is missing. This recovers votes lost to dropped messages before the round times out.
+pub struct Gossip {
+    config: GossipConfig,
+    /// Checks the signatures of messages before they are relayed.
+    verifier: Arc<SignatureVerifier>,
+    seen: StdMutex<SeenSet>,
+    peers: StdMutex<HashMap<PublicKey, GossipPeer>>,
+    round_state: StdMutex<Option<Arc<StdMutex<RoundState>>>>,
+    /// Where peers relaying forgeries are reported, if anywhere.
+    book: StdMutex<Option<Arc<AddressBook>>>,
+}
+
+impl Gossip {
+    pub fn new(config: GossipConfig, chain_id: String) -> Arc<Self> {
+        Arc::new(Gossip {
+            verifier: SignatureVerifier::new(VerifierConfig::default(), chain_id),
+            seen: StdMutex::new(SeenSet::new(config.seen_capacity)),
+            peers: StdMutex::new(HashMap::new()),
+            round_state: StdMutex::new(None),
+            book: StdMutex::new(None),
+            config,
+        })
+    }
+
+    /// Adds a neighbour that messages can be relayed to.
+    pub fn add_peer(&self, pubkey: PublicKey, sender: mpsc::Sender<PeerMessage>) {
+        let peer = GossipPeer { sender, known: SeenSet::new(self.config.seen_capacity) };
+        self.peers.lock().unwrap().insert(pubkey, peer);
+    }
+
+    pub fn remove_peer(&self, pubkey: &PublicKey) {
+        self.peers.lock().unwrap().remove(pubkey);
+    }
+
+    /// The verifier gossip checks messages with. Sharing it with the process saves the process
+    /// verifying the messages gossip delivers to it again.
+    pub fn verifier(&self) -> Arc<SignatureVerifier> {
+        self.verifier.clone()
+    }
+
+    /// Reports peers which relay messages with invalid signatures to an address book, which bans
+    /// them once they do so repeatedly.
+    pub fn set_address_book(&self, book: Arc<AddressBook>) {
+        *self.book.lock().unwrap() = Some(book);
+    }
+
+    /// Handles a message received from `from`. Returns true if the message is new, in which case
+    /// it has been relayed and should be delivered to the local process.
+    pub fn handle_inbound(&self, from: PublicKey, message: &SignedMessage) -> bool {
+        let hash = message.hash();
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&from) {
+            peer.known.insert(hash);
+        }
+        if self.seen.lock().unwrap().contains(&hash) {
+            return false;
+        }
+        // Don't spend bandwidth relaying forgeries.
+        if !self.verifier.verify_hashed(hash, message) {
+            if let Some(book) = self.book.lock().unwrap().as_ref() {
+                book.report(&from, Misbehaviour::InvalidSignature);
+            }
+            return false;
+        }
+        if !self.seen.lock().unwrap().insert(hash) {
+            return false;
+        }
+        self.relay(hash, message);
+        true
+    }
+
+    /// Publishes a message created by the local process.
+    pub fn publish(&self, message: &SignedMessage) {
+        let hash = message.hash();
+        if self.seen.lock().unwrap().insert(hash) {
+            self.relay(hash, message);
+        }
+    }
+
+    fn relay(&self, hash: MessageHash, message: &SignedMessage) {
+        let mut peers = self.peers.lock().unwrap();
+        let mut targets: Vec<PublicKey> =
+            peers.iter().filter(|(_, peer)| !peer.known.contains(&hash)).map(|(k, _)| *k).collect();
+        targets.shuffle(&mut rand::thread_rng());
+        targets.truncate(self.config.fanout);
+
+        for pubkey in targets {
+            let peer = peers.get_mut(&pubkey).expect("target is a peer");
+            peer.known.insert(hash);
+            // A peer with a full queue misses the message, rather than stalling the relay.
+            let _ = peer.sender.try_send(PeerMessage::Consensus(message.clone()));
+        }
+    }
+
+    /// Summarises the votes held for the current round, if we are tracking a process.
+    pub fn vote_set_bits(&self) -> Option<VoteSetBits> {
+        let round_state = self.round_state.lock().unwrap().clone()?;
+        let state = round_state.lock().unwrap();
+        let (prevotes, precommits) = state.votes.bit_arrays(state.round);
+        Some(VoteSetBits {
+            height: state.height,
+            round: state.round,
+            step: state.step,
+            prevotes,
+            precommits,
+        })
+    }
+
+    /// Handles a peer's vote summary by sending it the votes it is missing for that round.
+    /// Precommits are held back until the peer reaches the precommit step: until then it is
+    /// gathering prevotes, which would otherwise compete with precommits for room in its queue.
+    pub fn handle_vote_set_bits(&self, from: PublicKey, bits: &VoteSetBits) {
+        let Some(round_state) = self.round_state.lock().unwrap().clone() else {
+            return;
+        };
+        let missing: Vec<SignedMessage> = {
+            let state = round_state.lock().unwrap();
+            if state.votes.height() != bits.height {
+                return;
+            }
+            let mut missing = Vec::new();
+            for (step, theirs) in
+                [(Step::Prevote, &bits.prevotes), (Step::Precommit, &bits.precommits)]
+            {
+                if step > bits.step {
+                    continue;
+                }
+                let ours = match step {
+                    Step::Prevote => state.votes.prevotes(bits.round),
+                    _ => state.votes.precommits(bits.round),
+                };
+                if let Some(ours) = ours {
+                    missing.extend(
+                        ours.bit_array()
+                            .missing_from(theirs)
+                            .into_iter()
+                            .filter_map(|i| ours.get(i).cloned()),
+                    );
+                }
+            }
+            missing
+        };
+
+        let mut peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.get_mut(&from) else {
+            return;
+        };
+        for vote in missing {
+            peer.known.insert(vote.hash());
+            let _ = peer.sender.try_send(PeerMessage::Consensus(vote));
+        }
+    }
+
+    /// Attaches gossip to a process's round state, and starts periodically sending a summary of
+    /// the votes we hold to every peer. The summaries stop when the returned task is aborted.
+    pub fn track_votes(self: &Arc<Self>, round_state: Arc<StdMutex<RoundState>>) -> JoinHandle<()> {
+        *self.round_state.lock().unwrap() = Some(round_state);
+
+        let gossip = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(gossip.config.vote_set_bits_interval);
+            loop {
+                ticker.tick().await;
+                let Some(bits) = gossip.vote_set_bits() else {
+                    continue;
+                };
+                for peer in gossip.peers.lock().unwrap().values() {
+                    let _ = peer.sender.try_send(PeerMessage::VoteSetBits(bits.clone()));
+                }
+            }
+        })
+    }
+
+    /// Starts routing messages between the network and a local process.
+    ///
+    /// `inbound` carries messages from peers, tagged with the peer they arrived from. Returns the
+    /// sender the local process broadcasts on, and the receiver it reads new messages from.
+    pub fn start(
+        self: &Arc<Self>,
+        inbound: Arc<Mutex<mpsc::Receiver<(PublicKey, PeerMessage)>>>,
+    ) -> (mpsc::Sender<SignedMessage>, Arc<Mutex<mpsc::Receiver<SignedMessage>>>) {
+        let (deliver_tx, deliver_rx) = mpsc::channel(100);
+        let gossip = self.clone();
+        tokio::spawn(async move {
+            let mut inbound = inbound.lock().await;
+            while let Some((from, message)) = inbound.recv().await {
+                match message {
+                    PeerMessage::Consensus(message) => {
+                        if gossip.handle_inbound(from, &message) &&
+                            deliver_tx.send(message).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                    PeerMessage::VoteSetBits(bits) => gossip.handle_vote_set_bits(from, &bits),
+                    // Peer exchange is handled before messages reach gossip.
+                    PeerMessage::PexRequest | PeerMessage::PexAddrs(_) => {}
+                }
+            }
+        });
+
+        let (publish_tx, mut publish_rx) = mpsc::channel::<SignedMessage>(100);
+        let gossip = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = publish_rx.recv().await {
+                gossip.publish(&message);
+            }
+        });
+
+        (publish_tx, Arc::new(Mutex::new(deliver_rx)))
+    }
+}
+
+/// Returns a sender which delivers messages into another node's `inbound` channel, tagged as
+/// coming from `from`. Used to connect gossip nodes over in-process channels.
+pub fn channel_link(
+    from: PublicKey,
+    inbound: mpsc::Sender<(PublicKey, PeerMessage)>,
+) -> mpsc::Sender<PeerMessage> {
+    let (sender, mut receiver) = mpsc::channel::<PeerMessage>(100);
+    tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if inbound.send((from, message)).await.is_err() {
+                return;
+            }
+        }
+    });
+    sender
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::ECDSAKeypair, messages::Message, vote_set::HeightVoteSet};
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_ring_delivers_each_message_once() {
+        const N: usize = 6;
+        let keypairs: Vec<_> = (0..N).map(|_| ECDSAKeypair::new()).collect();
+        let mut inbound_tx = Vec::new();
+        let mut inbound_rx = Vec::new();
+        for _ in 0..N {
+            let (tx, rx) = mpsc::channel(100);
+            inbound_tx.push(tx);
+            inbound_rx.push(Arc::new(Mutex::new(rx)));
+        }
+
+        // Each node is only linked to its two neighbours on the ring.
+        let mut nodes = Vec::new();
+        for (i, rx) in inbound_rx.into_iter().enumerate() {
+            let gossip = Gossip::new(GossipConfig::default(), "test-chain".to_string());
+            for j in [(i + 1) % N, (i + N - 1) % N] {
+                let from = keypairs[i].get_public_key();
+                gossip.add_peer(
+                    keypairs[j].get_public_key(),
+                    channel_link(from, inbound_tx[j].clone()),
+                );
+            }
+            nodes.push(gossip.start(rx));
+        }
+
+        let message = SignedMessage::new(
+            Message::Prevote { height: 0, round: 1, value: Some("v".to_string()) },
+            "test-chain",
+            &keypairs[0],
+        );
+        nodes[0].0.send(message.clone()).await.unwrap();
+
+        for (_, receiver) in &nodes[1..] {
+            let mut receiver = receiver.lock().await;
+            let received = timeout(Duration::from_secs(1), receiver.recv()).await.unwrap().unwrap();
+            assert_eq!(received.hash(), message.hash());
+            // No duplicates arrive, even though the message travels both ways around the ring.
+            assert!(timeout(Duration::from_millis(100), receiver.recv()).await.is_err());
+        }
+        // The publisher doesn't get its own message echoed back.
+        assert!(nodes[0].1.lock().await.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_vote_set_bits_sends_missing_votes() {
+        let keypairs: Vec<_> = (0..3).map(|_| ECDSAKeypair::new()).collect();
+        let validators: Vec<_> = keypairs.iter().map(|k| k.get_public_key()).collect();
+        let prevote = |keypair| {
+            let prevote = Message::Prevote { height: 0, round: 1, value: None };
+            SignedMessage::new(prevote, "test-chain", keypair)
+        };
+
+        let mut votes = HeightVoteSet::new(0, validators.clone());
+        votes.add_vote(prevote(&keypairs[0])).unwrap();
+        votes.add_vote(prevote(&keypairs[1])).unwrap();
+        let precommit = Message::Precommit { height: 0, round: 1, value: None };
+        votes.add_vote(SignedMessage::new(precommit, "test-chain", &keypairs[0])).unwrap();
+        let state = RoundState { height: 0, round: 1, step: Step::Prevote, votes };
+
+        let gossip = Gossip::new(GossipConfig::default(), "test-chain".to_string());
+        *gossip.round_state.lock().unwrap() = Some(Arc::new(StdMutex::new(state)));
+        let (tx, mut rx) = mpsc::channel(10);
+        gossip.add_peer(validators[2], tx);
+
+        // The peer only holds validator 0's prevote, so it is sent validator 1's. It is still
+        // prevoting, so isn't sent the precommit it lacks yet.
+        let mut bits = gossip.vote_set_bits().unwrap();
+        bits.prevotes.set(1, false);
+        bits.precommits.set(0, false);
+        gossip.handle_vote_set_bits(validators[2], &bits);
+
+        match rx.try_recv().unwrap() {
+            PeerMessage::Consensus(vote) => assert_eq!(vote.sender, validators[1]),
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+
+        // Once precommitting, it is.
+        bits.prevotes.set(1, true);
+        bits.step = Step::Precommit;
+        gossip.handle_vote_set_bits(validators[2], &bits);
+        match rx.try_recv().unwrap() {
+            PeerMessage::Consensus(vote) => {
+                assert!(matches!(vote.body, Message::Precommit { .. }));
+                assert_eq!(vote.sender, validators[0]);
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+}
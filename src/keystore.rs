@@ -0,0 +1,442 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::AccountConfig,
+    crypto::{CryptoError, KeyType, Keypair, PublicKey},
+};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed keystore: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("unsupported keystore: {0}")]
+    Unsupported(String),
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("invalid hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("invalid key: {0}")]
+    Key(#[from] CryptoError),
+    #[error("no account named {0}")]
+    NotFound(String),
+    #[error("an account named {0} already exists")]
+    Exists(String),
+    #[error("invalid account name {0:?}, use letters, digits, '-', '_' and '.'")]
+    InvalidName(String),
+    #[error("the keystore decrypts to a key other than its public key {0}")]
+    PubkeyMismatch(String),
+}
+
+/// The cost of deriving the encryption key from a passphrase with scrypt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    /// The CPU/memory cost, a power of two.
+    pub n: u32,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// The parameters Ethereum clients use by default, taking about a second and 256MB.
+    fn default() -> Self {
+        ScryptParams { n: 1 << 18, r: 8, p: 1 }
+    }
+}
+
+impl ScryptParams {
+    /// Cheap parameters, for tests and throwaway accounts.
+    pub fn light() -> Self {
+        ScryptParams { n: 1 << 12, r: 8, p: 6 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct KdfParams {
+    dklen: usize,
+    #[serde(flatten)]
+    scrypt: ScryptParams,
+    #[serde(with = "hex")]
+    salt: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CipherParams {
+    #[serde(with = "hex")]
+    iv: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CryptoParams {
+    cipher: String,
+    cipherparams: CipherParams,
+    #[serde(with = "hex")]
+    ciphertext: Vec<u8>,
+    kdf: String,
+    kdfparams: KdfParams,
+    #[serde(with = "hex")]
+    mac: Vec<u8>,
+}
+
+/// An account's secret key, encrypted with a passphrase. The format is Ethereum's keystore v3: the
+/// key is encrypted with AES-128-CTR under a key derived from the passphrase with scrypt, and
+/// authenticated by the Keccak256 hash of the second half of the derived key and the ciphertext.
+/// The key type and public key are stored alongside in the clear.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub id: String,
+    #[serde(default)]
+    pub key_type: KeyType,
+    pub pubkey: String,
+    crypto: CryptoParams,
+}
+
+/// The contents of an account file: an encrypted keystore, or a plaintext account as written
+/// before keystores were introduced.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum AccountFile {
+    Encrypted(Keystore),
+    Plaintext(AccountConfig),
+}
+
+impl AccountFile {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// A random (version 4) UUID.
+fn uuid_v4() -> String {
+    let mut b = random_bytes::<16>();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[..8], &h[8..12], &h[12..16], &h[16..20], &h[20..])
+}
+
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> Result<Vec<u8>, Error> {
+    let ScryptParams { n, r, p } = kdf.scrypt;
+    if !n.is_power_of_two() || kdf.dklen != 32 {
+        return Err(Error::Unsupported("scrypt parameters".to_string()));
+    }
+    let params = scrypt::Params::new(n.trailing_zeros() as u8, r, p, kdf.dklen)
+        .map_err(|_| Error::Unsupported("scrypt parameters".to_string()))?;
+    let mut key = vec![0u8; kdf.dklen];
+    scrypt::scrypt(passphrase.as_bytes(), &kdf.salt, &params, &mut key)
+        .expect("output length matches the parameters");
+    Ok(key)
+}
+
+fn mac(derived_key: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    Keccak256::new().chain_update(&derived_key[16..32]).chain_update(ciphertext).finalize().to_vec()
+}
+
+impl Keystore {
+    /// Encrypts a keypair's secret key with a passphrase, using a fresh salt and IV.
+    pub fn encrypt(keypair: &Keypair, passphrase: &str, params: ScryptParams) -> Self {
+        let kdfparams =
+            KdfParams { dklen: 32, scrypt: params, salt: random_bytes::<32>().to_vec() };
+        let derived_key = derive_key(passphrase, &kdfparams).expect("valid scrypt parameters");
+        let iv = random_bytes::<16>();
+        let mut ciphertext = hex::decode(keypair.get_secret_key()).expect("secret keys are hex");
+        Aes128Ctr::new(derived_key[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+        Keystore {
+            version: 3,
+            id: uuid_v4(),
+            key_type: keypair.key_type(),
+            pubkey: keypair.get_public_key().to_string(),
+            crypto: CryptoParams {
+                cipher: "aes-128-ctr".to_string(),
+                cipherparams: CipherParams { iv: iv.to_vec() },
+                mac: mac(&derived_key, &ciphertext),
+                ciphertext,
+                kdf: "scrypt".to_string(),
+                kdfparams,
+            },
+        }
+    }
+
+    /// Decrypts the keypair. Fails with `WrongPassphrase` if the MAC doesn't match.
+    pub fn decrypt(&self, passphrase: &str) -> Result<Keypair, Error> {
+        if self.version != 3 {
+            return Err(Error::Unsupported(format!("version {}", self.version)));
+        }
+        let crypto = &self.crypto;
+        if crypto.kdf != "scrypt" {
+            return Err(Error::Unsupported(format!("kdf {}", crypto.kdf)));
+        }
+        if crypto.cipher != "aes-128-ctr" || crypto.cipherparams.iv.len() != 16 {
+            return Err(Error::Unsupported(format!("cipher {}", crypto.cipher)));
+        }
+        let derived_key = derive_key(passphrase, &crypto.kdfparams)?;
+        if mac(&derived_key, &crypto.ciphertext) != crypto.mac {
+            return Err(Error::WrongPassphrase);
+        }
+
+        let mut secret_key = crypto.ciphertext.clone();
+        Aes128Ctr::new(derived_key[..16].into(), crypto.cipherparams.iv.as_slice().into())
+            .apply_keystream(&mut secret_key);
+        let keypair = Keypair::new_from_privatekey(self.key_type, &hex::encode(secret_key))?;
+        if self.pubkey.parse::<PublicKey>()? != keypair.get_public_key() {
+            return Err(Error::PubkeyMismatch(self.pubkey.clone()));
+        }
+        Ok(keypair)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the keystore to `path`, readable only by its owner. The keystore is written to a
+    /// temporary file beside it and renamed over the old one once on disk, so a crash or a full
+    /// disk never leaves an account without its key.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        // A temporary file left by an earlier crash keeps its permissions, so start afresh.
+        match std::fs::remove_file(&tmp) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        // Make the rename itself durable.
+        #[cfg(unix)]
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// An account in a keyring: its name, and its keystore or why the keystore couldn't be read.
+pub type KeyringEntry = (String, Result<Keystore, Error>);
+
+/// A directory of keystores, one for each named account, saved as `<name>.json`.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+impl Keyring {
+    /// Opens the keyring in `dir`, creating the directory if it doesn't exist.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Keyring { dir })
+    }
+
+    /// The file the keystore of an account is saved in.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// The accounts in the keyring, sorted by name. A keystore which can't be read doesn't hide the
+    /// others, and is listed with the error it failed with.
+    pub fn list(&self) -> Result<Vec<KeyringEntry>, Error> {
+        let mut accounts = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()?.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            if path.is_file() && valid_name(name) {
+                accounts.push((name.to_string(), Keystore::load(&path)));
+            }
+        }
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(accounts)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Keystore, Error> {
+        let path = self.checked_path(name)?;
+        if !path.exists() {
+            return Err(Error::NotFound(name.to_string()));
+        }
+        Keystore::load(&path)
+    }
+
+    /// Adds an account, refusing to replace one with the same name.
+    pub fn add(&self, name: &str, keystore: &Keystore) -> Result<PathBuf, Error> {
+        let path = self.checked_path(name)?;
+        if path.exists() {
+            return Err(Error::Exists(name.to_string()));
+        }
+        keystore.save(&path)?;
+        Ok(path)
+    }
+
+    /// Replaces the keystore of an existing account, as when its passphrase is changed. The old
+    /// keystore is only replaced once the new one is on disk.
+    pub fn update(&self, name: &str, keystore: &Keystore) -> Result<PathBuf, Error> {
+        let path = self.checked_path(name)?;
+        if !path.exists() {
+            return Err(Error::NotFound(name.to_string()));
+        }
+        keystore.save(&path)?;
+        Ok(path)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        let path = self.checked_path(name)?;
+        if !path.exists() {
+            return Err(Error::NotFound(name.to_string()));
+        }
+        Ok(std::fs::remove_file(path)?)
+    }
+
+    fn checked_path(&self, name: &str) -> Result<PathBuf, Error> {
+        if !valid_name(name) {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        Ok(self.path(name))
+    }
+}
+
+/// Account names become file names, so may not contain separators or start with a dot.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() &&
+        !name.starts_with('.') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        for key_type in [KeyType::Secp256k1, KeyType::Ed25519] {
+            let keypair = Keypair::generate(key_type);
+            let keystore = Keystore::encrypt(&keypair, "hunter2", ScryptParams::light());
+            assert_eq!(keystore.pubkey, keypair.get_public_key().to_string());
+
+            // The keystore survives a round trip through JSON, and decrypts to the same key.
+            let json = serde_json::to_string(&keystore).unwrap();
+            let keystore: Keystore = serde_json::from_str(&json).unwrap();
+            let decrypted = keystore.decrypt("hunter2").unwrap();
+            assert_eq!(decrypted.get_secret_key(), keypair.get_secret_key());
+            assert!(matches!(keystore.decrypt("hunter3"), Err(Error::WrongPassphrase)));
+
+            // A keystore claiming someone else's public key is refused.
+            let other = Keypair::generate(key_type).get_public_key().to_string();
+            let keystore = Keystore { pubkey: other, ..keystore };
+            assert!(matches!(keystore.decrypt("hunter2"), Err(Error::PubkeyMismatch(_))));
+        }
+    }
+
+    #[test]
+    fn test_save_replaces_keystore() {
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", std::process::id()));
+        let keypair = Keypair::generate(KeyType::Ed25519);
+        let old = Keystore::encrypt(&keypair, "hunter2", ScryptParams::light());
+        let new = Keystore::encrypt(&keypair, "hunter3", ScryptParams::light());
+        old.save(&path).unwrap();
+        // A stale temporary file doesn't lend the keystore its permissions.
+        std::fs::write(path.with_extension("json.tmp"), "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let stale = std::fs::Permissions::from_mode(0o644);
+            std::fs::set_permissions(path.with_extension("json.tmp"), stale).unwrap();
+        }
+        new.save(&path).unwrap();
+        assert_eq!(Keystore::load(&path).unwrap(), new);
+        assert!(!path.with_extension("json.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_known_answer() {
+        // Computed independently of this module, with the parameters of `ScryptParams::light`.
+        let keystore: Keystore = serde_json::from_str(
+            r#"{
+                "crypto": {
+                    "cipher": "aes-128-ctr",
+                    "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+                    "ciphertext": "584f4eb2783472ec149b334c22d77ddbf118a0783ea24e66ca8a7883cd2b4bf5",
+                    "kdf": "scrypt",
+                    "kdfparams": {
+                        "dklen": 32,
+                        "n": 4096,
+                        "p": 6,
+                        "r": 8,
+                        "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+                    },
+                    "mac": "860ad3ce9642f99d709abc696e25ede7cd35daf6a6041e975d14c53dae118886"
+                },
+                "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+                "pubkey": "secp256k1:0332d87c5cd4b31d81c5b010af42a2e413af253dc3a91bd3d53c6b2c45291c3de7",
+                "version": 3
+            }"#,
+        )
+        .unwrap();
+        let keypair = keystore.decrypt("testpassword").unwrap();
+        assert_eq!(
+            keypair.get_secret_key(),
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+        );
+    }
+
+    #[test]
+    fn test_keyring() {
+        let dir = std::env::temp_dir().join(format!("keyring-{}", std::process::id()));
+        let keyring = Keyring::open(&dir).unwrap();
+        let keypair = Keypair::generate(KeyType::Ed25519);
+        let keystore = Keystore::encrypt(&keypair, "hunter2", ScryptParams::light());
+
+        keyring.add("validator-1", &keystore).unwrap();
+        keyring.add("alice", &keystore).unwrap();
+        assert!(matches!(keyring.add("alice", &keystore), Err(Error::Exists(_))));
+        assert!(matches!(keyring.add("../alice", &keystore), Err(Error::InvalidName(_))));
+        let names: Vec<_> = keyring.list().unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["alice", "validator-1"]);
+        assert_eq!(keyring.get("alice").unwrap(), keystore);
+
+        // A malformed keystore is listed with its error, beside the others.
+        std::fs::write(keyring.path("broken"), "{").unwrap();
+        let accounts = keyring.list().unwrap();
+        assert_eq!(accounts.len(), 3);
+        assert!(matches!(accounts[1], (ref name, Err(Error::Malformed(_))) if name == "broken"));
+        assert_eq!(accounts[2].1.as_ref().unwrap(), &keystore);
+
+        let other = Keystore::encrypt(&keypair, "hunter3", ScryptParams::light());
+        keyring.update("validator-1", &other).unwrap();
+        assert_eq!(keyring.get("validator-1").unwrap(), other);
+        assert!(matches!(keyring.update("bob", &other), Err(Error::NotFound(_))));
+
+        keyring.delete("alice").unwrap();
+        assert!(matches!(keyring.get("alice"), Err(Error::NotFound(_))));
+        assert!(matches!(keyring.delete("alice"), Err(Error::NotFound(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
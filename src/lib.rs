@@ -1,12 +1,32 @@
+pub mod addrbook;
 pub mod algos;
+#[cfg(feature = "bls")]
+pub mod bls;
+pub mod codec;
+#[cfg(feature = "bls")]
+pub mod commit;
 pub mod config;
 pub mod crypto;
+pub mod decision_log;
+pub mod error;
 pub mod events;
+pub mod gossip;
+pub mod keystore;
 pub mod messages;
+pub mod metrics;
+pub mod p2p;
 pub mod params;
+pub mod peers;
+pub mod pex;
+pub mod privval;
 pub mod process;
 pub mod rpc_client;
 pub mod rpc_server;
+pub mod secret_connection;
+pub mod verification;
+pub mod vote_set;
+
+pub use error::{Error, Result};
 
 #[cfg(test)]
 mod tests {
@@ -19,7 +39,7 @@ mod tests {
         let data = b"gm tendermint";
 
         let signature = keypair.sign(data);
-        assert!(verify_signature(data, &signature.to_inner(), keypair.get_public_key()));
+        assert!(verify_signature(data, &signature, keypair.get_public_key()));
 
         println!("Signature verified successfully!");
     }
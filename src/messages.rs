@@ -1,14 +1,79 @@
-use crate::crypto::{verify_signature, Keypair, PublicKey, Signature};
+use crate::{
+    crypto::{PublicKey, Signature, Signer, Verifier},
+    pex::PexAddr,
+    vote_set::BitArray,
+};
 use serde::{Deserialize, Serialize};
+use serde_canonical_json::CanonicalFormatter;
+use sha3::{Digest, Keccak256};
 
 // Define message types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Message {
-    Propose { round: u64, value: String },
-    Prevote { round: u64, value: Option<String> },
-    Precommit { round: u64, value: Option<String> },
+    Propose { height: u64, round: u64, value: String },
+    Prevote { height: u64, round: u64, value: Option<String> },
+    Precommit { height: u64, round: u64, value: Option<String> },
 }
 
+impl Message {
+    /// The step of a round in which the message is sent.
+    pub fn step(&self) -> Step {
+        match self {
+            Message::Propose { .. } => Step::Propose,
+            Message::Prevote { .. } => Step::Prevote,
+            Message::Precommit { .. } => Step::Precommit,
+        }
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Propose { .. } => MessageType::Propose,
+            Message::Prevote { .. } => MessageType::Prevote,
+            Message::Precommit { .. } => MessageType::Precommit,
+        }
+    }
+
+    /// Returns the bytes signed for this message on the chain `chain_id`.
+    ///
+    /// These are the canonical JSON encoding (keys sorted, no whitespace) of the chain id, the
+    /// message type tag, height, round and value. Including the chain id means a message signed
+    /// for one network is rejected by every other.
+    pub fn sign_bytes(&self, chain_id: &str) -> Vec<u8> {
+        let (height, round, value) = match self {
+            Message::Propose { height, round, value } => (*height, *round, Some(value)),
+            Message::Prevote { height, round, value } |
+            Message::Precommit { height, round, value } => (*height, *round, value.as_ref()),
+        };
+        let canonical = CanonicalMessage {
+            chain_id,
+            message_type: self.message_type().tag(),
+            height,
+            round,
+            value: value.map(String::as_str),
+        };
+        to_canonical_json(&canonical)
+    }
+}
+
+/// Encodes a value as canonical JSON: object keys sorted and no whitespace, so the same value
+/// always produces the same bytes.
+pub(crate) fn to_canonical_json<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut ser = serde_json::Serializer::with_formatter(Vec::new(), CanonicalFormatter::new());
+    value.serialize(&mut ser).expect("value serializes");
+    ser.into_inner()
+}
+
+#[derive(Serialize)]
+struct CanonicalMessage<'a> {
+    chain_id: &'a str,
+    #[serde(rename = "type")]
+    message_type: u8,
+    height: u64,
+    round: u64,
+    value: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageType {
     Propose,
     Prevote,
@@ -23,6 +88,24 @@ impl MessageType {
             MessageType::Precommit => matches!(msg, Message::Precommit { .. }),
         }
     }
+
+    /// The tag identifying the message type in sign bytes. These match Tendermint's
+    /// `SignedMsgType`, and must never change.
+    pub fn tag(&self) -> u8 {
+        match self {
+            MessageType::Prevote => 1,
+            MessageType::Precommit => 2,
+            MessageType::Propose => 32,
+        }
+    }
+}
+
+/// The step of a round a process is in. Steps are ordered as they happen in a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Step {
+    Propose,
+    Prevote,
+    Precommit,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,16 +116,98 @@ pub struct SignedMessage {
 }
 
 impl SignedMessage {
-    pub fn new(message: Message, keypair: &Keypair) -> Self {
-        let sender = keypair.get_public_key();
-        let sz: String = serde_json::to_string(&message).unwrap();
-        let signature = keypair.sign(sz.as_bytes());
+    /// Signs a message for the chain `chain_id`.
+    pub fn new(message: Message, chain_id: &str, keypair: &impl Signer) -> Self {
+        let sender = keypair.public_key();
+        let signature = keypair.sign(&message.sign_bytes(chain_id));
 
         SignedMessage { body: message, sender, signature }
     }
 
-    pub fn verify(&self) -> bool {
-        let sz: String = serde_json::to_string(&self.body).unwrap();
-        verify_signature(sz.as_bytes(), &self.signature.to_inner(), self.sender)
+    /// Verifies the message was signed by its sender for the chain `chain_id`.
+    pub fn verify(&self, chain_id: &str) -> bool {
+        self.sender.verify(&self.body.sign_bytes(chain_id), &self.signature)
+    }
+
+    /// Returns the Keccak256 hash of the signed message, which identifies it during gossip.
+    pub fn hash(&self) -> [u8; 32] {
+        let sz: String = serde_json::to_string(self).unwrap();
+        Keccak256::digest(sz.as_bytes()).into()
+    }
+}
+
+/// A summary of the votes a node holds for its current round, which peers use to send it the
+/// votes it is missing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteSetBits {
+    pub height: u64,
+    pub round: u64,
+    pub step: Step,
+    pub prevotes: BitArray,
+    pub precommits: BitArray,
+}
+
+/// Messages exchanged between peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PeerMessage {
+    /// A signed consensus message, relayed by gossip.
+    Consensus(SignedMessage),
+    /// A summary of the votes held by the sender.
+    VoteSetBits(VoteSetBits),
+    /// Asks the receiver for the addresses of the peers it knows.
+    PexRequest,
+    /// The addresses of some of the peers the sender knows.
+    PexAddrs(Vec<PexAddr>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ECDSAKeypair;
+
+    const CHAIN_ID: &str = "test-chain";
+
+    #[test]
+    fn test_sign_bytes_golden() {
+        let propose = Message::Propose { height: 3, round: 1, value: "abc".to_string() };
+        assert_eq!(
+            String::from_utf8(propose.sign_bytes(CHAIN_ID)).unwrap(),
+            r#"{"chain_id":"test-chain","height":3,"round":1,"type":32,"value":"abc"}"#
+        );
+        let prevote = Message::Prevote { height: 3, round: 1, value: Some("abc".to_string()) };
+        assert_eq!(
+            String::from_utf8(prevote.sign_bytes(CHAIN_ID)).unwrap(),
+            r#"{"chain_id":"test-chain","height":3,"round":1,"type":1,"value":"abc"}"#
+        );
+        let precommit = Message::Precommit { height: 18446744073709551615, round: 0, value: None };
+        assert_eq!(
+            String::from_utf8(precommit.sign_bytes(CHAIN_ID)).unwrap(),
+            r#"{"chain_id":"test-chain","height":18446744073709551615,"round":0,"type":2,"value":null}"#
+        );
+    }
+
+    #[test]
+    fn test_signature_golden() {
+        // Signatures are deterministic (RFC 6979), so the signature over the sign bytes is fixed.
+        let keypair = ECDSAKeypair::new_from_privatekey(
+            "e4195f507ae6d4d8508c1afa9fc148d983bc9e4bf555805849deec1eef5ecf71",
+        )
+        .unwrap();
+        let message = Message::Prevote { height: 3, round: 1, value: Some("abc".to_string()) };
+        let signed = SignedMessage::new(message, CHAIN_ID, &keypair);
+        assert_eq!(
+            serde_json::to_value(signed.signature).unwrap(),
+            "secp256k1:3044022069fa8077f37afe73ffe5f9d6c8f93468b0e4e72d338a460c40c5e346bb2c4a2b\
+             02204d86892473f6b15ce88c4514198b1190558738ff8e047a17f200ef96c3f0077a"
+        );
+    }
+
+    #[test]
+    fn test_verify_checks_chain_id() {
+        let keypair = ECDSAKeypair::new();
+        let message = Message::Prevote { height: 3, round: 1, value: None };
+        let signed = SignedMessage::new(message, CHAIN_ID, &keypair);
+        assert!(signed.verify(CHAIN_ID));
+        assert!(!signed.verify("other-chain"));
     }
 }
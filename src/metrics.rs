@@ -0,0 +1,232 @@
+use prometheus::{
+    core::Collector, exponential_buckets, linear_buckets, Encoder, Histogram, HistogramOpts,
+    HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{sync::Mutex as StdMutex, time::Instant};
+
+use crate::{messages::Step, process::Event};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to register metric: {0}")]
+    Register(#[from] prometheus::Error),
+}
+
+/// A node's Prometheus metrics. Consensus metrics are recorded from the process's events, and
+/// the counters other components keep, such as the signatures the verifier rejects, are
+/// registered alongside them.
+pub struct Metrics {
+    registry: Registry,
+    height: IntGauge,
+    round: IntGauge,
+    rounds_per_height: Histogram,
+    step_duration: HistogramVec,
+    timeouts: IntCounterVec,
+    votes: IntCounterVec,
+    decision_latency: Histogram,
+    peers: IntGauge,
+    rpc_send_failures: IntCounterVec,
+    timings: StdMutex<Timings>,
+}
+
+/// When the current height and step started, as seen in events.
+#[derive(Default)]
+struct Timings {
+    height: Option<Instant>,
+    step: Option<(Step, Instant)>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            height: IntGauge::new("tendermint_consensus_height", "The height being decided")
+                .unwrap(),
+            round: IntGauge::new("tendermint_consensus_round", "The round of the current height")
+                .unwrap(),
+            rounds_per_height: Histogram::with_opts(
+                HistogramOpts::new(
+                    "tendermint_consensus_rounds_per_height",
+                    "The rounds taken to decide each height",
+                )
+                .buckets(linear_buckets(1.0, 1.0, 10).unwrap()),
+            )
+            .unwrap(),
+            step_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "tendermint_consensus_step_duration_seconds",
+                    "The time spent in each step of a round",
+                )
+                .buckets(exponential_buckets(0.005, 2.0, 12).unwrap()),
+                &["step"],
+            )
+            .unwrap(),
+            timeouts: IntCounterVec::new(
+                Opts::new("tendermint_consensus_timeouts_total", "Steps which timed out"),
+                &["step"],
+            )
+            .unwrap(),
+            votes: IntCounterVec::new(
+                Opts::new(
+                    "tendermint_consensus_votes_total",
+                    "Prevotes and precommits counted, the node's own included",
+                ),
+                &["step"],
+            )
+            .unwrap(),
+            decision_latency: Histogram::with_opts(
+                HistogramOpts::new(
+                    "tendermint_consensus_decision_latency_seconds",
+                    "The time from starting a height to deciding it",
+                )
+                .buckets(exponential_buckets(0.05, 2.0, 12).unwrap()),
+            )
+            .unwrap(),
+            peers: IntGauge::new("tendermint_p2p_peers", "Peers currently connected").unwrap(),
+            rpc_send_failures: IntCounterVec::new(
+                Opts::new(
+                    "tendermint_rpc_client_send_failures_total",
+                    "Messages RPC clients failed to post to an inbox",
+                ),
+                &["url"],
+            )
+            .unwrap(),
+            timings: StdMutex::new(Timings::default()),
+        };
+        let collectors: [Box<dyn Collector>; 9] = [
+            Box::new(metrics.height.clone()),
+            Box::new(metrics.round.clone()),
+            Box::new(metrics.rounds_per_height.clone()),
+            Box::new(metrics.step_duration.clone()),
+            Box::new(metrics.timeouts.clone()),
+            Box::new(metrics.votes.clone()),
+            Box::new(metrics.decision_latency.clone()),
+            Box::new(metrics.peers.clone()),
+            Box::new(metrics.rpc_send_failures.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric names are unique");
+        }
+        metrics
+    }
+
+    /// Adds a metric kept by another component, e.g. `SignatureVerifier::invalid_signatures`.
+    /// Fails if a metric of the same name is already registered.
+    pub fn register(&self, collector: impl Collector + 'static) -> Result<(), Error> {
+        Ok(self.registry.register(Box::new(collector))?)
+    }
+
+    /// The count of messages each `RpcClient` failed to post, by inbox url, to give to
+    /// `RpcClient::new`.
+    pub fn rpc_send_failures(&self) -> IntCounterVec {
+        self.rpc_send_failures.clone()
+    }
+
+    /// Updates the consensus metrics from a process's event.
+    pub fn record(&self, event: &Event) {
+        let now = Instant::now();
+        let mut timings = self.timings.lock().unwrap();
+        match event {
+            Event::NewHeight { height } => {
+                self.height.set(*height as i64);
+                timings.height = Some(now);
+                self.end_step(&mut timings, now);
+            }
+            Event::NewRound { round, .. } => {
+                self.round.set(*round as i64);
+                self.end_step(&mut timings, now);
+            }
+            Event::NewStep { step, .. } => {
+                self.end_step(&mut timings, now);
+                timings.step = Some((*step, now));
+            }
+            Event::Vote { step, .. } => self.votes.with_label_values(&[step_label(*step)]).inc(),
+            Event::Timeout { step, .. } => {
+                self.timeouts.with_label_values(&[step_label(*step)]).inc()
+            }
+            Event::Decision { round, .. } => {
+                self.rounds_per_height.observe(*round as f64);
+                if let Some(start) = timings.height.take() {
+                    self.decision_latency.observe((now - start).as_secs_f64());
+                }
+                self.end_step(&mut timings, now);
+            }
+            _ => {}
+        }
+    }
+
+    fn end_step(&self, timings: &mut Timings, now: Instant) {
+        if let Some((step, start)) = timings.step.take() {
+            let duration = (now - start).as_secs_f64();
+            self.step_duration.with_label_values(&[step_label(step)]).observe(duration);
+        }
+    }
+
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.set(peers as i64);
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).expect("metrics encode");
+        String::from_utf8(buffer).expect("metrics are utf-8")
+    }
+}
+
+/// The label of a step in metrics.
+fn step_label(step: Step) -> &'static str {
+    match step {
+        Step::Propose => "propose",
+        Step::Prevote => "prevote",
+        Step::Precommit => "precommit",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_events() {
+        let metrics = Metrics::new();
+        metrics.record(&Event::NewHeight { height: 3 });
+        metrics.record(&Event::NewRound { height: 3, round: 1, proposer: 0 });
+        metrics.record(&Event::NewStep { height: 3, round: 1, step: Step::Propose });
+        metrics.record(&Event::Timeout { height: 3, round: 1, step: Step::Propose });
+        metrics.record(&Event::NewStep { height: 3, round: 1, step: Step::Prevote });
+        metrics.record(&Event::Decision {
+            height: 4,
+            round: 1,
+            value: "a".to_string(),
+            from: 0,
+            #[cfg(feature = "bls")]
+            certificate: None,
+        });
+
+        let text = metrics.encode();
+        assert!(text.contains("tendermint_consensus_height 3"));
+        assert!(text.contains("tendermint_consensus_timeouts_total{step=\"propose\"} 1"));
+        assert!(
+            text.contains("tendermint_consensus_step_duration_seconds_count{step=\"prevote\"} 1")
+        );
+        assert!(text.contains("tendermint_consensus_decision_latency_seconds_count 1"));
+
+        // Clients sharing the counter are told apart by their url.
+        metrics.rpc_send_failures().with_label_values(&["http://a/inbox"]).inc();
+        metrics.rpc_send_failures().with_label_values(&["http://b/inbox"]).inc();
+        let text = metrics.encode();
+        assert!(
+            text.contains("tendermint_rpc_client_send_failures_total{url=\"http://a/inbox\"} 1")
+        );
+        assert!(
+            text.contains("tendermint_rpc_client_send_failures_total{url=\"http://b/inbox\"} 1")
+        );
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+    time::{sleep, Duration},
+};
+
+use crate::{
+    addrbook::{AddressBook, Misbehaviour},
+    codec::{self, Encoding, WireMessage},
+    crypto::{Keypair, PublicKey},
+    peers::{Hello, PeerState, PeerTable, SignedHello},
+    secret_connection::{self, SecretConnection},
+};
+
+/// How long a client waits before redialing a peer it failed to reach.
+const REDIAL_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Connection(#[from] secret_connection::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("peer {0} is banned")]
+    Banned(PublicKey),
+    #[error("too many inbound peers")]
+    TooManyPeers,
+    #[error("dialed peer identified as {0}, not the expected validator")]
+    UnexpectedPeer(PublicKey),
+    #[error("peer {0} is neither a validator nor an allowed peer")]
+    UnknownPeer(PublicKey),
+    #[error("peer sent an invalid hello")]
+    InvalidHello,
+    #[error("peer is on chain {0}")]
+    ChainIdMismatch(String),
+    #[error("peer has genesis {}", hex::encode(.0))]
+    GenesisMismatch([u8; 32]),
+    #[error("malformed message: {0}")]
+    Malformed(codec::Error),
+}
+
+/// The identity and chain a node presents to its peers.
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub keypair: Keypair,
+    pub chain_id: String,
+    /// The hash of the chain's genesis. Peers with a different genesis are rejected.
+    pub genesis_hash: [u8; 32],
+    /// The address the node accepts peer connections on.
+    pub listen_addr: SocketAddr,
+    /// How messages sent to peers are encoded. Messages from peers are accepted in any encoding.
+    pub encoding: Encoding,
+    /// The identities the node connects with: the validators' node keys, and the seeds and full
+    /// nodes allowed by its config. Connections with any other peer are refused.
+    pub allowed: Vec<PublicKey>,
+}
+
+fn check_peer(node: &NodeInfo, pubkey: PublicKey) -> Result<(), Error> {
+    if node.allowed.contains(&pubkey) {
+        Ok(())
+    } else {
+        Err(Error::UnknownPeer(pubkey))
+    }
+}
+
+/// Exchanges signed hellos over a freshly established connection, returning the peer's hello.
+async fn exchange_hello<S>(
+    conn: &mut SecretConnection<S>,
+    node: &NodeInfo,
+    latest_height: u64,
+) -> Result<Hello, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let hello = Hello {
+        pubkey: node.keypair.get_public_key(),
+        chain_id: node.chain_id.clone(),
+        genesis_hash: node.genesis_hash,
+        listen_addr: node.listen_addr,
+        latest_height,
+    };
+    let hello = SignedHello::new(hello, &node.keypair);
+    conn.write_msg(&serde_json::to_vec(&hello).expect("hello serializes")).await?;
+
+    let remote = conn.read_msg().await?;
+    let remote: SignedHello =
+        serde_json::from_slice(&remote).map_err(|err| Error::Malformed(err.into()))?;
+    if !remote.verify() || remote.hello.pubkey != conn.remote_pubkey() {
+        return Err(Error::InvalidHello);
+    }
+    if remote.hello.chain_id != node.chain_id {
+        return Err(Error::ChainIdMismatch(remote.hello.chain_id));
+    }
+    if remote.hello.genesis_hash != node.genesis_hash {
+        return Err(Error::GenesisMismatch(remote.hello.genesis_hash));
+    }
+    Ok(remote.hello)
+}
+
+/// Accepts authenticated connections from peers and forwards the messages they send to a
+/// channel, tagged with the identity of the peer they arrived from. Connections from banned or
+/// unknown peers, or from another chain or genesis, are dropped during the handshake, as are
+/// connections beyond `max_inbound`. The listen address of every accepted peer is added to the
+/// address book.
+pub struct PeerServer<T> {
+    node: NodeInfo,
+    table: Arc<PeerTable<T>>,
+    book: Arc<AddressBook>,
+    max_inbound: usize,
+    inbound: Arc<AtomicUsize>,
+    sender: mpsc::Sender<(PublicKey, T)>,
+    receiver: Arc<Mutex<mpsc::Receiver<(PublicKey, T)>>>,
+}
+
+impl<T> PeerServer<T>
+where
+    T: Send + 'static + Clone + WireMessage + Debug,
+{
+    pub fn new(
+        node: NodeInfo,
+        table: Arc<PeerTable<T>>,
+        book: Arc<AddressBook>,
+        max_inbound: usize,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(100);
+        PeerServer {
+            node,
+            table,
+            book,
+            max_inbound,
+            inbound: Arc::new(AtomicUsize::new(0)),
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    pub fn get_receiver(&self) -> Arc<Mutex<mpsc::Receiver<(PublicKey, T)>>> {
+        self.receiver.clone()
+    }
+
+    /// Listens for peer connections until the listener fails.
+    pub async fn run(&self) -> std::io::Result<()> {
+        let listener = TcpListener::bind(self.node.listen_addr).await?;
+        tracing::info!(addr = %self.node.listen_addr, "Peer server listening");
+
+        loop {
+            let (stream, remote_addr) = listener.accept().await?;
+            let slot = match InboundSlot::acquire(&self.inbound, self.max_inbound) {
+                Some(slot) => slot,
+                None => {
+                    tracing::warn!(
+                        peer = %remote_addr,
+                        error = %Error::TooManyPeers,
+                        "Peer connection refused"
+                    );
+                    continue;
+                }
+            };
+            let node = self.node.clone();
+            let table = self.table.clone();
+            let book = self.book.clone();
+            let sender = self.sender.clone();
+
+            tokio::spawn(async move {
+                let result = handle_inbound(stream, remote_addr, node, table, book, sender).await;
+                drop(slot);
+                if let Err(err) = result {
+                    tracing::info!(peer = %remote_addr, error = %err, "Peer connection closed");
+                }
+            });
+        }
+    }
+}
+
+/// Counts an inbound connection for as long as it is held.
+struct InboundSlot(Arc<AtomicUsize>);
+
+impl InboundSlot {
+    fn acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max).then_some(n + 1))
+            .ok()
+            .map(|_| InboundSlot(count.clone()))
+    }
+}
+
+impl Drop for InboundSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn handle_inbound<T>(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    node: NodeInfo,
+    table: Arc<PeerTable<T>>,
+    book: Arc<AddressBook>,
+    sender: mpsc::Sender<(PublicKey, T)>,
+) -> Result<(), Error>
+where
+    T: Send + 'static + Clone + WireMessage,
+{
+    let mut conn = SecretConnection::new(stream, &node.keypair).await?;
+    let remote = conn.remote_pubkey();
+    if book.is_banned(&remote) {
+        return Err(Error::Banned(remote));
+    }
+    // Unknown peers are refused before they can send anything, so they never enter the book.
+    check_peer(&node, remote)?;
+    let hello = match exchange_hello(&mut conn, &node, table.latest_height()).await {
+        Ok(hello) => hello,
+        Err(err) => {
+            if matches!(err, Error::InvalidHello | Error::Malformed(_)) {
+                book.report(&remote, Misbehaviour::InvalidHello);
+            }
+            return Err(err);
+        }
+    };
+    // Peers listening on all interfaces are reached on the address they connected from.
+    let mut listen_addr = hello.listen_addr;
+    if listen_addr.ip().is_unspecified() {
+        listen_addr.set_ip(remote_addr.ip());
+    }
+    book.add(remote, listen_addr);
+    table.on_hello(hello);
+
+    loop {
+        let bytes = conn.read_msg().await?;
+        let message: T = match codec::decode(&bytes) {
+            Ok(message) => message,
+            Err(err) => {
+                book.report(&remote, Misbehaviour::MalformedMessage);
+                return Err(Error::Malformed(err));
+            }
+        };
+        if book.is_banned(&remote) {
+            return Err(Error::Banned(remote));
+        }
+        table.mark_seen(&remote);
+        if sender.send((remote, message)).await.is_err() {
+            // The receiving side has shut down.
+            return Ok(());
+        }
+    }
+}
+
+/// Dials a peer over an authenticated connection and sends it every message written to the
+/// client's channel. The peer is registered in the peer table, which tracks whether the connection
+/// is up; the connection is re-established if it drops, and a message which failed to send is sent
+/// again once it is. Dial attempts are recorded in the address book, and the client stops once the
+/// peer is banned or every sender for it is dropped.
+pub struct PeerClient<T> {
+    node: NodeInfo,
+    table: Arc<PeerTable<T>>,
+    book: Arc<AddressBook>,
+    pubkey: PublicKey,
+    addr: SocketAddr,
+    sender: mpsc::Sender<T>,
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> PeerClient<T>
+where
+    T: Send + 'static + Clone + WireMessage + Debug,
+{
+    pub fn new(
+        capacity: usize,
+        node: NodeInfo,
+        table: Arc<PeerTable<T>>,
+        book: Arc<AddressBook>,
+        pubkey: PublicKey,
+        addr: SocketAddr,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        table.insert(pubkey, addr, sender.clone());
+        PeerClient { node, table, book, pubkey, addr, sender, receiver }
+    }
+
+    pub fn get_sender(&self) -> mpsc::Sender<T> {
+        self.sender.clone()
+    }
+
+    /// Spawns the task which dials the peer and writes outgoing messages to it.
+    pub fn start(self) {
+        let PeerClient { node, table, book, pubkey, addr, mut receiver, .. } = self;
+
+        tokio::spawn(async move {
+            // A message which failed to send, and is sent first on the next connection.
+            let mut pending: Option<T> = None;
+            loop {
+                // The table entry is left to whoever dropped the senders, as the peer may already
+                // have been redialed by a new client.
+                if book.is_banned(&pubkey) || receiver.is_closed() {
+                    return;
+                }
+                let mut conn = match dial(&node, &table, pubkey, addr).await {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        tracing::warn!(peer = %addr, error = %err, "Failed to connect to peer");
+                        if matches!(err, Error::InvalidHello) {
+                            book.report(&pubkey, Misbehaviour::InvalidHello);
+                        }
+                        book.mark_attempt(&pubkey);
+                        table.set_state(&pubkey, PeerState::Disconnected);
+                        sleep(REDIAL_INTERVAL).await;
+                        continue;
+                    }
+                };
+                book.mark_good(&pubkey);
+                table.set_state(&pubkey, PeerState::Connected);
+
+                loop {
+                    let message = match pending.take() {
+                        Some(message) => message,
+                        None => match receiver.recv().await {
+                            Some(message) => message,
+                            None => break,
+                        },
+                    };
+                    let bytes = codec::encode(&message, node.encoding);
+                    if let Err(err) = conn.write_msg(&bytes).await {
+                        tracing::warn!(peer = %addr, error = %err, "Failed to send message to peer");
+                        // The message is sent again once the peer is redialed.
+                        pending = Some(message);
+                        break;
+                    }
+                }
+                if !receiver.is_closed() {
+                    table.set_state(&pubkey, PeerState::Disconnected);
+                }
+            }
+        });
+    }
+}
+
+async fn dial<T>(
+    node: &NodeInfo,
+    table: &PeerTable<T>,
+    pubkey: PublicKey,
+    addr: SocketAddr,
+) -> Result<SecretConnection<TcpStream>, Error>
+where
+    T: Send + 'static + Clone,
+{
+    let stream = TcpStream::connect(addr).await?;
+    let mut conn = SecretConnection::new(stream, &node.keypair).await?;
+    if conn.remote_pubkey() != pubkey {
+        return Err(Error::UnexpectedPeer(conn.remote_pubkey()));
+    }
+    check_peer(node, pubkey)?;
+    let hello = exchange_hello(&mut conn, node, table.latest_height()).await?;
+    table.on_hello(hello);
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addrbook::AddressBookConfig,
+        crypto::{ECDSAKeypair, KeyType},
+        messages::PeerMessage,
+    };
+
+    fn node_info(keypair: &Keypair, allowed: Vec<PublicKey>) -> NodeInfo {
+        NodeInfo {
+            keypair: keypair.clone(),
+            chain_id: "test-chain".to_string(),
+            genesis_hash: [0; 32],
+            listen_addr: "127.0.0.1:3030".parse().unwrap(),
+            encoding: Encoding::Proto,
+            allowed,
+        }
+    }
+
+    /// Accepts peer connections as `node` on a free port.
+    async fn serve(
+        node: NodeInfo,
+    ) -> (SocketAddr, Arc<AddressBook>, mpsc::Receiver<(PublicKey, PeerMessage)>) {
+        let table = Arc::new(PeerTable::<PeerMessage>::new());
+        let book =
+            Arc::new(AddressBook::new(AddressBookConfig::default(), node.keypair.get_public_key()));
+        let (sender, receiver) = mpsc::channel(10);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn({
+            let book = book.clone();
+            async move {
+                loop {
+                    let (stream, remote_addr) = listener.accept().await.unwrap();
+                    let (node, table, book, sender) =
+                        (node.clone(), table.clone(), book.clone(), sender.clone());
+                    tokio::spawn(handle_inbound(stream, remote_addr, node, table, book, sender));
+                }
+            }
+        });
+        (addr, book, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_refuses_peers_outside_validator_set() {
+        let keypair = Keypair::generate(KeyType::Ed25519);
+        let validator: Keypair = ECDSAKeypair::new().into();
+        let stranger: Keypair = ECDSAKeypair::new().into();
+        let (addr, book, mut receiver) =
+            serve(node_info(&keypair, vec![validator.get_public_key()])).await;
+
+        // A key outside the validator set is dropped before the hello, and isn't remembered.
+        let pubkey = keypair.get_public_key();
+        let dialer = node_info(&stranger, vec![pubkey]);
+        assert!(dial(&dialer, &PeerTable::<PeerMessage>::new(), pubkey, addr).await.is_err());
+        assert!(book.get(&stranger.get_public_key()).is_none());
+
+        // Validators are let through.
+        let dialer = node_info(&validator, vec![pubkey]);
+        let mut conn = dial(&dialer, &PeerTable::<PeerMessage>::new(), pubkey, addr).await.unwrap();
+        conn.write_msg(&codec::encode(&PeerMessage::PexRequest, Encoding::Proto)).await.unwrap();
+        let (from, _) = receiver.recv().await.unwrap();
+        assert_eq!(from, validator.get_public_key());
+        assert!(book.get(&from).is_some());
+        assert_eq!(book.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_dial_non_validators() {
+        let keypair: Keypair = ECDSAKeypair::new().into();
+        let stranger = Keypair::generate(KeyType::Ed25519);
+        let (addr, _, _) = serve(node_info(&stranger, vec![keypair.get_public_key()])).await;
+
+        // However willing the peer is, a node only talks to the peers it allows.
+        let pubkey = stranger.get_public_key();
+        let result =
+            dial(&node_info(&keypair, vec![]), &PeerTable::<PeerMessage>::new(), pubkey, addr)
+                .await;
+        assert!(matches!(result, Err(Error::UnknownPeer(key)) if key == pubkey));
+    }
+}
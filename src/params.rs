@@ -1,5 +1,57 @@
-// Define the number of nodes and the maximum number of faulty nodes (f)
-// NOTE: NODES must be at least 4 if you're building a real BFT system. N >= 3F+1.
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::messages::Step;
+
+// The number of nodes the examples run.
+// NOTE: NODES must be at least 4 to tolerate a faulty node. N >= 3F+1.
 pub const NODES: usize = 5;
-pub const F: usize = 1;
-pub const QUORUM: usize = 2 * F + 1;
+
+/// The voting power a quorum needs: more than two thirds of `total_power`. Any two quorums share
+/// more than a third of the power, so they overlap in a correct validator while faulty validators
+/// hold less than a third.
+pub fn quorum_power(total_power: u64) -> u64 {
+    total_power / 3 * 2 + (total_power % 3 * 2) / 3 + 1
+}
+
+/// Parameters of the consensus algorithm, agreed on in genesis by every validator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// How long to wait for a proposal in the first round, in milliseconds.
+    pub timeout_propose_ms: u64,
+    /// How long to wait for a quorum of prevotes in the first round, in milliseconds.
+    pub timeout_prevote_ms: u64,
+    /// How long to wait for a quorum of precommits in the first round, in milliseconds.
+    pub timeout_precommit_ms: u64,
+    /// How much every timeout grows with each round after the first, in milliseconds.
+    pub timeout_delta_ms: u64,
+    /// The largest value that may be proposed, in bytes.
+    pub max_value_size: usize,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            timeout_propose_ms: 1000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_delta_ms: 0,
+            max_value_size: 1024 * 1024,
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// The timeout for a step of a round: `timeoutX(r) = timeoutX + (r - 1) * timeoutDelta`.
+    /// Rounds start at 1.
+    pub fn timeout(&self, step: Step, round: u64) -> Duration {
+        let initial = match step {
+            Step::Propose => self.timeout_propose_ms,
+            Step::Prevote => self.timeout_prevote_ms,
+            Step::Precommit => self.timeout_precommit_ms,
+        };
+        let delta = self.timeout_delta_ms.saturating_mul(round.saturating_sub(1));
+        Duration::from_millis(initial.saturating_add(delta))
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    crypto::{PublicKey, Signature, Signer, Verifier},
+    messages::to_canonical_json,
+};
+
+/// Sent by both sides of a new peer connection once the secret connection is established.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    /// The identity key of the sender.
+    pub pubkey: PublicKey,
+    /// The chain the sender is participating in.
+    pub chain_id: String,
+    /// The hash of the sender's genesis.
+    #[serde(with = "hex")]
+    pub genesis_hash: [u8; 32],
+    /// The address the sender accepts peer connections on.
+    pub listen_addr: SocketAddr,
+    /// The latest height the sender has decided.
+    pub latest_height: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedHello {
+    pub hello: Hello,
+    pub signature: Signature,
+}
+
+impl SignedHello {
+    pub fn new(hello: Hello, keypair: &impl Signer) -> Self {
+        let signature = keypair.sign(&to_canonical_json(&hello));
+
+        SignedHello { hello, signature }
+    }
+
+    pub fn verify(&self) -> bool {
+        self.hello.pubkey.verify(&to_canonical_json(&self.hello), &self.signature)
+    }
+}
+
+/// Liveness of the outbound connection to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerState {
+    /// We are trying to reach the peer.
+    Dialing,
+    /// The handshake completed and messages are being sent to the peer.
+    Connected,
+    /// The connection dropped; the peer will be redialed.
+    Disconnected,
+}
+
+/// A snapshot of what we know about a peer.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub pubkey: PublicKey,
+    /// The address we dial the peer on.
+    pub dial_addr: SocketAddr,
+    pub state: PeerState,
+    /// The most recent hello received from the peer, on either an inbound or outbound connection.
+    pub hello: Option<Hello>,
+    /// When we last received anything from the peer.
+    pub last_seen: Option<Instant>,
+}
+
+struct PeerEntry<T> {
+    info: PeerInfo,
+    sender: mpsc::Sender<T>,
+}
+
+/// The routing table of a node: every peer it knows about, their liveness, and the channel used to
+/// send them messages.
+pub struct PeerTable<T> {
+    peers: RwLock<HashMap<PublicKey, PeerEntry<T>>>,
+    latest_height: AtomicU64,
+}
+
+impl<T> Default for PeerTable<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PeerTable<T>
+where
+    T: Clone + Send + 'static,
+{
+    pub fn new() -> Self {
+        PeerTable { peers: RwLock::new(HashMap::new()), latest_height: AtomicU64::new(0) }
+    }
+
+    /// Adds a peer we are about to dial. Messages for the peer are written to `sender`.
+    pub fn insert(&self, pubkey: PublicKey, dial_addr: SocketAddr, sender: mpsc::Sender<T>) {
+        let info =
+            PeerInfo { pubkey, dial_addr, state: PeerState::Dialing, hello: None, last_seen: None };
+        self.peers.write().unwrap().insert(pubkey, PeerEntry { info, sender });
+    }
+
+    /// Removes a peer. Its client stops once the other senders for the peer are dropped.
+    pub fn remove(&self, pubkey: &PublicKey) {
+        self.peers.write().unwrap().remove(pubkey);
+    }
+
+    pub fn set_state(&self, pubkey: &PublicKey, state: PeerState) {
+        if let Some(entry) = self.peers.write().unwrap().get_mut(pubkey) {
+            entry.info.state = state;
+        }
+    }
+
+    /// Records a hello received from a peer.
+    pub fn on_hello(&self, hello: Hello) {
+        if let Some(entry) = self.peers.write().unwrap().get_mut(&hello.pubkey) {
+            entry.info.last_seen = Some(Instant::now());
+            entry.info.hello = Some(hello);
+        }
+    }
+
+    /// Records that a peer is alive.
+    pub fn mark_seen(&self, pubkey: &PublicKey) {
+        if let Some(entry) = self.peers.write().unwrap().get_mut(pubkey) {
+            entry.info.last_seen = Some(Instant::now());
+        }
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<PeerInfo> {
+        self.peers.read().unwrap().get(pubkey).map(|entry| entry.info.clone())
+    }
+
+    /// Returns a snapshot of all peers.
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.read().unwrap().values().map(|entry| entry.info.clone()).collect()
+    }
+
+    /// The latest height decided by this node, advertised in our hellos.
+    pub fn latest_height(&self) -> u64 {
+        self.latest_height.load(Ordering::Relaxed)
+    }
+
+    pub fn set_latest_height(&self, height: u64) {
+        self.latest_height.store(height, Ordering::Relaxed);
+    }
+
+    /// Sends a message to every peer. Messages for peers that are not connected are queued until
+    /// the connection is up; peers whose queue is full miss the message rather than stalling the
+    /// sender.
+    pub fn broadcast(&self, message: &T) {
+        for entry in self.peers.read().unwrap().values() {
+            let _ = entry.sender.try_send(message.clone());
+        }
+    }
+
+    /// Sends a message to a single peer. Returns false if the peer is unknown or its queue is full.
+    pub fn send(&self, pubkey: &PublicKey, message: T) -> bool {
+        match self.peers.read().unwrap().get(pubkey) {
+            Some(entry) => entry.sender.try_send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Returns a channel whose messages are broadcast to all peers. This lets the table stand in
+    /// for the fixed list of senders a `Process` is constructed with.
+    pub fn broadcaster(self: &Arc<Self>, capacity: usize) -> mpsc::Sender<T> {
+        let (sender, mut receiver) = mpsc::channel::<T>(capacity);
+        let table = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                table.broadcast(&message);
+            }
+        });
+        sender
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ECDSAKeypair;
+
+    #[tokio::test]
+    async fn test_broadcast_skips_full_peers() {
+        let table = Arc::new(PeerTable::<u64>::new());
+        let (a, b) = (ECDSAKeypair::new().get_public_key(), ECDSAKeypair::new().get_public_key());
+        let (tx_a, mut rx_a) = mpsc::channel(10);
+        let (tx_b, mut rx_b) = mpsc::channel(1);
+        table.insert(a, "127.0.0.1:1".parse().unwrap(), tx_a);
+        table.insert(b, "127.0.0.1:2".parse().unwrap(), tx_b);
+        table.set_state(&a, PeerState::Connected);
+
+        // Peer b's queue fills up after the first message, without blocking delivery to a.
+        let broadcaster = table.broadcaster(10);
+        broadcaster.send(7).await.unwrap();
+        broadcaster.send(8).await.unwrap();
+
+        assert_eq!(rx_a.recv().await, Some(7));
+        assert_eq!(rx_a.recv().await, Some(8));
+        assert_eq!(rx_b.recv().await, Some(7));
+        assert!(rx_b.try_recv().is_err());
+        assert_eq!(table.get(&b).unwrap().state, PeerState::Dialing);
+    }
+
+    #[test]
+    fn test_signed_hello() {
+        let keypair = ECDSAKeypair::new();
+        let hello = Hello {
+            pubkey: keypair.get_public_key(),
+            chain_id: "test-chain".to_string(),
+            genesis_hash: [7; 32],
+            listen_addr: "127.0.0.1:3030".parse().unwrap(),
+            latest_height: 3,
+        };
+        let mut signed = SignedHello::new(hello, &keypair);
+        assert!(signed.verify());
+
+        signed.hello.latest_height = 4;
+        assert!(!signed.verify());
+    }
+}
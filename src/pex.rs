@@ -0,0 +1,340 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    time::{interval, Duration},
+};
+
+use crate::{
+    addrbook::{AddressBook, Misbehaviour},
+    crypto::PublicKey,
+    gossip::Gossip,
+    messages::PeerMessage,
+    p2p::{NodeInfo, PeerClient},
+    peers::{PeerState, PeerTable},
+};
+
+/// The address of a peer, as shared in peer exchange. Written as `pubkey@ip:port`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PexAddr {
+    pub pubkey: PublicKey,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for PexAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pubkey, addr) =
+            s.split_once('@').ok_or_else(|| format!("expected pubkey@ip:port, got {}", s))?;
+        Ok(PexAddr {
+            pubkey: PublicKey::from_str(pubkey).map_err(|err| err.to_string())?,
+            addr: SocketAddr::from_str(addr).map_err(|err| err.to_string())?,
+        })
+    }
+}
+
+impl Display for PexAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.pubkey, self.addr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PexConfig {
+    /// Peers dialed when the address book has no other peers to try.
+    pub seeds: Vec<PexAddr>,
+    /// The maximum number of peers we dial.
+    pub max_outbound: usize,
+    /// The maximum number of peers which may dial us.
+    pub max_inbound: usize,
+    /// The maximum number of addresses sent in, or accepted from, a single exchange.
+    pub max_addrs: usize,
+    /// How often we dial new peers, ask peers for addresses and save the address book.
+    pub ensure_peers_interval: Duration,
+}
+
+impl Default for PexConfig {
+    fn default() -> Self {
+        PexConfig {
+            seeds: Vec::new(),
+            max_outbound: 10,
+            max_inbound: 40,
+            max_addrs: 100,
+            ensure_peers_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Peer exchange. Keeps the node connected to up to `max_outbound` peers, picked from the address
+/// book, and fills the address book by asking connected peers for the addresses they know.
+///
+/// When the address book has no peers left to dial, the node dials one of its seeds instead. Any
+/// node can act as a seed, as every node answers requests for addresses.
+pub struct Pex {
+    config: PexConfig,
+    node: NodeInfo,
+    table: Arc<PeerTable<PeerMessage>>,
+    book: Arc<AddressBook>,
+    gossip: Arc<Gossip>,
+    /// Peers we have asked for addresses, and not yet heard back from.
+    requested: StdMutex<HashSet<PublicKey>>,
+}
+
+impl Pex {
+    pub fn new(
+        config: PexConfig,
+        node: NodeInfo,
+        table: Arc<PeerTable<PeerMessage>>,
+        book: Arc<AddressBook>,
+        gossip: Arc<Gossip>,
+    ) -> Arc<Self> {
+        Arc::new(Pex {
+            config,
+            node,
+            table,
+            book,
+            gossip,
+            requested: StdMutex::new(HashSet::new()),
+        })
+    }
+
+    /// Handles a peer exchange message from `from`.
+    pub fn handle_message(&self, from: PublicKey, message: &PeerMessage) {
+        match message {
+            PeerMessage::PexRequest => {
+                let addrs: Vec<PexAddr> = self
+                    .book
+                    .sample(self.config.max_addrs)
+                    .into_iter()
+                    .filter(|known| known.pubkey != from)
+                    .filter_map(|known| Some(PexAddr { pubkey: known.pubkey, addr: known.addr? }))
+                    .collect();
+                // Replies go over our own connection to the peer. If we haven't dialed the peer
+                // yet, it learns our address from our hello and asks again later.
+                self.table.send(&from, PeerMessage::PexAddrs(addrs));
+            }
+            PeerMessage::PexAddrs(addrs) => {
+                let requested = self.requested.lock().unwrap().remove(&from);
+                if !requested || addrs.len() > self.config.max_addrs {
+                    self.report(from, Misbehaviour::PexAbuse);
+                    return;
+                }
+                // Peers we wouldn't connect with aren't worth remembering.
+                for addr in addrs {
+                    if self.node.allowed.contains(&addr.pubkey) &&
+                        !self.book.is_banned(&addr.pubkey)
+                    {
+                        self.book.add(addr.pubkey, addr.addr);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds to a peer's ban score, and disconnects from it if it is banned.
+    pub fn report(&self, pubkey: PublicKey, misbehaviour: Misbehaviour) {
+        if self.book.report(&pubkey, misbehaviour) {
+            tracing::warn!(peer = %pubkey, ?misbehaviour, "Banned peer");
+            self.disconnect(&pubkey);
+        }
+    }
+
+    fn disconnect(&self, pubkey: &PublicKey) {
+        // Dropping the last sender for the peer stops its client.
+        self.table.remove(pubkey);
+        self.gossip.remove_peer(pubkey);
+    }
+
+    fn dial(&self, addr: PexAddr) {
+        let client = PeerClient::new(
+            100,
+            self.node.clone(),
+            self.table.clone(),
+            self.book.clone(),
+            addr.pubkey,
+            addr.addr,
+        );
+        self.gossip.add_peer(addr.pubkey, client.get_sender());
+        client.start();
+        // Ask every new peer for the peers it knows.
+        self.requested.lock().unwrap().insert(addr.pubkey);
+        self.table.send(&addr.pubkey, PeerMessage::PexRequest);
+    }
+
+    /// Dials peers until we have `max_outbound` of them, and asks a connected peer for more
+    /// addresses if we still need peers.
+    pub fn ensure_peers(&self) {
+        // Drop banned peers, and peers we can't reach whose address has since changed, so they
+        // are redialed on the new address.
+        let mut peers = self.table.peers();
+        peers.retain(|peer| {
+            let moved = peer.state != PeerState::Connected &&
+                self.book.get(&peer.pubkey).and_then(|known| known.addr) != Some(peer.dial_addr);
+            let keep = !moved && !self.book.is_banned(&peer.pubkey);
+            if !keep {
+                self.disconnect(&peer.pubkey);
+            }
+            keep
+        });
+
+        let mut dialed: HashSet<PublicKey> = peers.iter().map(|peer| peer.pubkey).collect();
+        let mut outbound = dialed.len();
+        while outbound < self.config.max_outbound {
+            let Some(known) = self.book.pick_to_dial(&dialed) else {
+                break;
+            };
+            let addr = PexAddr { pubkey: known.pubkey, addr: known.addr.expect("dialable") };
+            dialed.insert(addr.pubkey);
+            self.dial(addr);
+            outbound += 1;
+        }
+
+        if outbound < self.config.max_outbound {
+            let connected: Vec<PublicKey> = peers
+                .iter()
+                .filter(|peer| peer.state == PeerState::Connected)
+                .map(|peer| peer.pubkey)
+                .collect();
+            if let Some(peer) = connected.choose(&mut rand::thread_rng()) {
+                self.requested.lock().unwrap().insert(*peer);
+                self.table.send(peer, PeerMessage::PexRequest);
+            } else {
+                let seeds: Vec<&PexAddr> = self
+                    .config
+                    .seeds
+                    .iter()
+                    .filter(|seed| !dialed.contains(&seed.pubkey))
+                    .collect();
+                if let Some(seed) = seeds.choose(&mut rand::thread_rng()) {
+                    self.book.add(seed.pubkey, seed.addr);
+                    self.dial(**seed);
+                }
+            }
+        }
+
+        if let Err(err) = self.book.save() {
+            tracing::error!(error = %err, "Failed to save address book");
+        }
+    }
+
+    /// Starts peer exchange. Peer exchange messages are taken out of `inbound`, and the returned
+    /// receiver carries the remaining messages, for the gossip layer.
+    pub fn start(
+        self: &Arc<Self>,
+        inbound: Arc<Mutex<mpsc::Receiver<(PublicKey, PeerMessage)>>>,
+    ) -> Arc<Mutex<mpsc::Receiver<(PublicKey, PeerMessage)>>> {
+        let (sender, receiver) = mpsc::channel(100);
+        let pex = self.clone();
+        tokio::spawn(async move {
+            let mut inbound = inbound.lock().await;
+            while let Some((from, message)) = inbound.recv().await {
+                match message {
+                    PeerMessage::PexRequest | PeerMessage::PexAddrs(_) => {
+                        pex.handle_message(from, &message)
+                    }
+                    message => {
+                        if sender.send((from, message)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        let pex = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(pex.config.ensure_peers_interval);
+            loop {
+                ticker.tick().await;
+                pex.ensure_peers();
+            }
+        });
+
+        Arc::new(Mutex::new(receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addrbook::AddressBookConfig, codec::Encoding, crypto::ECDSAKeypair, gossip::GossipConfig,
+    };
+
+    fn pex(keypair: &ECDSAKeypair, allowed: Vec<PublicKey>) -> Arc<Pex> {
+        let node = NodeInfo {
+            keypair: keypair.clone().into(),
+            chain_id: "test-chain".to_string(),
+            genesis_hash: [0; 32],
+            listen_addr: "127.0.0.1:3030".parse().unwrap(),
+            encoding: Encoding::Proto,
+            allowed,
+        };
+        let book = AddressBook::new(AddressBookConfig::default(), keypair.get_public_key());
+        Pex::new(
+            PexConfig::default(),
+            node,
+            Arc::new(PeerTable::new()),
+            Arc::new(book),
+            Gossip::new(GossipConfig::default(), "test-chain".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_pex_addr_from_str() {
+        let pubkey = ECDSAKeypair::new().get_public_key();
+        let addr: PexAddr = format!("{}@10.0.0.1:26656", pubkey).parse().unwrap();
+        assert_eq!(addr.pubkey, pubkey);
+        assert_eq!(addr.addr, "10.0.0.1:26656".parse().unwrap());
+        assert_eq!(addr.to_string().parse::<PexAddr>().unwrap(), addr);
+        assert!("10.0.0.1:26656".parse::<PexAddr>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_addrs() {
+        let keypair = ECDSAKeypair::new();
+        let peer = ECDSAKeypair::new().get_public_key();
+        let new = PexAddr {
+            pubkey: ECDSAKeypair::new().get_public_key(),
+            addr: "127.0.0.1:3033".parse().unwrap(),
+        };
+        let pex = pex(&keypair, vec![peer, new.pubkey]);
+        let (sender, mut receiver) = mpsc::channel(10);
+        pex.table.insert(peer, "127.0.0.1:3031".parse().unwrap(), sender);
+
+        // Requests are answered with the addresses in the book, except the peer's own.
+        let known = PexAddr {
+            pubkey: ECDSAKeypair::new().get_public_key(),
+            addr: "127.0.0.1:3032".parse().unwrap(),
+        };
+        pex.book.add(known.pubkey, known.addr);
+        pex.book.add(peer, "127.0.0.1:3031".parse().unwrap());
+        pex.handle_message(peer, &PeerMessage::PexRequest);
+        match receiver.try_recv().unwrap() {
+            PeerMessage::PexAddrs(addrs) => assert_eq!(addrs, vec![known]),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // Addresses we asked for are added to the book if we would connect with the peer,
+        // unsolicited ones count against the peer.
+        let unknown = PexAddr {
+            pubkey: ECDSAKeypair::new().get_public_key(),
+            addr: "127.0.0.1:3034".parse().unwrap(),
+        };
+        pex.requested.lock().unwrap().insert(peer);
+        pex.handle_message(peer, &PeerMessage::PexAddrs(vec![new, unknown]));
+        assert!(pex.book.get(&new.pubkey).is_some());
+        assert!(pex.book.get(&unknown.pubkey).is_none());
+        pex.handle_message(peer, &PeerMessage::PexAddrs(vec![]));
+        assert_eq!(pex.book.get(&peer).unwrap().ban_score, Misbehaviour::PexAbuse.penalty());
+    }
+}
@@ -0,0 +1,532 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time::{timeout, Duration},
+};
+
+use crate::{
+    crypto::{Keypair, PublicKey, Signature, Signer, Verifier},
+    messages::{Message, SignedMessage, Step},
+    secret_connection::{self, SecretConnection},
+};
+
+/// How long a node waits for the signer to answer a sign request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Connection(#[from] secret_connection::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed message: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("signer identified as {0}, not the expected validator")]
+    UnexpectedSigner(PublicKey),
+    #[error("sign bytes don't match the request")]
+    InvalidRequest,
+    #[error("refusing to sign for chain {0}")]
+    ChainIdMismatch(String),
+    #[error("refusing to sign {2:?} at height {0} round {1}, before the last signed step")]
+    Regression(u64, u64, Step),
+    #[error("refusing to sign conflicting {2:?} at height {0} round {1}")]
+    DoubleSign(u64, u64, Step),
+    #[error("no nodes are allowed to connect to the signer")]
+    NoneAllowed,
+    #[error("signer refused: {0}")]
+    Refused(String),
+    #[error("signer returned an invalid signature")]
+    InvalidSignature,
+    #[error("signer timed out")]
+    Timeout,
+}
+
+/// A request to sign a proposal or vote. The signer checks the height, round and step against
+/// what it last signed, and that the sign bytes are those of a message at that height, round and
+/// step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignRequest {
+    pub chain_id: String,
+    pub height: u64,
+    pub round: u64,
+    pub step: Step,
+    #[serde(with = "hex")]
+    pub sign_bytes: Vec<u8>,
+}
+
+impl SignRequest {
+    pub fn new(message: &Message, chain_id: &str) -> Self {
+        let (height, round) = match message {
+            Message::Propose { height, round, .. } |
+            Message::Prevote { height, round, .. } |
+            Message::Precommit { height, round, .. } => (*height, *round),
+        };
+        SignRequest {
+            chain_id: chain_id.to_string(),
+            height,
+            round,
+            step: message.step(),
+            sign_bytes: message.sign_bytes(chain_id),
+        }
+    }
+
+    /// Returns whether the sign bytes are exactly those of a message at the request's chain,
+    /// height, round and step.
+    fn is_consistent(&self) -> bool {
+        #[derive(Deserialize)]
+        struct Fields {
+            value: Option<String>,
+        }
+        let Ok(Fields { value }) = serde_json::from_slice(&self.sign_bytes) else {
+            return false;
+        };
+        let (height, round) = (self.height, self.round);
+        let message = match (self.step, value) {
+            (Step::Propose, Some(value)) => Message::Propose { height, round, value },
+            (Step::Propose, None) => return false,
+            (Step::Prevote, value) => Message::Prevote { height, round, value },
+            (Step::Precommit, value) => Message::Precommit { height, round, value },
+        };
+        message.sign_bytes(&self.chain_id) == self.sign_bytes
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SignResponse {
+    Signature(Signature),
+    Error(String),
+}
+
+/// The last proposal or vote a signer signed. The signer never signs at an earlier height, round
+/// or step, and at the same one only re-signs the same bytes, which rules out double signing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignState {
+    pub height: u64,
+    pub round: u64,
+    /// None until the first message is signed.
+    pub step: Option<Step>,
+    #[serde(with = "hex")]
+    pub sign_bytes: Vec<u8>,
+    pub signature: Option<Signature>,
+}
+
+impl SignState {
+    /// Checks a request against the state. Returns the previous signature if the request is for
+    /// the bytes last signed, and None if the request may be signed.
+    fn check(&self, request: &SignRequest) -> Result<Option<Signature>, Error> {
+        let Some(step) = self.step else {
+            return Ok(None);
+        };
+        let last = (self.height, self.round, step);
+        let next = (request.height, request.round, request.step);
+        if next < last {
+            return Err(Error::Regression(request.height, request.round, request.step));
+        }
+        if next == last {
+            return match self.signature {
+                Some(signature) if request.sign_bytes == self.sign_bytes => Ok(Some(signature)),
+                _ => Err(Error::DoubleSign(request.height, request.round, request.step)),
+            };
+        }
+        Ok(None)
+    }
+}
+
+/// Signs proposals and votes for one chain with a validator key, with double-sign protection.
+/// The sign state is written to `state_file` before each signature is released, so the
+/// protection survives restarts.
+pub struct SignerService<K> {
+    keypair: K,
+    chain_id: String,
+    state: SignState,
+    state_file: Option<PathBuf>,
+}
+
+impl<K: Signer> SignerService<K> {
+    /// Creates a signer, resuming from the sign state in `state_file` if it exists.
+    pub fn new(keypair: K, chain_id: String, state_file: Option<PathBuf>) -> Result<Self, Error> {
+        let state = match &state_file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(data) => serde_json::from_str(&data)?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => SignState::default(),
+                Err(err) => return Err(err.into()),
+            },
+            None => SignState::default(),
+        };
+        Ok(SignerService { keypair, chain_id, state, state_file })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public_key()
+    }
+
+    pub fn state(&self) -> &SignState {
+        &self.state
+    }
+
+    /// Signs a request, unless it could be a double sign.
+    pub fn sign(&mut self, request: &SignRequest) -> Result<Signature, Error> {
+        if request.chain_id != self.chain_id {
+            return Err(Error::ChainIdMismatch(request.chain_id.clone()));
+        }
+        if !request.is_consistent() {
+            return Err(Error::InvalidRequest);
+        }
+        if let Some(signature) = self.state.check(request)? {
+            return Ok(signature);
+        }
+
+        let signature = self.keypair.sign(&request.sign_bytes);
+        let state = SignState {
+            height: request.height,
+            round: request.round,
+            step: Some(request.step),
+            sign_bytes: request.sign_bytes.clone(),
+            signature: Some(signature),
+        };
+        if let Some(path) = &self.state_file {
+            // Write a temporary file and rename it over the old state, so a crash never leaves a
+            // truncated state behind.
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, serde_json::to_string_pretty(&state)?)?;
+            std::fs::rename(tmp, path)?;
+        }
+        self.state = state;
+        Ok(signature)
+    }
+}
+
+/// Serves sign requests from nodes over authenticated, encrypted connections. The signer
+/// authenticates with the validator key, so a node knows which validator it signs for. Requests
+/// from every connection are handled one at a time.
+pub struct SignerServer<K> {
+    listener: TcpListener,
+    identity: K,
+    service: Arc<StdMutex<SignerService<K>>>,
+    /// The nodes allowed to connect, by identity key.
+    allowed: Vec<PublicKey>,
+}
+
+impl<K> SignerServer<K>
+where
+    K: Signer + Clone + Send + Sync + 'static,
+{
+    /// Listens on `addr` for the nodes in `allowed`. Anyone able to connect could have votes
+    /// signed, so at least one node must be allowed.
+    pub async fn bind(
+        addr: SocketAddr,
+        service: SignerService<K>,
+        allowed: Vec<PublicKey>,
+    ) -> Result<Self, Error> {
+        if allowed.is_empty() {
+            return Err(Error::NoneAllowed);
+        }
+        Ok(SignerServer {
+            listener: TcpListener::bind(addr).await?,
+            identity: service.keypair.clone(),
+            service: Arc::new(StdMutex::new(service)),
+            allowed,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a handle to the service, to inspect its sign state.
+    pub fn service(&self) -> Arc<StdMutex<SignerService<K>>> {
+        self.service.clone()
+    }
+
+    /// Accepts connections from nodes until the listener fails.
+    pub async fn run(self) -> std::io::Result<()> {
+        loop {
+            let (stream, addr) = self.listener.accept().await?;
+            let identity = self.identity.clone();
+            let service = self.service.clone();
+            let allowed = self.allowed.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve(stream, identity, service, allowed).await {
+                    tracing::info!(node = %addr, error = %err, "Signer connection closed");
+                }
+            });
+        }
+    }
+}
+
+async fn serve<K: Signer>(
+    stream: TcpStream,
+    identity: K,
+    service: Arc<StdMutex<SignerService<K>>>,
+    allowed: Vec<PublicKey>,
+) -> Result<(), Error> {
+    let mut conn = SecretConnection::new(stream, &identity).await?;
+    if !allowed.contains(&conn.remote_pubkey()) {
+        return Err(Error::Refused(format!("node {} is not allowed", conn.remote_pubkey())));
+    }
+    loop {
+        let request: SignRequest = serde_json::from_slice(&conn.read_msg().await?)?;
+        let response = match service.lock().unwrap().sign(&request) {
+            Ok(signature) => SignResponse::Signature(signature),
+            Err(err) => {
+                tracing::warn!(error = %err, "Refused to sign");
+                SignResponse::Error(err.to_string())
+            }
+        };
+        conn.write_msg(&serde_json::to_vec(&response)?).await?;
+    }
+}
+
+/// A connection to a signer holding a validator key outside of the node. The connection is
+/// re-established if it drops.
+pub struct RemoteSigner {
+    addr: SocketAddr,
+    /// The key the node authenticates to the signer with.
+    identity: Keypair,
+    /// The validator key the signer holds.
+    pubkey: PublicKey,
+    conn: Mutex<Option<SecretConnection<TcpStream>>>,
+}
+
+impl RemoteSigner {
+    /// Connects to the signer at `addr`, learning the validator key it signs with.
+    pub async fn connect(addr: SocketAddr, identity: Keypair) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        let conn = SecretConnection::new(stream, &identity).await?;
+        Ok(RemoteSigner {
+            addr,
+            identity,
+            pubkey: conn.remote_pubkey(),
+            conn: Mutex::new(Some(conn)),
+        })
+    }
+
+    /// The public key of the validator the signer signs for.
+    pub fn public_key(&self) -> PublicKey {
+        self.pubkey
+    }
+
+    /// Asks the signer to sign a request, and checks the signature it returns. A request that
+    /// fails to reach the signer is retried once on a new connection.
+    pub async fn sign(&self, request: &SignRequest) -> Result<Signature, Error> {
+        let mut conn = self.conn.lock().await;
+        let signature = match self.request(&mut conn, request).await {
+            Err(Error::Connection(_) | Error::Io(_) | Error::Timeout) => {
+                self.request(&mut conn, request).await?
+            }
+            result => result?,
+        };
+        if !self.pubkey.verify(&request.sign_bytes, &signature) {
+            return Err(Error::InvalidSignature);
+        }
+        Ok(signature)
+    }
+
+    async fn request(
+        &self,
+        conn: &mut Option<SecretConnection<TcpStream>>,
+        request: &SignRequest,
+    ) -> Result<Signature, Error> {
+        let result = timeout(REQUEST_TIMEOUT, async {
+            if conn.is_none() {
+                let stream = TcpStream::connect(self.addr).await?;
+                let new_conn = SecretConnection::new(stream, &self.identity).await?;
+                if new_conn.remote_pubkey() != self.pubkey {
+                    return Err(Error::UnexpectedSigner(new_conn.remote_pubkey()));
+                }
+                *conn = Some(new_conn);
+            }
+            let active = conn.as_mut().expect("connected above");
+            active.write_msg(&serde_json::to_vec(request)?).await?;
+            let response: SignResponse = serde_json::from_slice(&active.read_msg().await?)?;
+            Ok(response)
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout));
+
+        match result {
+            Ok(SignResponse::Signature(signature)) => Ok(signature),
+            Ok(SignResponse::Error(reason)) => Err(Error::Refused(reason)),
+            Err(err) => {
+                // The connection is in an unknown state, start afresh next time.
+                *conn = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The key a process signs its proposals and votes with: a keypair held by the process, or a
+/// remote signer.
+#[allow(clippy::large_enum_variant)]
+pub enum PrivValidator {
+    Local(Keypair),
+    Remote(RemoteSigner),
+}
+
+impl From<Keypair> for PrivValidator {
+    fn from(keypair: Keypair) -> Self {
+        PrivValidator::Local(keypair)
+    }
+}
+
+impl From<RemoteSigner> for PrivValidator {
+    fn from(signer: RemoteSigner) -> Self {
+        PrivValidator::Remote(signer)
+    }
+}
+
+impl PrivValidator {
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            PrivValidator::Local(keypair) => keypair.get_public_key(),
+            PrivValidator::Remote(signer) => signer.public_key(),
+        }
+    }
+
+    /// Signs a message for the chain `chain_id`.
+    pub async fn sign(&self, message: Message, chain_id: &str) -> Result<SignedMessage, Error> {
+        match self {
+            PrivValidator::Local(keypair) => Ok(SignedMessage::new(message, chain_id, keypair)),
+            PrivValidator::Remote(signer) => {
+                let signature = signer.sign(&SignRequest::new(&message, chain_id)).await?;
+                Ok(SignedMessage { body: message, signature, sender: signer.public_key() })
+            }
+        }
+    }
+}
+
+/// A signer serving on a loopback port, with its sign state in memory, for tests. It only serves
+/// the node identity it makes for itself.
+#[cfg(any(test, feature = "test-util"))]
+pub struct MockSigner {
+    addr: SocketAddr,
+    identity: Keypair,
+    service: Arc<StdMutex<SignerService<Keypair>>>,
+    task: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockSigner {
+    pub async fn start(keypair: Keypair, chain_id: &str) -> Self {
+        let service =
+            SignerService::new(keypair, chain_id.to_string(), None).expect("no state file to read");
+        let identity = Keypair::generate(crate::crypto::KeyType::Ed25519);
+        let allowed = vec![identity.get_public_key()];
+        let server = SignerServer::bind("127.0.0.1:0".parse().unwrap(), service, allowed)
+            .await
+            .expect("loopback port is available");
+        MockSigner {
+            addr: server.local_addr().expect("listener is bound"),
+            identity,
+            service: server.service(),
+            task: tokio::spawn(server.run()),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The last message signed.
+    pub fn state(&self) -> SignState {
+        self.service.lock().unwrap().state().clone()
+    }
+
+    /// Connects a remote signer, with the identity the signer allows.
+    pub async fn connect(&self) -> RemoteSigner {
+        RemoteSigner::connect(self.addr, self.identity.clone())
+            .await
+            .expect("mock signer is running")
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Drop for MockSigner {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{ECDSAKeypair, KeyType};
+
+    const CHAIN_ID: &str = "test-chain";
+
+    fn prevote(height: u64, round: u64, value: &str) -> SignRequest {
+        let message = Message::Prevote { height, round, value: Some(value.to_string()) };
+        SignRequest::new(&message, CHAIN_ID)
+    }
+
+    #[test]
+    fn test_double_sign_protection() {
+        let path = std::env::temp_dir().join(format!("sign-state-{}.json", std::process::id()));
+        let keypair = ECDSAKeypair::new();
+        let mut service =
+            SignerService::new(keypair.clone(), CHAIN_ID.to_string(), Some(path.clone())).unwrap();
+
+        let signature = service.sign(&prevote(1, 1, "a")).unwrap();
+        // The same vote is signed again with the same signature, but not a conflicting one.
+        assert_eq!(service.sign(&prevote(1, 1, "a")).unwrap(), signature);
+        assert!(matches!(service.sign(&prevote(1, 1, "b")), Err(Error::DoubleSign(1, 1, _))));
+        let proposal = Message::Propose { height: 1, round: 1, value: "b".to_string() };
+        assert!(matches!(
+            service.sign(&SignRequest::new(&proposal, CHAIN_ID)),
+            Err(Error::Regression(1, 1, Step::Propose))
+        ));
+        assert!(service.sign(&prevote(1, 2, "b")).is_ok());
+
+        // Requests must be for the signer's chain, and their sign bytes must match.
+        assert!(matches!(
+            service.sign(&SignRequest::new(&proposal, "other-chain")),
+            Err(Error::ChainIdMismatch(_))
+        ));
+        let mislabelled = SignRequest { round: 3, ..prevote(1, 2, "c") };
+        assert!(matches!(service.sign(&mislabelled), Err(Error::InvalidRequest)));
+
+        // The state survives a restart.
+        let mut restarted =
+            SignerService::new(keypair, CHAIN_ID.to_string(), Some(path.clone())).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(restarted.state(), service.state());
+        assert!(matches!(restarted.sign(&prevote(1, 1, "a")), Err(Error::Regression(..))));
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer() {
+        let keypair = Keypair::generate(KeyType::Secp256k1);
+        let mock = MockSigner::start(keypair.clone(), CHAIN_ID).await;
+        let signer = PrivValidator::from(mock.connect().await);
+        assert_eq!(signer.public_key(), keypair.get_public_key());
+
+        let message = Message::Precommit { height: 2, round: 1, value: None };
+        let signed = signer.sign(message.clone(), CHAIN_ID).await.unwrap();
+        assert!(signed.verify(CHAIN_ID));
+        assert_eq!(mock.state().step, Some(Step::Precommit));
+
+        // Refusals are reported, and the connection stays usable.
+        let conflicting = Message::Precommit { height: 2, round: 1, value: Some("v".to_string()) };
+        assert!(matches!(signer.sign(conflicting, CHAIN_ID).await, Err(Error::Refused(_))));
+        assert!(signer.sign(message, CHAIN_ID).await.unwrap().verify(CHAIN_ID));
+
+        // A node the signer doesn't allow gets nothing signed.
+        let stranger = RemoteSigner::connect(mock.addr(), Keypair::generate(KeyType::Ed25519));
+        let stranger = stranger.await.unwrap();
+        let request =
+            SignRequest::new(&Message::Precommit { height: 3, round: 1, value: None }, CHAIN_ID);
+        assert!(stranger.sign(&request).await.is_err());
+        assert_eq!(mock.state().height, 2);
+
+        let service = SignerService::new(keypair, CHAIN_ID.to_string(), None).unwrap();
+        let bound = SignerServer::bind("127.0.0.1:0".parse().unwrap(), service, Vec::new()).await;
+        assert!(matches!(bound, Err(Error::NoneAllowed)));
+    }
+}
@@ -1,21 +1,136 @@
-use std::{collections::HashMap, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex as StdMutex},
+};
 use tokio::{
     sync::{mpsc, Mutex},
-    time::{timeout, Duration},
+    time::timeout,
+};
+use tracing::{debug, error, field, info, instrument, warn, Span};
+
+#[cfg(feature = "bls")]
+use crate::commit::CommitCertificate;
+use crate::{
+    algos::*,
+    crypto::*,
+    decision_log::DecisionLog,
+    events::*,
+    messages::*,
+    params::*,
+    privval::PrivValidator,
+    verification::{SignatureVerifier, VerifierConfig},
+    vote_set::{HeightVoteSet, VoteError, VoteSet},
 };
 
-use crate::{algos::*, crypto::*, events::*, messages::*, params::*};
+/// A consensus event. Serialized as JSON with the kind of the event as its `type`, e.g.
+/// `{"type":"decision","height":3,...}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The process started deciding a new height.
+    NewHeight { height: u64 },
+    /// The process started a round, proposed by the validator at index `proposer`.
+    NewRound { height: u64, round: u64, proposer: usize },
+    /// The process moved on to a step of the round.
+    NewStep { height: u64, round: u64, step: Step },
+    /// The proposal for the round was accepted, the process's own included.
+    Proposal { height: u64, round: u64, value: String, proposer: PublicKey },
+    /// A prevote or precommit was counted for the first time, the process's own included.
+    Vote { height: u64, round: u64, step: Step, value: Option<String>, sender: PublicKey },
+    /// A quorum of the votes of a step are for the same value, or for nil if `value` is None.
+    Quorum { height: u64, round: u64, step: Step, value: Option<String> },
+    /// The process stopped waiting for the messages of a step.
+    Timeout { height: u64, round: u64, step: Step },
+    /// The process precommitted a value it saw a quorum of prevotes for, locking on it.
+    Lock { height: u64, round: u64, value: String },
+    /// The process released its lock on seeing a quorum of nil prevotes in a later round.
+    Unlock { height: u64, round: u64 },
+    /// A validator signed two different votes for the same step of a round.
+    Evidence {
+        height: u64,
+        round: u64,
+        validator: PublicKey,
+        first: Box<SignedMessage>,
+        second: Box<SignedMessage>,
+    },
+    /// A value was decided.
+    Decision {
+        height: u64,
+        round: u64,
+        value: String,
+        from: usize,
+        /// The precommits for the value aggregated into one signature, if a quorum of them are
+        /// from validators with BLS keys.
+        #[cfg(feature = "bls")]
+        certificate: Option<CommitCertificate>,
+    },
+}
+
+impl Event {
+    /// The kind of the event, as named in its JSON `type`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::NewHeight { .. } => "new_height",
+            Event::NewRound { .. } => "new_round",
+            Event::NewStep { .. } => "new_step",
+            Event::Proposal { .. } => "proposal",
+            Event::Vote { .. } => "vote",
+            Event::Quorum { .. } => "quorum",
+            Event::Timeout { .. } => "timeout",
+            Event::Lock { .. } => "lock",
+            Event::Unlock { .. } => "unlock",
+            Event::Evidence { .. } => "evidence",
+            Event::Decision { .. } => "decision",
+        }
+    }
 
+    /// The height the event happened at.
+    pub fn height(&self) -> u64 {
+        match self {
+            Event::NewHeight { height } |
+            Event::NewRound { height, .. } |
+            Event::NewStep { height, .. } |
+            Event::Proposal { height, .. } |
+            Event::Vote { height, .. } |
+            Event::Quorum { height, .. } |
+            Event::Timeout { height, .. } |
+            Event::Lock { height, .. } |
+            Event::Unlock { height, .. } |
+            Event::Evidence { height, .. } |
+            Event::Decision { height, .. } => *height,
+        }
+    }
+}
+
+/// The position of a process in consensus, and the votes it holds at the current height. This is
+/// shared with the tasks running alongside the process, such as gossip.
 #[derive(Debug, Clone)]
-pub enum Event {
-    Decision { height: u64, round: u64, value: String, from: usize },
+pub struct RoundState {
+    pub height: u64,
+    pub round: u64,
+    pub step: Step,
+    pub votes: HeightVoteSet,
 }
 
 /// A process running the Tendermint consensus algorithm.
 pub struct Process {
     pub id: usize,
 
-    pub keypair: Keypair,
+    /// Signs the process's proposals and votes.
+    signer: PrivValidator,
+
+    /// The chain messages are signed for.
+    chain_id: String,
+
+    /// The identity keys of the validators, indexed by process id.
+    validators: Vec<PublicKey>,
+
+    /// Checks the signatures of received messages.
+    verifier: Arc<SignatureVerifier>,
+
+    /// The timeouts of each step, and the largest value that may be proposed.
+    params: ConsensusParams,
 
     /// Channel to receive messages from other processes.
     receiver: Arc<Mutex<mpsc::Receiver<SignedMessage>>>,
@@ -29,15 +144,19 @@ pub struct Process {
     /// Event source.
     events: EventSystem<Event>,
 
+    /// Where decisions are persisted, if anywhere, before they are published.
+    decision_log: Option<Arc<DecisionLog>>,
+
     /// State.
     decisions: Vec<String>,
 
+    /// The current round and step, and the votes received at the current height.
+    round_state: Arc<StdMutex<RoundState>>,
+
     /// Callback to get the value to be proposed for agreement.
     get_value: fn() -> String,
 }
 
-
-
 /// Consensus operates in terms of epochs, which contain an unlimited number of rounds.
 #[derive(Debug, Clone)]
 pub struct EpochState {
@@ -53,25 +172,63 @@ pub struct EpochState {
     precommits: HashMap<u64, Vec<Option<String>>>,
     /// The decision reached by the consensus algorithm, if any.
     decision: Option<String>,
+    /// The value the process last precommitted, and the round it did so in. The process proposes
+    /// and prevotes it in later rounds, until a quorum of prevotes is for another value or nil.
+    locked: Option<(u64, String)>,
+}
+
+impl EpochState {
+    /// The state of a consensus instance that has yet to start its first round.
+    pub fn new(height: u64) -> Self {
+        EpochState {
+            height,
+            round: 0,
+            proposals: HashMap::new(),
+            prevotes: HashMap::new(),
+            precommits: HashMap::new(),
+            decision: None,
+            locked: None,
+        }
+    }
+
+    /// The height of the consensus instance. Once a value is decided, the height after it.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
 }
 
 impl Process {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: usize,
-        keypair: Keypair,
+        signer: impl Into<PrivValidator>,
+        chain_id: String,
+        validators: Vec<PublicKey>,
         receiver: Arc<Mutex<mpsc::Receiver<SignedMessage>>>,
         processes: Vec<mpsc::Sender<SignedMessage>>,
         proposer_sequence: Vec<usize>,
         get_value: fn() -> String,
     ) -> Self {
+        let round_state = RoundState {
+            height: 0,
+            round: 0,
+            step: Step::Propose,
+            votes: HeightVoteSet::new(0, validators.clone()),
+        };
         Process {
             id,
-            keypair,
+            signer: signer.into(),
+            verifier: SignatureVerifier::new(VerifierConfig::default(), chain_id.clone()),
+            params: ConsensusParams::default(),
+            chain_id,
+            validators,
             receiver,
             processes,
             proposer_sequence,
             decisions: Vec::new(),
             events: EventSystem::new(),
+            decision_log: None,
+            round_state: Arc::new(StdMutex::new(round_state)),
             get_value,
         }
     }
@@ -81,7 +238,47 @@ impl Process {
         self.events.subscribe()
     }
 
-    /// Runs
+    /// Returns a handle to the consensus event stream, to subscribe to from elsewhere.
+    pub fn events(&self) -> EventSystem<Event> {
+        self.events.clone()
+    }
+
+    /// Replaces the event source, typically with one holding more events for slow subscribers.
+    /// Subscribers to the old one receive no more events.
+    pub fn set_events(&mut self, events: EventSystem<Event>) {
+        self.events = events;
+    }
+
+    /// Persists every decision to a log before it is published, for subscribers to replay.
+    pub fn set_decision_log(&mut self, log: Arc<DecisionLog>) {
+        self.decision_log = Some(log);
+    }
+
+    /// Replaces the verifier received messages are checked with, typically to share one with the
+    /// gossip layer.
+    pub fn set_verifier(&mut self, verifier: Arc<SignatureVerifier>) {
+        self.verifier = verifier;
+    }
+
+    /// Replaces the consensus parameters, typically with those of the chain's genesis.
+    pub fn set_consensus_params(&mut self, params: ConsensusParams) {
+        self.params = params;
+    }
+
+    /// Sets the voting power of each validator, in the order of the validator set, typically to
+    /// those of the chain's genesis. Every validator has a power of 1 otherwise. Votes already
+    /// received are discarded. Panics if there isn't one power for each validator.
+    pub fn set_voting_powers(&mut self, powers: Vec<u64>) {
+        let mut state = self.round_state.lock().unwrap();
+        state.votes = HeightVoteSet::with_powers(state.height, self.validators.clone(), powers);
+    }
+
+    /// Returns a handle to the process's round state and votes.
+    pub fn round_state(&self) -> Arc<StdMutex<RoundState>> {
+        self.round_state.clone()
+    }
+
+    // Runs
     // pub async fn run(&self) {
     //     loop {
     //         epoch_state = self.run_round(epoch_state).await;
@@ -96,219 +293,461 @@ impl Process {
     /// Each epoch consists of at least one round. If the round fails to reach consensus, the epoch
     /// will continue to the next round. This function returns upon the consensus deciding a new
     /// value.
+    #[instrument(name = "consensus", skip_all, fields(node = self.id, height = field::Empty))]
     pub async fn run_epoch(&mut self, epoch_state: Option<EpochState>) -> EpochState {
-        let mut epoch_state = epoch_state.unwrap_or(EpochState {
-            height: 0,
-            round: 0,
-            proposals: HashMap::new(),
-            prevotes: HashMap::new(),
-            precommits: HashMap::new(),
-            decision: None,
-        });
-        
+        let mut epoch_state = epoch_state.unwrap_or_else(|| EpochState::new(0));
+        Span::current().record("height", epoch_state.height);
+        self.events.publish(Event::NewHeight { height: epoch_state.height });
+
         loop {
             epoch_state = self.run_round(epoch_state).await;
 
             if epoch_state.decision.is_some() {
-                epoch_state.height += 1;
+                #[cfg(feature = "bls")]
+                let certificate = self.commit_certificate(&epoch_state);
                 self.decisions.push(epoch_state.decision.clone().unwrap());
 
-                // Publish decision event
-                self.events.publish(Event::Decision {
+                // Publish decision event, at the height decided.
+                let decision = Event::Decision {
                     height: epoch_state.height,
                     round: epoch_state.round,
                     value: epoch_state.decision.clone().unwrap(),
                     from: self.id,
-                });
+                    #[cfg(feature = "bls")]
+                    certificate,
+                };
+                if let Some(log) = &self.decision_log {
+                    if let Err(err) = log.append(&decision).await {
+                        error!(error = %err, "Failed to persist decision");
+                    }
+                }
+                self.events.publish(decision);
+                epoch_state.height += 1;
                 break;
             }
         }
-        println!("Node {} decided on {:?}", self.id, epoch_state.decision);
+        info!(value = ?epoch_state.decision, round = epoch_state.round, "Decided");
         epoch_state
     }
 
     /// Runs a single round of Tendermint consensus, taking in the current epoch state.
     /// Returns the updated epoch state.
+    #[instrument(name = "round", skip_all, fields(round = epoch_state0.round + 1))]
     pub async fn run_round(&self, epoch_state0: EpochState) -> EpochState {
         let mut epoch = epoch_state0.clone();
         epoch.round += 1;
 
+        let height = epoch.height;
         let round = epoch.round;
-        println!("Node {} starting round {}", self.id, round);
+        info!("Starting round");
 
         // Determine proposer
-        let proposer = get_proposer_for_round(round as u8, &self.proposer_sequence);
+        let proposer = get_proposer_for_round(round, &self.proposer_sequence);
+        self.events.publish(Event::NewRound { height, round, proposer });
+        self.enter_step(height, round, Step::Propose);
         if self.id == proposer {
-            // Propose a value.
-            let value = (self.get_value)();
-            println!("Node {} proposing value {}", self.id, value);
-            self.broadcast(Message::Propose { round, value: value.clone() }).await;
+            // Propose a value, or the value we are locked on.
+            let value = match &epoch.locked {
+                Some((_, value)) => value.clone(),
+                None => (self.get_value)(),
+            };
+            debug!(%value, "Proposing value");
+            self.broadcast(Message::Propose { height, round, value: value.clone() }).await;
             // Save own proposal
             epoch.proposals.insert(round, value);
         }
 
         // Await proposals
         if self.id != proposer {
-            let propose_timeout = get_timeout_for_round(round);
-
-            self.receive_messages_until_timeout(
-                MessageType::Propose,
-                propose_timeout,
-                |msg| {
-                    if let Message::Propose { round: r, value } = msg.body {
-                        if r == round {
-                            println!(
-                                "Node {} received proposal from Node {}: {}",
-                                self.id, msg.sender, value
-                            );
-                            epoch.proposals.insert(r, value);
-                            return true
-                        }
-                    }
-                    false
-                },
-                || {
-                    // Timeout reached
-                    println!("Node {} timed out waiting for proposals in round {}", self.id, round);
-                },
-            )
-            .await;
+            let received = self
+                .receive_messages_until_timeout(&mut epoch, Step::Propose, |epoch, _| {
+                    epoch.proposals.contains_key(&round)
+                })
+                .await;
+            if !received {
+                info!("Timed out waiting for a proposal");
+                self.events.publish(Event::Timeout { height, round, step: Step::Propose });
+            }
         }
 
         // Prevote phase
-        let proposal = epoch.proposals.get(&round).cloned();
-        self.broadcast(Message::Prevote { round, value: proposal.clone() }).await;
+        self.enter_step(height, round, Step::Prevote);
+        // A process locked on a value prevotes it, whatever was proposed.
+        let proposal = match &epoch.locked {
+            Some((_, value)) => Some(value.clone()),
+            None => epoch.proposals.get(&round).cloned(),
+        };
+        self.broadcast(Message::Prevote { height, round, value: proposal }).await;
 
         // Collect prevotes
-        let prevote_timeout = get_timeout_for_round(round);
-        let _start = tokio::time::Instant::now();
-        let mut prevotes = Vec::new();
-
-        self.receive_messages_until_timeout(
-            MessageType::Prevote,
-            prevote_timeout,
-            |msg| {
-                if let Message::Prevote { round: r, value } = msg.body {
-                    if r == round {
-                        prevotes.push(value.clone());
-                        println!(
-                            "Node {} received prevote from Node {}: {:?}",
-                            self.id, msg.sender, value
-                        );
-                        if prevotes.len() >= QUORUM {
-                            return true;
-                        }
-                    }
-                }
-                false
-            },
-            || {
-                // Timeout reached
-                println!("Node {} timed out waiting for prevotes in round {}", self.id, round);
-            },
-        )
-        .await;
-        epoch.prevotes.insert(round, prevotes.clone());
+        let received = self
+            .receive_messages_until_timeout(&mut epoch, Step::Prevote, |_, votes| {
+                votes.prevotes(round).is_some_and(VoteSet::has_quorum)
+            })
+            .await;
+        if !received {
+            info!("Timed out waiting for prevotes");
+            self.events.publish(Event::Timeout { height, round, step: Step::Prevote });
+        }
+        epoch.prevotes.insert(round, self.votes_for(round, Step::Prevote));
 
         // Determine decision based on prevotes
-        let decision = Self::majority_decision(&prevotes);
-        // println!("Node {} decided on {:?}", self.id, decision);
-        self.broadcast(Message::Precommit { round, value: decision.clone() }).await;
+        let decision = self.quorum_value(round, Step::Prevote).flatten();
+        self.enter_step(height, round, Step::Precommit);
+        self.update_lock(&mut epoch, &decision);
+        self.broadcast(Message::Precommit { height, round, value: decision.clone() }).await;
 
         // Collect precommits
-        let _precommit_timeout = get_timeout_for_round(round);
-        let mut precommits = Vec::new();
-
-        self.receive_messages_until_timeout(
-            MessageType::Precommit,
-            prevote_timeout,
-            |msg| {
-                if let Message::Precommit { round: r, value } = msg.body {
-                    if r == round {
-                        precommits.push(value.clone());
-                        println!(
-                            "Node {} received precommit from Node {}: {:?}",
-                            self.id, msg.sender, value
-                        );
-                        if precommits.len() >= QUORUM {
-                            return true;
-                        }
-                    }
-                }
-                false
-            },
-            || {
-                // Timeout reached
-                println!("Node {} timed out waiting for precommits in round {}", self.id, round);
-            },
-        )
-        .await;
-        epoch.precommits.insert(round, precommits.clone());
+        let received = self
+            .receive_messages_until_timeout(&mut epoch, Step::Precommit, |_, votes| {
+                votes.precommits(round).is_some_and(VoteSet::has_quorum)
+            })
+            .await;
+        if !received {
+            info!("Timed out waiting for precommits");
+            self.events.publish(Event::Timeout { height, round, step: Step::Precommit });
+        }
+        epoch.precommits.insert(round, self.votes_for(round, Step::Precommit));
 
         // Final decision
-        if Self::count_occurrences(&precommits, &decision) >= QUORUM {
-            println!("Node {} has committed value {:?} in round {}", self.id, decision, round);
+        if decision.is_some() && self.quorum_value(round, Step::Precommit) == Some(decision.clone())
+        {
+            debug!(value = ?decision, "Committed value");
             // Consensus reached
             epoch.decision = decision;
         } else {
-            println!("Node {} failed to decide in round {}. Moving to next round.", self.id, round);
+            info!("Failed to decide, moving to the next round");
         }
 
         epoch
     }
 
+    /// Records the step the process is in, resetting the vote sets when the height changes.
+    fn enter_step(&self, height: u64, round: u64, step: Step) {
+        let mut state = self.round_state.lock().unwrap();
+        if state.votes.height() != height {
+            state.votes.reset(height);
+        }
+        state.height = height;
+        state.round = round;
+        state.step = step;
+        drop(state);
+        self.events.publish(Event::NewStep { height, round, step });
+    }
+
+    /// Locks on the value about to be precommitted, or releases the lock if a quorum prevoted nil.
+    fn update_lock(&self, epoch: &mut EpochState, decision: &Option<String>) {
+        let (height, round) = (epoch.height, epoch.round);
+        match decision {
+            Some(value) => {
+                epoch.locked = Some((round, value.clone()));
+                self.events.publish(Event::Lock { height, round, value: value.clone() });
+            }
+            None if self.quorum_value(epoch.round, Step::Prevote) == Some(None) &&
+                epoch.locked.as_ref().is_some_and(|(locked_round, _)| *locked_round < round) =>
+            {
+                epoch.locked = None;
+                self.events.publish(Event::Unlock { height, round });
+            }
+            None => {}
+        }
+    }
+
+    /// Aggregates the precommits for the value decided in the epoch's current round.
+    #[cfg(feature = "bls")]
+    fn commit_certificate(&self, epoch: &EpochState) -> Option<CommitCertificate> {
+        let value = epoch.decision.as_ref()?;
+        let state = self.round_state.lock().unwrap();
+        let precommits = state.votes.precommits(epoch.round)?;
+        CommitCertificate::aggregate(epoch.height, epoch.round, value, precommits)
+    }
+
+    /// The value a quorum of the prevotes or precommits of a round are for: Some(None) for nil,
+    /// and None if there is no quorum for any value.
+    fn quorum_value(&self, round: u64, step: Step) -> Option<Option<String>> {
+        let state = self.round_state.lock().unwrap();
+        step_votes(&state.votes, round, step).and_then(VoteSet::quorum_value)
+    }
+
+    /// Returns the values of the prevotes or precommits held for a round.
+    fn votes_for(&self, round: u64, step: Step) -> Vec<Option<String>> {
+        let state = self.round_state.lock().unwrap();
+        step_votes(&state.votes, round, step).map(|v| v.values()).unwrap_or_default()
+    }
+
+    /// Counts a vote, publishing it if it's new along with the quorum it completes, or as evidence
+    /// if it conflicts with the vote held from the same validator. Returns true if it's new.
+    fn add_vote(&self, vote: SignedMessage) -> bool {
+        let (height, round, value) = match &vote.body {
+            Message::Prevote { height, round, value } |
+            Message::Precommit { height, round, value } => (*height, *round, value.clone()),
+            Message::Propose { .. } => return false,
+        };
+        let step = vote.body.step();
+        let sender = vote.sender;
+        let mut state = self.round_state.lock().unwrap();
+        let (added, events) = match state.votes.add_vote(vote.clone()) {
+            Ok(true) => {
+                let votes = step_votes(&state.votes, round, step).expect("the vote was added");
+                let power = votes.power_for(&value);
+                let quorum = quorum_power(state.votes.total_power());
+                let sender_power =
+                    state.votes.validator_index(&sender).map_or(0, |i| state.votes.power(i));
+                let mut events =
+                    vec![Event::Vote { height, round, step, value: value.clone(), sender }];
+                // Only the vote which completes the quorum publishes it.
+                if power >= quorum && power - sender_power < quorum {
+                    events.push(Event::Quorum { height, round, step, value });
+                }
+                (true, events)
+            }
+            Err(VoteError::Conflicting(index)) => {
+                let first = step_votes(&state.votes, round, step).and_then(|v| v.get(index));
+                let evidence = first.map(|first| Event::Evidence {
+                    height,
+                    round,
+                    validator: sender,
+                    first: Box::new(first.clone()),
+                    second: Box::new(vote),
+                });
+                (false, evidence.into_iter().collect())
+            }
+            _ => (false, Vec::new()),
+        };
+        drop(state);
+        for event in events {
+            self.events.publish(event);
+        }
+        added
+    }
+
     async fn broadcast(&self, msg: Message) {
-        let signed_msg = SignedMessage::new(msg, &self.keypair);
+        let signed_msg = match self.signer.sign(msg, &self.chain_id).await {
+            Ok(signed_msg) => signed_msg,
+            Err(err) => {
+                error!(error = %err, "Failed to sign");
+                return;
+            }
+        };
+        // Our own votes count towards quorums, and are shared with peers missing them.
+        match &signed_msg.body {
+            Message::Propose { height, round, value } => self.events.publish(Event::Proposal {
+                height: *height,
+                round: *round,
+                value: value.clone(),
+                proposer: signed_msg.sender,
+            }),
+            _ => {
+                self.add_vote(signed_msg.clone());
+            }
+        }
         for sender in &self.processes {
             let _ = sender.send(signed_msg.clone()).await;
         }
     }
 
+    /// Receives messages in `step` until `done` returns true or the step times out. Returns false
+    /// on timeout. Every valid message for the current height is recorded, whatever the round or
+    /// step, so votes that arrive early are not lost.
+    #[instrument(name = "step", skip_all, fields(step = ?step))]
     async fn receive_messages_until_timeout(
         &self,
-        msg_type: MessageType,
-        timeout_duration: Duration,
-        mut handler: impl FnMut(SignedMessage) -> bool,
-        on_timeout: impl Fn(),
-    ) {
+        epoch: &mut EpochState,
+        step: Step,
+        mut done: impl FnMut(&EpochState, &HeightVoteSet) -> bool,
+    ) -> bool {
+        let timeout_duration = self.params.timeout(step, epoch.round);
         let start = tokio::time::Instant::now();
         let mut receiver = self.receiver.lock().await;
 
-        while start.elapsed() < timeout_duration {
-            match timeout(timeout_duration - start.elapsed(), receiver.recv()).await {
+        loop {
+            if done(epoch, &self.round_state.lock().unwrap().votes) {
+                return true;
+            }
+            let Some(remaining) = timeout_duration.checked_sub(start.elapsed()) else {
+                return false;
+            };
+            match timeout(remaining, receiver.recv()).await {
                 Ok(Some(msg)) => {
-                    if !msg.verify() {
-                        // Ignore messages with invalid signatures.
-                        continue;
+                    // Verify the messages already queued behind this one together.
+                    let mut batch = vec![msg];
+                    while batch.len() < self.verifier.batch_size() {
+                        let Ok(msg) = receiver.try_recv() else {
+                            break;
+                        };
+                        batch.push(msg);
                     }
-
-                    if msg_type.matches(&msg.body) && handler(msg) {
-                        break;
+                    for msg in self.verifier.filter_valid(batch).await {
+                        self.handle_message(epoch, msg);
                     }
                 }
-                _ => {
-                    // Timeout reached or channel closed
-                    on_timeout();
-                    break;
+                // Timeout reached or channel closed
+                _ => return false,
+            }
+        }
+    }
+
+    /// Handles a message whose signature has been verified.
+    fn handle_message(&self, epoch: &mut EpochState, msg: SignedMessage) {
+        match msg.body {
+            Message::Propose { height, round, ref value } if height == epoch.height => {
+                // Rounds are numbered from 1, and only the proposer for the round may propose.
+                if round == 0 {
+                    return;
+                }
+                let proposer = get_proposer_for_round(round, &self.proposer_sequence);
+                if self.validators.get(proposer) != Some(&msg.sender) {
+                    return;
+                }
+                if value.len() > self.params.max_value_size {
+                    warn!(size = value.len(), sender = %msg.sender, "Ignored oversized proposal");
+                    return;
+                }
+                if let Entry::Vacant(entry) = epoch.proposals.entry(round) {
+                    debug!(sender = %msg.sender, %value, "Received proposal");
+                    entry.insert(value.clone());
+                    self.events.publish(Event::Proposal {
+                        height,
+                        round,
+                        value: value.clone(),
+                        proposer: msg.sender,
+                    });
+                }
+            }
+            Message::Prevote { .. } | Message::Precommit { .. } => {
+                let description = format!("{:?}", msg.body);
+                let sender = msg.sender;
+                if self.add_vote(msg) {
+                    debug!(%sender, vote = %description, "Received vote");
                 }
             }
+            _ => {}
+        }
+    }
+}
+
+/// The prevotes or precommits held for a round.
+fn step_votes(votes: &HeightVoteSet, round: u64, step: Step) -> Option<&VoteSet> {
+    match step {
+        Step::Precommit => votes.precommits(round),
+        _ => votes.prevotes(round),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_votes_publish_quorum_and_evidence() {
+        let keypairs: Vec<Keypair> =
+            (0..NODES).map(|_| Keypair::generate(KeyType::Secp256k1)).collect();
+        let validators = keypairs.iter().map(|k| k.get_public_key()).collect();
+        let (_, receiver) = mpsc::channel(1);
+        let process = Process::new(
+            0,
+            keypairs[0].clone(),
+            "test-chain".to_string(),
+            validators,
+            Arc::new(Mutex::new(receiver)),
+            vec![],
+            (0..NODES).collect(),
+            String::new,
+        );
+        let mut events = Box::pin(process.subscribe());
+        let prevote = |keypair: &Keypair, value: &str| {
+            let body = Message::Prevote { height: 0, round: 1, value: Some(value.to_string()) };
+            SignedMessage::new(body, "test-chain", keypair)
+        };
+
+        for keypair in &keypairs[1..=quorum_power(NODES as u64) as usize] {
+            assert!(process.add_vote(prevote(keypair, "a")));
+            assert!(matches!(events.next().await, Some(Event::Vote { .. })));
         }
+        assert!(matches!(
+            events.next().await,
+            Some(Event::Quorum { step: Step::Prevote, value: Some(ref v), .. }) if v == "a"
+        ));
+
+        assert!(!process.add_vote(prevote(&keypairs[1], "b")));
+        let Some(Event::Evidence { validator, first, second, .. }) = events.next().await else {
+            panic!("expected evidence");
+        };
+        assert_eq!(validator, keypairs[1].get_public_key());
+        assert_ne!(first.body, second.body);
     }
 
-    fn majority_decision(prevotes: &Vec<Option<String>>) -> Option<String> {
-        let mut counts = HashMap::new();
-        for vote in prevotes {
-            *counts.entry(vote.clone()).or_insert(0) += 1;
+    #[tokio::test]
+    async fn test_locked_process_prevotes_its_lock() {
+        let keypairs: Vec<Keypair> =
+            (0..NODES).map(|_| Keypair::generate(KeyType::Secp256k1)).collect();
+        let validators = keypairs.iter().map(|k| k.get_public_key()).collect();
+        let (sender, receiver) = mpsc::channel(1);
+        let process = Process::new(
+            0,
+            keypairs[0].clone(),
+            "test-chain".to_string(),
+            validators,
+            Arc::new(Mutex::new(receiver)),
+            vec![],
+            (0..NODES).collect(),
+            String::new,
+        );
+        let mut events = Box::pin(process.subscribe());
+
+        // Locked on "a" in round 1, the process prevotes it over the "b" proposed in round 2.
+        let epoch =
+            EpochState { round: 1, locked: Some((1, "a".to_string())), ..EpochState::new(0) };
+        let body = Message::Propose { height: 0, round: 2, value: "b".to_string() };
+        sender.send(SignedMessage::new(body, "test-chain", &keypairs[1])).await.unwrap();
+        let round = tokio::spawn(async move { process.run_round(epoch).await });
+        loop {
+            match events.next().await {
+                Some(Event::Vote { step: Step::Prevote, value, sender, .. }) => {
+                    assert_eq!(sender, keypairs[0].get_public_key());
+                    assert_eq!(value.as_deref(), Some("a"));
+                    break;
+                }
+                Some(_) => continue,
+                None => panic!("events ended"),
+            }
         }
-        counts
-            .into_iter()
-            .max_by_key(|&(_, count)| count)
-            .filter(|&(_, count)| count >= QUORUM)
-            .map(|(value, _)| value)
-            .unwrap_or(None)
+        round.abort();
     }
 
-    fn count_occurrences(precommits: &Vec<Option<String>>, decision: &Option<String>) -> usize {
-        precommits.iter().filter(|&v| v == decision).count()
+    #[test]
+    fn test_proposals_for_any_round() {
+        let keypairs: Vec<Keypair> =
+            (0..NODES).map(|_| Keypair::generate(KeyType::Secp256k1)).collect();
+        let validators = keypairs.iter().map(|k| k.get_public_key()).collect();
+        let (_, receiver) = mpsc::channel(1);
+        let process = Process::new(
+            0,
+            keypairs[0].clone(),
+            "test-chain".to_string(),
+            validators,
+            Arc::new(Mutex::new(receiver)),
+            vec![],
+            (0..NODES).collect(),
+            String::new,
+        );
+        let propose = |keypair: &Keypair, round: u64| {
+            let body = Message::Propose { height: 0, round, value: "v".to_string() };
+            SignedMessage::new(body, "test-chain", keypair)
+        };
+
+        // There is no round 0, so no proposer for it.
+        let mut epoch = EpochState::new(0);
+        process.handle_message(&mut epoch, propose(&keypairs[0], 0));
+        assert!(epoch.proposals.is_empty());
+
+        // Rounds past 255 don't wrap around to round 0.
+        let proposer = get_proposer_for_round(256, &process.proposer_sequence);
+        assert_eq!(proposer, 255 % NODES);
+        process.handle_message(&mut epoch, propose(&keypairs[(proposer + 1) % NODES], 256));
+        assert!(epoch.proposals.is_empty());
+        process.handle_message(&mut epoch, propose(&keypairs[proposer], 256));
+        assert_eq!(epoch.proposals.get(&256).map(String::as_str), Some("v"));
     }
 }
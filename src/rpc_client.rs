@@ -1,25 +1,47 @@
-use reqwest::Client;
-use serde::Serialize;
+use prometheus::{IntCounter, IntCounterVec};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use sha3::{Digest, Keccak256};
 use std::{fmt::Debug, sync::Arc};
 use tokio::sync::{mpsc, Mutex};
 
+use crate::codec::{self, Encoding, WireMessage};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server replied {0}: {1}")]
+    Status(StatusCode, String),
+}
+
 #[derive(Debug, Clone)]
 pub struct RpcClient<T> {
     inbox_url: String,
+    encoding: Encoding,
     sender: mpsc::Sender<T>, // Exposes the mpsc::Sender to send messages
     client: Arc<Client>,     // Reqwest HTTP client wrapped in Arc for shared ownership
     receiver: Arc<Mutex<mpsc::Receiver<T>>>, // Internal receiver used by the background task
+    send_failures: IntCounter,
 }
 
 impl<T> RpcClient<T>
 where
-    T: Send + 'static + Serialize + Debug, // T must implement Serialize to be posted via HTTP
+    T: Send + Sync + 'static + WireMessage + Debug, // T must be encodable to be posted via HTTP
 {
-    // Initialize the RpcClient with a given channel capacity and reqwest client
-    pub fn new(capacity: usize, inbox_url: String) -> Self {
+    // Initialize the RpcClient with a given channel capacity, wire encoding and reqwest client.
+    // Messages which fail to post are counted in `send_failures`, under the inbox url, which is
+    // shared between clients, e.g. `Metrics::rpc_send_failures`.
+    pub fn new(
+        capacity: usize,
+        inbox_url: String,
+        encoding: Encoding,
+        send_failures: &IntCounterVec,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel(capacity);
         RpcClient {
+            send_failures: send_failures.with_label_values(&[&inbox_url]),
             inbox_url,
+            encoding,
             sender,
             client: Arc::new(Client::new()),
             receiver: Arc::new(Mutex::new(receiver)),
@@ -39,30 +61,49 @@ where
             while let Some(message) = receiver.recv().await {
                 let client = client.clone();
                 let url = self.inbox_url.clone();
+                let encoding = self.encoding;
+                let send_failures = self.send_failures.clone();
 
                 // Send the HTTP POST request with the message
                 tokio::spawn(async move {
-                    match client.post(&url).json(&message).send().await {
-                        Ok(response) => {
-                            if response.status().is_success() {
-                            } else {
-                                eprintln!("Failed to send message: {:?}", response);
-                                // print response body
-                                eprintln!("Response body: {:?}", response.text().await);
-                                eprintln!("Message: {:?}", message);
-                            }
-                        }
-                        Err(err) => {
-                            eprintln!("HTTP error: {:?}", err);
-                        }
+                    // Messages may be large, so failures are logged with the hash of the body.
+                    let body = codec::encode(&message, encoding);
+                    let hash = hex::encode(Keccak256::digest(&body));
+                    if let Err(err) = post(&client, &url, encoding, body).await {
+                        tracing::warn!(
+                            url = %url,
+                            error = %err,
+                            body_hash = %hash,
+                            "Failed to send message"
+                        );
+                        send_failures.inc();
                     }
                 });
             }
         });
     }
 
+    /// Posts a single message to the inbox, waiting for the server to accept it.
+    pub async fn send(&self, message: &T) -> Result<(), Error> {
+        post(&self.client, &self.inbox_url, self.encoding, codec::encode(message, self.encoding))
+            .await
+    }
+
     // Expose the mpsc::Sender so that other parts of the code can send messages
     pub fn get_sender(&self) -> mpsc::Sender<T> {
         self.sender.clone()
     }
 }
+
+async fn post(client: &Client, url: &str, encoding: Encoding, body: Vec<u8>) -> Result<(), Error> {
+    let content_type = match encoding {
+        Encoding::Json => "application/json",
+        Encoding::Proto => "application/x-protobuf",
+    };
+    let response = client.post(url).header(CONTENT_TYPE, content_type).body(body).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::Status(status, response.text().await.unwrap_or_default()));
+    }
+    Ok(())
+}
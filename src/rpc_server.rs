@@ -1,7 +1,43 @@
-use serde::{de::DeserializeOwned, Serialize};
-use std::{net::IpAddr, sync::Arc};
-use tokio::sync::{mpsc, Mutex};
-use warp::Filter;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
+use tokio_stream::{Stream, StreamExt};
+use warp::{filters::sse, http::StatusCode, Filter, Reply};
+
+#[cfg(feature = "bls")]
+use crate::commit::CommitCertificate;
+use crate::{
+    codec::{self, WireMessage},
+    config::{TendermintConfig, ValidatorInfo},
+    crypto::PublicKey,
+    decision_log::{self, DecisionLog},
+    events::{EventSystem, Lagged},
+    messages::{PeerMessage, Step},
+    metrics::Metrics,
+    peers::{PeerState, PeerTable},
+    process::{Event, RoundState},
+};
+
+/// The largest message body the server accepts. Larger bodies are refused with 413.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// How many of the latest decisions the query server keeps.
+const MAX_DECISIONS: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to listen on {0}: {1}")]
+    Bind(SocketAddr, warp::Error),
+}
 
 pub struct Server<T> {
     sender: mpsc::Sender<T>,
@@ -13,7 +49,7 @@ pub struct Server<T> {
 
 impl<T> Server<T>
 where
-    T: Send + Sync + 'static + Serialize + DeserializeOwned + std::fmt::Debug + Clone,
+    T: Send + Sync + 'static + WireMessage + std::fmt::Debug + Clone,
 {
     pub fn new(addr: IpAddr, port: u16) -> Self {
         let (sender, receiver) = mpsc::channel(100);
@@ -24,27 +60,497 @@ where
         self.receiver.clone()
     }
 
-    pub async fn run(&self) {
-        let sender = self.sender.clone();
-        let port = self.port;
-        println!("Server running on port {}", port);
+    /// Serves the inbox until the server stops. Fails if the address can't be bound.
+    pub async fn run(&self) -> Result<(), Error> {
+        let addr = SocketAddr::new(self.addr, self.port);
+        let (_, server) = warp::serve(inbox(self.sender.clone(), self.port))
+            .try_bind_ephemeral(addr)
+            .map_err(|err| Error::Bind(addr, err))?;
+        tracing::info!(%addr, "Inbox server running");
+        server.await;
+        Ok(())
+    }
+}
+
+/// The route that listens on /inbox and sends incoming messages to the sender. Messages may be
+/// sent in any wire encoding. Malformed messages are refused with 400, and messages arriving
+/// while the channel is full, or closed, with 503.
+fn inbox<T>(
+    sender: mpsc::Sender<T>,
+    port: u16,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    T: Send + Sync + 'static + WireMessage + std::fmt::Debug,
+{
+    warp::path("inbox")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| {
+            let (reply, status) = match codec::decode::<T>(&body) {
+                Ok(message) => {
+                    tracing::debug!(port, ?message, "Received message");
+                    match sender.try_send(message) {
+                        Ok(()) => ("Message received".to_string(), StatusCode::OK),
+                        Err(TrySendError::Full(_)) => {
+                            ("Inbox is full".to_string(), StatusCode::SERVICE_UNAVAILABLE)
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            ("Inbox is closed".to_string(), StatusCode::SERVICE_UNAVAILABLE)
+                        }
+                    }
+                }
+                Err(err) => (format!("Malformed message: {}", err), StatusCode::BAD_REQUEST),
+            };
+            warp::reply::with_status(warp::reply::json(&reply), status)
+        })
+}
+
+/// A decision, as reported by the query endpoints.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionInfo {
+    pub height: u64,
+    pub round: u64,
+    pub value: String,
+    #[cfg(feature = "bls")]
+    pub certificate: Option<CommitCertificate>,
+}
+
+/// Where the node is in consensus.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsensusStatus {
+    pub height: u64,
+    pub round: u64,
+    pub step: Step,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub node_id: PublicKey,
+    pub chain_id: String,
+    /// The node's index in the validator set, if it is a validator.
+    pub validator_index: Option<usize>,
+    /// Where the node is in consensus, if it takes part in it.
+    pub consensus: Option<ConsensusStatus>,
+    pub latest_decision: Option<DecisionInfo>,
+}
 
-        // Define the warp route that listens on /inbox and sends incoming messages to the sender
-        let inbox_route = warp::path("inbox").and(warp::post()).and(warp::body::json()).and_then(
-            move |message: T| {
-                // Print message JSON, prefixed with port [:3030] Received message: Message { data:
-                // "Hello, world!" }
-                println!("[:{}] Received message: {:?}", port, message);
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+    pub pubkey: PublicKey,
+    pub dial_addr: SocketAddr,
+    pub state: PeerState,
+    /// The address and latest height the peer last told us about in its hello.
+    pub listen_addr: Option<SocketAddr>,
+    pub latest_height: Option<u64>,
+    /// How many seconds ago we last heard from the peer.
+    pub last_seen_secs: Option<u64>,
+}
+
+/// What the query endpoints report on: the node's identity, validator set and peers, its position
+/// in consensus, and the decisions it has recorded.
+pub struct QueryState {
+    node_id: PublicKey,
+    chain_id: String,
+    validators: Vec<ValidatorInfo>,
+    validator_index: Option<usize>,
+    round_state: Option<Arc<StdMutex<RoundState>>>,
+    events: Option<EventSystem<Event>>,
+    decision_log: Option<Arc<DecisionLog>>,
+    metrics: Option<Arc<Metrics>>,
+    peers: Arc<PeerTable<PeerMessage>>,
+    decisions: StdMutex<BTreeMap<u64, DecisionInfo>>,
+}
+
+/// Consensus events as a subscriber receives them.
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, Lagged>> + Send>>;
+
+impl QueryState {
+    /// The state of a node outside of the validator set.
+    pub fn new(
+        node_id: PublicKey,
+        genesis: &TendermintConfig,
+        peers: Arc<PeerTable<PeerMessage>>,
+    ) -> Self {
+        QueryState {
+            node_id,
+            chain_id: genesis.chain_id.clone(),
+            validators: genesis.validators.clone(),
+            validator_index: None,
+            round_state: None,
+            events: None,
+            decision_log: None,
+            metrics: None,
+            peers,
+            decisions: StdMutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Reports on the process a validator runs, at `index` in the validator set.
+    pub fn with_process(
+        mut self,
+        index: usize,
+        round_state: Arc<StdMutex<RoundState>>,
+        events: EventSystem<Event>,
+    ) -> Self {
+        self.validator_index = Some(index);
+        self.round_state = Some(round_state);
+        self.events = Some(events);
+        self
+    }
+
+    /// Replays the process's logged decisions to event subscribers starting from a past height.
+    pub fn with_decision_log(mut self, log: Arc<DecisionLog>) -> Self {
+        self.decision_log = Some(log);
+        self
+    }
+
+    /// Serves the node's metrics at `/metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The metrics in the Prometheus text format, with the peer count as of now. None if the node
+    /// keeps no metrics.
+    fn metrics(&self) -> Option<String> {
+        let metrics = self.metrics.as_ref()?;
+        let connected =
+            self.peers.peers().iter().filter(|peer| peer.state == PeerState::Connected).count();
+        metrics.set_peers(connected);
+        Some(metrics.encode())
+    }
+
+    /// Subscribes to the process's events, replaying the logged decisions from `from_height` first
+    /// if there is a log. None if the node runs no process.
+    async fn subscribe(
+        &self,
+        from_height: Option<u64>,
+    ) -> Option<Result<EventStream, decision_log::Error>> {
+        let events = self.events.as_ref()?;
+        Some(match (&self.decision_log, from_height) {
+            (Some(log), Some(height)) => {
+                log.subscribe_from(events, height).await.map(|s| Box::pin(s) as EventStream)
+            }
+            _ => Ok(Box::pin(events.subscribe_checked())),
+        })
+    }
+
+    /// Records the decisions among consensus events. Only the latest `MAX_DECISIONS` are kept.
+    pub fn record(&self, event: &Event) {
+        let Event::Decision {
+            height,
+            round,
+            value,
+            #[cfg(feature = "bls")]
+            certificate,
+            ..
+        } = event
+        else {
+            return;
+        };
+        let decision = DecisionInfo {
+            height: *height,
+            round: *round,
+            value: value.clone(),
+            #[cfg(feature = "bls")]
+            certificate: certificate.clone(),
+        };
+        let mut decisions = self.decisions.lock().unwrap();
+        decisions.insert(*height, decision);
+        while decisions.len() > MAX_DECISIONS {
+            decisions.pop_first();
+        }
+    }
 
-                let sender = sender.clone();
-                async move {
-                    sender.send(message).await.unwrap(); // Send the message to the channel
-                    Ok::<_, warp::Rejection>(warp::reply::json(&"Message received"))
+    pub fn status(&self) -> Status {
+        let consensus = self.round_state.as_ref().map(|state| {
+            let state = state.lock().unwrap();
+            ConsensusStatus { height: state.height, round: state.round, step: state.step }
+        });
+        Status {
+            node_id: self.node_id,
+            chain_id: self.chain_id.clone(),
+            validator_index: self.validator_index,
+            consensus,
+            latest_decision: self.decisions.lock().unwrap().values().next_back().cloned(),
+        }
+    }
+
+    pub fn decision(&self, height: u64) -> Option<DecisionInfo> {
+        self.decisions.lock().unwrap().get(&height).cloned()
+    }
+
+    pub fn peers(&self) -> Vec<PeerStatus> {
+        let mut peers: Vec<_> = self
+            .peers
+            .peers()
+            .into_iter()
+            .map(|peer| PeerStatus {
+                pubkey: peer.pubkey,
+                dial_addr: peer.dial_addr,
+                state: peer.state,
+                listen_addr: peer.hello.as_ref().map(|hello| hello.listen_addr),
+                latest_height: peer.hello.as_ref().map(|hello| hello.latest_height),
+                last_seen_secs: peer.last_seen.map(|seen| seen.elapsed().as_secs()),
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.dial_addr);
+        peers
+    }
+}
+
+/// Which events a subscriber to `/events` receives: those of the comma-separated kinds in `type`,
+/// or all kinds, from `from_height` up to `to_height`, after which the stream ends.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    #[serde(rename = "type")]
+    pub kinds: Option<String>,
+    pub from_height: Option<u64>,
+    pub to_height: Option<u64>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        let kind_matches =
+            self.kinds.as_ref().is_none_or(|kinds| kinds.split(',').any(|k| k == event.kind()));
+        kind_matches &&
+            self.from_height.is_none_or(|from| event.height() >= from) &&
+            self.to_height.is_none_or(|to| event.height() <= to)
+    }
+
+    fn is_past(&self, event: &Event) -> bool {
+        self.to_height.is_some_and(|to| event.height() > to)
+    }
+}
+
+/// Streams consensus events as server-sent events, named by the event kind with the event as JSON
+/// data. A subscriber that falls behind gets a `lagged` event with the number of events it missed.
+fn event_stream(
+    events: EventStream,
+    filter: EventFilter,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    let past = filter.clone();
+    events
+        .take_while(move |item| !matches!(item, Ok(event) if past.is_past(event)))
+        .filter_map(move |item| match item {
+            Ok(event) if filter.matches(&event) => Some(
+                sse::Event::default()
+                    .event(event.kind())
+                    .data(serde_json::to_string(&event).expect("events serialize")),
+            ),
+            Ok(_) => None,
+            Err(Lagged(missed)) => Some(
+                sse::Event::default().event("lagged").data(format!("{{\"missed\":{}}}", missed)),
+            ),
+        })
+        .map(Ok)
+}
+
+/// Serves read-only queries about a node, on a listener of its own so it can be kept private, or
+/// not run at all.
+pub struct QueryServer {
+    state: Arc<QueryState>,
+    pub addr: SocketAddr,
+}
+
+impl QueryServer {
+    pub fn new(addr: SocketAddr, state: Arc<QueryState>) -> Self {
+        QueryServer { state, addr }
+    }
+
+    /// Serves queries until the server stops. Fails if the address can't be bound.
+    pub async fn run(&self) -> Result<(), Error> {
+        let (_, server) = warp::serve(queries(self.state.clone()))
+            .try_bind_ephemeral(self.addr)
+            .map_err(|err| Error::Bind(self.addr, err))?;
+        tracing::info!(addr = %self.addr, "Query server running");
+        server.await;
+        Ok(())
+    }
+}
+
+/// The query routes: `GET /health`, `/status`, `/decisions/{height}`, `/validators` and `/peers`,
+/// all replying with JSON, `/events`, streaming consensus events filtered by `EventFilter`, and
+/// `/metrics`, in the Prometheus text format. Heights without a recorded decision are 404, as are
+/// events on a node outside consensus and metrics on a node keeping none.
+fn queries(
+    state: Arc<QueryState>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let state = warp::any().map(move || state.clone());
+    let health = warp::path!("health").map(|| warp::reply::json(&"ok"));
+    let status = warp::path!("status")
+        .and(state.clone())
+        .map(|state: Arc<QueryState>| warp::reply::json(&state.status()));
+    let decision = warp::path!("decisions" / u64).and(state.clone()).map(
+        |height: u64, state: Arc<QueryState>| match state.decision(height) {
+            Some(decision) => {
+                warp::reply::with_status(warp::reply::json(&decision), StatusCode::OK)
+            }
+            None => warp::reply::with_status(
+                warp::reply::json(&format!("No decision at height {}", height)),
+                StatusCode::NOT_FOUND,
+            ),
+        },
+    );
+    let validators = warp::path!("validators")
+        .and(state.clone())
+        .map(|state: Arc<QueryState>| warp::reply::json(&state.validators));
+    let peers = warp::path!("peers")
+        .and(state.clone())
+        .map(|state: Arc<QueryState>| warp::reply::json(&state.peers()));
+    let metrics =
+        warp::path!("metrics").and(state.clone()).map(|state: Arc<QueryState>| {
+            match state.metrics() {
+                Some(text) => warp::reply::with_header(
+                    text,
+                    "content-type",
+                    "text/plain; version=0.0.4; charset=utf-8",
+                )
+                .into_response(),
+                None => warp::reply::with_status(
+                    warp::reply::json(&"The node keeps no metrics"),
+                    StatusCode::NOT_FOUND,
+                )
+                .into_response(),
+            }
+        });
+    let events = warp::path!("events").and(warp::query::<EventFilter>()).and(state).then(
+        |filter: EventFilter, state: Arc<QueryState>| async move {
+            match state.subscribe(filter.from_height).await {
+                Some(Ok(events)) => {
+                    let stream = event_stream(events, filter);
+                    warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response()
                 }
-            },
+                Some(Err(err)) => warp::reply::with_status(
+                    warp::reply::json(&format!("Failed to read decision log: {}", err)),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response(),
+                None => warp::reply::with_status(
+                    warp::reply::json(&"The node doesn't take part in consensus"),
+                    StatusCode::NOT_FOUND,
+                )
+                .into_response(),
+            }
+        },
+    );
+    warp::get().and(health.or(status).or(decision).or(validators).or(peers).or(metrics).or(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codec::{encode, Encoding},
+        crypto::{ECDSAKeypair, Keypair},
+        messages::{Message, SignedMessage},
+        process::{EpochState, Process},
+    };
+
+    #[tokio::test]
+    async fn test_inbox_status_codes() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let filter = inbox::<SignedMessage>(sender, 3030);
+        let message = SignedMessage::new(
+            Message::Prevote { height: 1, round: 1, value: None },
+            "test-chain",
+            &ECDSAKeypair::new(),
+        );
+        let post = |body: Vec<u8>| warp::test::request().method("POST").path("/inbox").body(body);
+
+        let response = post(encode(&message, Encoding::Proto)).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The channel holds one message, so the next is refused until it is received.
+        let response = post(encode(&message, Encoding::Json)).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        receiver.recv().await.unwrap();
+
+        let response = post(b"{\"Nope\":1}".to_vec()).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = post(vec![0; MAX_BODY_SIZE as usize + 1]).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = warp::test::request().path("/inbox").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        drop(receiver);
+        let response = post(encode(&message, Encoding::Proto)).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_queries() {
+        let keypair = ECDSAKeypair::new();
+        let validator =
+            ValidatorInfo::new(keypair.get_public_key(), "127.0.0.1:3030".parse().unwrap());
+        let genesis = TendermintConfig::new("test-chain".to_string(), vec![validator]);
+        let state = QueryState::new(keypair.get_public_key(), &genesis, Arc::new(PeerTable::new()));
+        state.record(&Event::Decision {
+            height: 1,
+            round: 2,
+            value: "abc".to_string(),
+            from: 0,
+            #[cfg(feature = "bls")]
+            certificate: None,
+        });
+        let filter = queries(Arc::new(state.with_metrics(Arc::new(Metrics::new()))));
+        let get = |path: &str| warp::test::request().path(path).reply(&filter);
+
+        let response = get("/status").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(status["chain_id"], "test-chain");
+        assert_eq!(status["consensus"], serde_json::Value::Null);
+        assert_eq!(status["latest_decision"]["value"], "abc");
+
+        assert_eq!(get("/decisions/1").await.status(), StatusCode::OK);
+        assert_eq!(get("/decisions/2").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get("/health").await.status(), StatusCode::OK);
+        let validators: serde_json::Value =
+            serde_json::from_slice(get("/validators").await.body()).unwrap();
+        assert_eq!(validators[0]["port"], 3030);
+        assert_eq!(get("/peers").await.body().as_ref(), b"[]");
+        assert_eq!(get("/events").await.status(), StatusCode::NOT_FOUND);
+        let metrics = get("/metrics").await;
+        assert_eq!(metrics.status(), StatusCode::OK);
+        assert!(String::from_utf8_lossy(metrics.body()).contains("tendermint_p2p_peers 0"));
+    }
+
+    #[tokio::test]
+    async fn test_decisions_are_served_at_their_height() {
+        let keypair: Keypair = ECDSAKeypair::new().into();
+        let validator =
+            ValidatorInfo::new(keypair.get_public_key(), "127.0.0.1:3030".parse().unwrap());
+        let genesis = TendermintConfig::new("test-chain".to_string(), vec![validator]);
+        let (_sender, receiver) = mpsc::channel(1);
+        let mut process = Process::new(
+            0,
+            keypair.clone(),
+            genesis.chain_id.clone(),
+            genesis.validator_keys().unwrap(),
+            Arc::new(Mutex::new(receiver)),
+            vec![],
+            vec![0],
+            || "v5".to_string(),
         );
+        let mut events = process.subscribe();
+        let next = process.run_epoch(Some(EpochState::new(5))).await;
+        assert_eq!(next.height(), 6);
 
-        // Start the server
-        warp::serve(inbox_route).run((self.addr, self.port)).await;
+        let state = QueryState::new(keypair.get_public_key(), &genesis, Arc::new(PeerTable::new()));
+        while let Some(event) = events.next().await {
+            if matches!(event, Event::Decision { .. }) {
+                state.record(&event);
+                break;
+            }
+        }
+        let filter = queries(Arc::new(state));
+        let response = warp::test::request().path("/decisions/5").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let decision: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(decision["height"], 5);
+        assert_eq!(decision["value"], "v5");
+        let response = warp::test::request().path("/decisions/6").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 }
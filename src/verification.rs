@@ -0,0 +1,207 @@
+use prometheus::IntCounter;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex as StdMutex},
+    thread,
+};
+
+use crate::{gossip::MessageHash, messages::SignedMessage};
+
+#[derive(Debug, Clone)]
+pub struct VerifierConfig {
+    /// The number of threads a batch of messages is verified on.
+    pub workers: usize,
+    /// The maximum number of queued messages verified together.
+    pub batch_size: usize,
+    /// How many verification results are remembered.
+    pub cache_capacity: usize,
+}
+
+impl Default for VerifierConfig {
+    fn default() -> Self {
+        VerifierConfig {
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            batch_size: 64,
+            cache_capacity: 10_000,
+        }
+    }
+}
+
+/// The results of verifying messages, by message hash. Once full, the oldest results are
+/// forgotten first.
+#[derive(Debug)]
+struct ResultCache {
+    results: HashMap<MessageHash, bool>,
+    order: VecDeque<MessageHash>,
+    capacity: usize,
+}
+
+impl ResultCache {
+    fn new(capacity: usize) -> Self {
+        ResultCache { results: HashMap::new(), order: VecDeque::new(), capacity }
+    }
+
+    fn get(&self, hash: &MessageHash) -> Option<bool> {
+        self.results.get(hash).copied()
+    }
+
+    fn insert(&mut self, hash: MessageHash, valid: bool) {
+        if self.results.insert(hash, valid).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Verifies the signatures of incoming messages before they reach consensus.
+///
+/// Messages are verified in batches, spread over a pool of worker threads, and the result for each
+/// message is cached by its hash. A message gossiped to us by several peers is only verified once,
+/// and when the verifier is shared between the gossip layer and the process, messages gossip has
+/// already checked reach the process without being verified again.
+pub struct SignatureVerifier {
+    config: VerifierConfig,
+    /// The chain messages must be signed for.
+    chain_id: String,
+    cache: StdMutex<ResultCache>,
+    /// Counts the messages found invalid, once each: cached results aren't counted again.
+    invalid: IntCounter,
+}
+
+impl SignatureVerifier {
+    pub fn new(config: VerifierConfig, chain_id: String) -> Arc<Self> {
+        Arc::new(SignatureVerifier {
+            cache: StdMutex::new(ResultCache::new(config.cache_capacity)),
+            config,
+            chain_id,
+            invalid: IntCounter::new(
+                "tendermint_invalid_signatures_total",
+                "Messages dropped for an invalid signature",
+            )
+            .unwrap(),
+        })
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.config.batch_size
+    }
+
+    /// The count of messages found invalid, to register with `Metrics`.
+    pub fn invalid_signatures(&self) -> IntCounter {
+        self.invalid.clone()
+    }
+
+    /// Verifies a single message.
+    pub fn verify(&self, message: &SignedMessage) -> bool {
+        self.verify_hashed(message.hash(), message)
+    }
+
+    /// Verifies a message whose hash the caller has already computed.
+    pub(crate) fn verify_hashed(&self, hash: MessageHash, message: &SignedMessage) -> bool {
+        if let Some(valid) = self.cache.lock().unwrap().get(&hash) {
+            return valid;
+        }
+        let valid = message.verify(&self.chain_id);
+        self.cache.lock().unwrap().insert(hash, valid);
+        if !valid {
+            self.invalid.inc();
+        }
+        valid
+    }
+
+    /// Verifies a batch of messages, returning whether each is valid. Messages without a cached
+    /// result are split between the worker threads.
+    pub fn verify_batch(&self, messages: &[SignedMessage]) -> Vec<bool> {
+        let hashes: Vec<MessageHash> = messages.iter().map(SignedMessage::hash).collect();
+        let mut results: Vec<Option<bool>> = {
+            let cache = self.cache.lock().unwrap();
+            hashes.iter().map(|hash| cache.get(hash)).collect()
+        };
+        let pending: Vec<usize> = (0..messages.len()).filter(|&i| results[i].is_none()).collect();
+
+        let chunk_size = pending.len().div_ceil(self.config.workers.max(1)).max(1);
+        let verified: Vec<(usize, bool)> = if pending.len() <= 1 {
+            pending.iter().map(|&i| (i, messages[i].verify(&self.chain_id))).collect()
+        } else {
+            thread::scope(|scope| {
+                let workers: Vec<_> = pending
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|&i| (i, messages[i].verify(&self.chain_id)))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                workers.into_iter().flat_map(|w| w.join().expect("verifier panicked")).collect()
+            })
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        for (i, valid) in verified {
+            cache.insert(hashes[i], valid);
+            results[i] = Some(valid);
+            if !valid {
+                self.invalid.inc();
+            }
+        }
+        results.into_iter().map(|valid| valid.expect("every message is verified")).collect()
+    }
+
+    /// Verifies a batch of messages off the async runtime, and returns the valid ones in their
+    /// original order.
+    pub async fn filter_valid(
+        self: &Arc<Self>,
+        messages: Vec<SignedMessage>,
+    ) -> Vec<SignedMessage> {
+        let verifier = self.clone();
+        let (messages, results) = tokio::task::spawn_blocking(move || {
+            let results = verifier.verify_batch(&messages);
+            (messages, results)
+        })
+        .await
+        .expect("verifier panicked");
+        messages.into_iter().zip(results).filter_map(|(m, valid)| valid.then_some(m)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::ECDSAKeypair, messages::Message};
+
+    #[test]
+    fn test_verify_batch_and_cache() {
+        let verifier = SignatureVerifier::new(
+            VerifierConfig { workers: 3, ..VerifierConfig::default() },
+            "test-chain".to_string(),
+        );
+        let keypair = ECDSAKeypair::new();
+        let mut messages: Vec<SignedMessage> = (0..10)
+            .map(|round| {
+                let prevote = Message::Prevote { height: 1, round, value: None };
+                SignedMessage::new(prevote, "test-chain", &keypair)
+            })
+            .collect();
+        // A forged message, and a message for another chain.
+        messages[3].sender = ECDSAKeypair::new().get_public_key();
+        messages[7] = SignedMessage::new(messages[7].body.clone(), "other-chain", &keypair);
+
+        let expected: Vec<bool> = (0..10).map(|i| i != 3 && i != 7).collect();
+        assert_eq!(verifier.verify_batch(&messages), expected);
+        assert_eq!(verifier.cache.lock().unwrap().results.len(), 10);
+        // Results are served from the cache after the first time.
+        assert_eq!(verifier.verify_batch(&messages), expected);
+        assert!(!verifier.verify(&messages[3]));
+        assert_eq!(verifier.cache.lock().unwrap().results.len(), 10);
+        verifier.cache.lock().unwrap().results.insert(messages[0].hash(), false);
+        assert!(!verifier.verify(&messages[0]));
+    }
+}
@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    crypto::PublicKey,
+    messages::{Message, SignedMessage},
+    params::quorum_power,
+};
+
+/// A fixed-size array of bits, used to summarise which validators' votes a node holds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawBitArray")]
+pub struct BitArray {
+    size: usize,
+    words: Vec<u64>,
+}
+
+/// A bit array as decoded, before its words are checked against its size.
+#[derive(Deserialize)]
+struct RawBitArray {
+    size: usize,
+    words: Vec<u64>,
+}
+
+impl TryFrom<RawBitArray> for BitArray {
+    type Error = &'static str;
+
+    fn try_from(raw: RawBitArray) -> Result<Self, Self::Error> {
+        BitArray::from_words(raw.size, raw.words).ok_or("bit array words don't match its size")
+    }
+}
+
+impl BitArray {
+    pub fn new(size: usize) -> Self {
+        BitArray { size, words: vec![0; size.div_ceil(64)] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        index < self.size &&
+            self.words.get(index / 64).is_some_and(|w| w & (1 << (index % 64)) != 0)
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        if index >= self.size {
+            return;
+        }
+        if value {
+            self.words[index / 64] |= 1 << (index % 64);
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    /// The bits packed into 64-bit words, least significant bit first.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Rebuilds a bit array from its words. Returns None if the number of words doesn't match
+    /// the size.
+    pub fn from_words(size: usize, words: Vec<u64>) -> Option<Self> {
+        (words.len() == size.div_ceil(64)).then_some(BitArray { size, words })
+    }
+
+    pub fn count_ones(&self) -> usize {
+        (0..self.size).filter(|&i| self.get(i)).count()
+    }
+
+    /// Returns the indices which are set in `self` but not in `other`.
+    pub fn missing_from(&self, other: &BitArray) -> Vec<usize> {
+        (0..self.size).filter(|&i| self.get(i) && !other.get(i)).collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VoteError {
+    #[error("message is not a vote")]
+    NotAVote,
+    #[error("vote is for height {0}, expected {1}")]
+    WrongHeight(u64, u64),
+    #[error("vote from {0}, which is not a validator")]
+    NotValidator(PublicKey),
+    #[error("validator {0} already cast a different vote in this round")]
+    Conflicting(usize),
+}
+
+/// The votes of one kind (prevote or precommit) cast in a single round, indexed by validator.
+#[derive(Debug, Clone)]
+pub struct VoteSet {
+    votes: Vec<Option<SignedMessage>>,
+    /// The voting power of each validator.
+    powers: Vec<u64>,
+}
+
+impl VoteSet {
+    fn new(powers: Vec<u64>) -> Self {
+        VoteSet { votes: vec![None; powers.len()], powers }
+    }
+
+    /// The vote cast by the validator at `index`, if we have it.
+    pub fn get(&self, index: usize) -> Option<&SignedMessage> {
+        self.votes.get(index).and_then(|v| v.as_ref())
+    }
+
+    /// The votes held, with the index of the validator who cast each.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &SignedMessage)> {
+        self.votes.iter().enumerate().filter_map(|(i, vote)| Some((i, vote.as_ref()?)))
+    }
+
+    /// The number of validators whose vote we hold.
+    pub fn len(&self) -> usize {
+        self.votes.iter().filter(|v| v.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The values voted for, one entry per vote held.
+    pub fn values(&self) -> Vec<Option<String>> {
+        self.votes.iter().flatten().filter_map(|v| vote_value(&v.body).cloned()).collect()
+    }
+
+    /// The voting power of the whole validator set.
+    pub fn total_power(&self) -> u64 {
+        self.powers.iter().sum()
+    }
+
+    /// The voting power of the validator at `index`.
+    pub fn validator_power(&self, index: usize) -> u64 {
+        self.powers.get(index).copied().unwrap_or(0)
+    }
+
+    /// The voting power of the votes held.
+    pub fn power(&self) -> u64 {
+        self.iter().map(|(i, _)| self.powers[i]).sum()
+    }
+
+    /// The voting power of the votes held for `value`, or for nil if it is None.
+    pub fn power_for(&self, value: &Option<String>) -> u64 {
+        self.iter()
+            .filter(|(_, vote)| vote_value(&vote.body) == Some(value))
+            .map(|(i, _)| self.powers[i])
+            .sum()
+    }
+
+    /// Whether the votes held make a quorum, whatever they are for.
+    pub fn has_quorum(&self) -> bool {
+        self.power() >= quorum_power(self.total_power())
+    }
+
+    /// Whether the votes held for `value` make a quorum.
+    pub fn has_quorum_for(&self, value: &Option<String>) -> bool {
+        self.power_for(value) >= quorum_power(self.total_power())
+    }
+
+    /// The value a quorum voted for: Some(None) for nil, and None if no value has a quorum.
+    pub fn quorum_value(&self) -> Option<Option<String>> {
+        self.iter()
+            .filter_map(|(_, vote)| vote_value(&vote.body))
+            .find(|value| self.has_quorum_for(value))
+            .cloned()
+    }
+
+    /// Summarises which validators' votes we hold.
+    pub fn bit_array(&self) -> BitArray {
+        let mut bits = BitArray::new(self.votes.len());
+        for (i, vote) in self.votes.iter().enumerate() {
+            bits.set(i, vote.is_some());
+        }
+        bits
+    }
+}
+
+/// Returns the value voted for, or None if the message is not a vote.
+fn vote_value(message: &Message) -> Option<&Option<String>> {
+    match message {
+        Message::Prevote { value, .. } | Message::Precommit { value, .. } => Some(value),
+        Message::Propose { .. } => None,
+    }
+}
+
+/// All prevotes and precommits received at a height, for every round.
+#[derive(Debug, Clone)]
+pub struct HeightVoteSet {
+    height: u64,
+    validators: Vec<PublicKey>,
+    powers: Vec<u64>,
+    prevotes: HashMap<u64, VoteSet>,
+    precommits: HashMap<u64, VoteSet>,
+}
+
+impl HeightVoteSet {
+    /// Collects the votes of validators with a voting power of 1 each.
+    pub fn new(height: u64, validators: Vec<PublicKey>) -> Self {
+        let powers = vec![1; validators.len()];
+        Self::with_powers(height, validators, powers)
+    }
+
+    /// Collects the votes of validators with the given voting powers, in the same order. Panics
+    /// if there isn't one power for each validator.
+    pub fn with_powers(height: u64, validators: Vec<PublicKey>, powers: Vec<u64>) -> Self {
+        assert_eq!(validators.len(), powers.len(), "one voting power per validator");
+        HeightVoteSet {
+            height,
+            validators,
+            powers,
+            prevotes: HashMap::new(),
+            precommits: HashMap::new(),
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Discards all votes and starts collecting votes for a new height.
+    pub fn reset(&mut self, height: u64) {
+        self.height = height;
+        self.prevotes.clear();
+        self.precommits.clear();
+    }
+
+    pub fn validator_index(&self, pubkey: &PublicKey) -> Option<usize> {
+        self.validators.iter().position(|v| v == pubkey)
+    }
+
+    /// The voting power of the whole validator set.
+    pub fn total_power(&self) -> u64 {
+        self.powers.iter().sum()
+    }
+
+    /// The voting power of the validator at `index`.
+    pub fn power(&self, index: usize) -> u64 {
+        self.powers.get(index).copied().unwrap_or(0)
+    }
+
+    /// Adds a vote. Returns true if the vote is new, and false if we already hold it.
+    pub fn add_vote(&mut self, vote: SignedMessage) -> Result<bool, VoteError> {
+        let (height, round, votes) = match vote.body {
+            Message::Prevote { height, round, .. } => (height, round, &mut self.prevotes),
+            Message::Precommit { height, round, .. } => (height, round, &mut self.precommits),
+            Message::Propose { .. } => return Err(VoteError::NotAVote),
+        };
+        if height != self.height {
+            return Err(VoteError::WrongHeight(height, self.height));
+        }
+        let index = self
+            .validators
+            .iter()
+            .position(|v| *v == vote.sender)
+            .ok_or(VoteError::NotValidator(vote.sender))?;
+
+        let set = votes.entry(round).or_insert_with(|| VoteSet::new(self.powers.clone()));
+        match &set.votes[index] {
+            Some(existing) if vote_value(&existing.body) == vote_value(&vote.body) => Ok(false),
+            Some(_) => Err(VoteError::Conflicting(index)),
+            None => {
+                set.votes[index] = Some(vote);
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn prevotes(&self, round: u64) -> Option<&VoteSet> {
+        self.prevotes.get(&round)
+    }
+
+    pub fn precommits(&self, round: u64) -> Option<&VoteSet> {
+        self.precommits.get(&round)
+    }
+
+    /// Summarises the votes held for a round. Rounds we have no votes for yield an empty array.
+    pub fn bit_arrays(&self, round: u64) -> (BitArray, BitArray) {
+        let empty = BitArray::new(self.validators.len());
+        (
+            self.prevotes(round).map(VoteSet::bit_array).unwrap_or_else(|| empty.clone()),
+            self.precommits(round).map(VoteSet::bit_array).unwrap_or(empty),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ECDSAKeypair;
+
+    #[test]
+    fn test_bit_array_missing_from() {
+        let mut ours = BitArray::new(70);
+        let mut theirs = BitArray::new(70);
+        for i in [0, 3, 64, 69] {
+            ours.set(i, true);
+        }
+        theirs.set(3, true);
+        assert_eq!(ours.count_ones(), 4);
+        assert_eq!(ours.missing_from(&theirs), vec![0, 64, 69]);
+        assert!(theirs.missing_from(&ours).is_empty());
+
+        // Decoding checks the words against the size, as `from_words` does.
+        let json = serde_json::to_string(&ours).unwrap();
+        assert_eq!(serde_json::from_str::<BitArray>(&json).unwrap(), ours);
+        assert!(serde_json::from_str::<BitArray>(r#"{"size":70,"words":[1]}"#).is_err());
+        assert!(serde_json::from_str::<BitArray>(r#"{"size":1,"words":[1,2]}"#).is_err());
+    }
+
+    #[test]
+    fn test_add_vote() {
+        let keypairs: Vec<_> = (0..3).map(|_| ECDSAKeypair::new()).collect();
+        let mut votes =
+            HeightVoteSet::new(1, keypairs.iter().map(|k| k.get_public_key()).collect());
+        let prevote = |value: &str, keypair| {
+            SignedMessage::new(
+                Message::Prevote { height: 1, round: 0, value: Some(value.to_string()) },
+                "test-chain",
+                keypair,
+            )
+        };
+
+        assert!(votes.add_vote(prevote("a", &keypairs[1])).unwrap());
+        assert!(!votes.add_vote(prevote("a", &keypairs[1])).unwrap());
+        assert!(matches!(
+            votes.add_vote(prevote("b", &keypairs[1])),
+            Err(VoteError::Conflicting(1))
+        ));
+        assert!(matches!(
+            votes.add_vote(prevote("a", &ECDSAKeypair::new())),
+            Err(VoteError::NotValidator(_))
+        ));
+
+        let (prevotes, precommits) = votes.bit_arrays(0);
+        assert_eq!((prevotes.count_ones(), precommits.count_ones()), (1, 0));
+        assert!(prevotes.get(1));
+        assert_eq!(votes.prevotes(0).unwrap().values(), vec![Some("a".to_string())]);
+    }
+
+    #[test]
+    fn test_quorum_by_power() {
+        let keypairs: Vec<_> = (0..3).map(|_| ECDSAKeypair::new()).collect();
+        let validators = keypairs.iter().map(|k| k.get_public_key()).collect();
+        // A quorum needs more than two thirds of the power of 10, i.e. 7.
+        let mut votes = HeightVoteSet::with_powers(1, validators, vec![6, 3, 1]);
+        let value = Some("a".to_string());
+        let prevote = |keypair, value: &Option<String>| {
+            let body = Message::Prevote { height: 1, round: 0, value: value.clone() };
+            SignedMessage::new(body, "test-chain", keypair)
+        };
+
+        votes.add_vote(prevote(&keypairs[0], &value)).unwrap();
+        votes.add_vote(prevote(&keypairs[2], &None)).unwrap();
+        let prevotes = votes.prevotes(0).unwrap();
+        assert_eq!((prevotes.power(), prevotes.power_for(&value)), (7, 6));
+        assert!(prevotes.has_quorum() && prevotes.quorum_value().is_none());
+
+        votes.add_vote(prevote(&keypairs[1], &value)).unwrap();
+        assert_eq!(votes.prevotes(0).unwrap().quorum_value(), Some(value));
+    }
+}